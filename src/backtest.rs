@@ -0,0 +1,214 @@
+//! Offline replay of recorded trades through the live `Strategy`.
+//!
+//! Trades are read from a CSV with the `TradeMsg` columns
+//! (`price,size,side,ts,spread[,market]`). Every signal is filled at the
+//! trade's price, subject to the same `max_position` rule as live buys, and
+//! open positions are closed by the same stop-loss/take-profit exits.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::data;
+use crate::features::{FeatureSpec, RollingFeatures};
+use crate::model;
+use crate::strategy::{Action, OrderSide, PositionState, Strategy};
+
+#[derive(Debug, Default, Serialize)]
+pub struct BacktestReport {
+    /// Final PnL with the open position marked at the last trade price.
+    pub pnl: f64,
+    /// Number of simulated fills.
+    pub trades: usize,
+    /// Share of position-reducing fills that realized a profit.
+    pub win_rate: f64,
+    /// Largest peak-to-trough decline of the marked PnL.
+    pub max_drawdown: f64,
+    /// Compounded return of the closed trades.
+    pub total_return: f64,
+    /// Mean over standard deviation of the closed trades' returns, scaled by
+    /// the square root of `annualization`.
+    pub sharpe: f64,
+    /// Average time from opening a position to a fill reducing it, in ms.
+    pub avg_holding_ms: f64,
+    #[serde(skip)]
+    pub closed: Vec<ClosedTrade>,
+}
+
+/// Part of a position closed by one fill.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClosedTrade {
+    /// "long" or "short": the side of the position that was reduced.
+    pub side: &'static str,
+    pub size: f64,
+    pub entry_price: f64,
+    pub exit_price: f64,
+    pub pnl: f64,
+    /// `pnl` over the closed size's entry notional.
+    pub return_pct: f64,
+    pub entry_ts: i64,
+    pub exit_ts: i64,
+}
+
+#[derive(Serialize)]
+pub struct BacktestParams<'a> {
+    pub data_path: &'a str,
+    pub model_path: &'a str,
+    pub threshold: f64,
+    /// Probability a long exits below; the mirrored `1 - threshold` when unset.
+    pub exit_threshold: Option<f64>,
+    pub trade_amount: f64,
+    /// Size of a fully confident signal; `trade_amount` when unset.
+    pub max_trade_amount: Option<f64>,
+    pub max_position: f64,
+    pub stop_loss_bps: Option<u64>,
+    pub take_profit_bps: Option<u64>,
+    /// Rolling features, matching those the model was trained with.
+    pub features: FeatureSpec,
+    /// Periods per year the Sharpe ratio is scaled to, counted in trades
+    /// (e.g. the expected trades per year). 1 leaves it per trade.
+    pub annualization: f64,
+}
+
+/// Simulated account filling at the trade price, with the cost basis of the
+/// open position tracked as a volume-weighted average.
+#[derive(Debug, Default)]
+struct SimAccount {
+    cash: f64,
+    position: PositionState,
+    closes: usize,
+    wins: usize,
+    /// Time the open position was opened (or flipped).
+    opened_ts: i64,
+    closed: Vec<ClosedTrade>,
+}
+
+impl SimAccount {
+    fn fill(&mut self, side: OrderSide, qty: f64, price: f64, ts: i64) {
+        let before = self.position;
+        if let Some(realized) = self.position.apply(side, qty, price) {
+            self.closes += 1;
+            if realized > 0.0 {
+                self.wins += 1;
+            }
+            let size = qty.min(before.size.abs());
+            let notional = size * before.entry_price;
+            self.closed.push(ClosedTrade {
+                side: if before.size > 0.0 { "long" } else { "short" },
+                size,
+                entry_price: before.entry_price,
+                exit_price: price,
+                pnl: realized,
+                return_pct: if notional > 0.0 { realized / notional } else { 0.0 },
+                entry_ts: self.opened_ts,
+                exit_ts: ts,
+            });
+        }
+        let flipped = self.position.size != 0.0 && self.position.size.signum() != before.size.signum();
+        if before.size == 0.0 || flipped {
+            self.opened_ts = ts;
+        }
+        let signed = if side == OrderSide::Buy { qty } else { -qty };
+        self.cash -= signed * price;
+    }
+
+    fn equity(&self, price: f64) -> f64 {
+        self.cash + self.position.size * price
+    }
+}
+
+pub fn run(params: &BacktestParams) -> Result<BacktestReport> {
+    let model = model::load(params.model_path)?;
+    model::check_features(&model, &params.features.names())?;
+    let mut strategy = Strategy::new(model, params.threshold)
+        .with_exits(params.stop_loss_bps, params.take_profit_bps);
+    if let Some(exit) = params.exit_threshold {
+        strategy = strategy.with_exit_threshold(exit);
+    }
+    let rows = data::read_trade_csv(params.data_path)?;
+
+    let mut account = SimAccount::default();
+    let mut report = BacktestReport::default();
+    let mut peak = 0.0f64;
+    let mut last_price = 0.0;
+    let mut rolling: HashMap<String, RollingFeatures> = HashMap::new();
+    for data::CsvRow { trade, .. } in rows {
+        let features = rolling
+            .entry(trade.market.clone())
+            .or_insert_with(|| RollingFeatures::new(&params.features))
+            .push(&trade);
+        match strategy.evaluate(&features, trade.price, &account.position) {
+            Action::Hold => {}
+            Action::Enter { side, confidence } => {
+                let max_amount = params.max_trade_amount.unwrap_or(params.trade_amount);
+                let mut amount = strategy.order_size(confidence, params.trade_amount, max_amount);
+                if side == OrderSide::Buy {
+                    amount = amount.min(params.max_position - account.position.size);
+                }
+                if amount <= 0.0 {
+                    log::debug!("Skipping buy at {}: max_position reached", trade.price);
+                } else {
+                    account.fill(side, amount, trade.price, trade.ts);
+                    report.trades += 1;
+                }
+            }
+            Action::Exit { side, reason } => {
+                log::debug!("{:?} exit at {}", reason, trade.price);
+                account.fill(side, account.position.size.abs(), trade.price, trade.ts);
+                report.trades += 1;
+            }
+        }
+        let equity = account.equity(trade.price);
+        peak = peak.max(equity);
+        report.max_drawdown = report.max_drawdown.max(peak - equity);
+        last_price = trade.price;
+    }
+
+    report.pnl = account.equity(last_price);
+    if account.closes > 0 {
+        report.win_rate = account.wins as f64 / account.closes as f64;
+    }
+    let returns: Vec<f64> = account.closed.iter().map(|t| t.return_pct).collect();
+    report.total_return = returns.iter().fold(1.0, |acc, r| acc * (1.0 + r)) - 1.0;
+    report.sharpe = sharpe(&returns, params.annualization);
+    if !account.closed.is_empty() {
+        let held: i64 = account.closed.iter().map(|t| t.exit_ts - t.entry_ts).sum();
+        report.avg_holding_ms = held as f64 / account.closed.len() as f64;
+    }
+    report.closed = account.closed;
+    Ok(report)
+}
+
+/// Sharpe ratio of per-trade `returns` (zero risk-free rate), scaled by
+/// `sqrt(annualization)`. Zero with fewer than two trades or no variance.
+fn sharpe(returns: &[f64], annualization: f64) -> f64 {
+    let n = returns.len() as f64;
+    if n < 2.0 {
+        return 0.0;
+    }
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    if variance <= 0.0 {
+        return 0.0;
+    }
+    mean / variance.sqrt() * annualization.sqrt()
+}
+
+/// Write `report` as JSON: the parameters it was run with, the summary
+/// statistics and every closed trade.
+pub fn write_json(report: &BacktestReport, params: &BacktestParams, path: &str) -> Result<()> {
+    #[derive(Serialize)]
+    struct Export<'a> {
+        config: &'a BacktestParams<'a>,
+        summary: &'a BacktestReport,
+        trades: &'a [ClosedTrade],
+    }
+    let export = Export {
+        config: params,
+        summary: report,
+        trades: &report.closed,
+    };
+    fs::write(path, serde_json::to_vec_pretty(&export)?)?;
+    Ok(())
+}
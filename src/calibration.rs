@@ -0,0 +1,168 @@
+//! Mapping of raw model probabilities to calibrated ones, fitted on samples
+//! held out from training so that a calibrated 0.7 is followed by an up move
+//! about 70% of the time.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Probabilities are clamped this far from 0 and 1 before taking logits.
+const EPSILON: f64 = 1e-6;
+/// Newton iterations of the Platt fit; it converges in a handful.
+const PLATT_ITERATIONS: usize = 100;
+
+/// Calibration method applied after training.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Calibration {
+    /// Use the model probabilities as they are.
+    #[default]
+    None,
+    /// Logistic fit on the logit of the probability; smooth and monotone,
+    /// needs few samples.
+    Platt,
+    /// Non-decreasing step function; fits any monotone distortion but needs
+    /// more samples.
+    Isotonic,
+}
+
+impl FromStr for Calibration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "platt" => Ok(Self::Platt),
+            "isotonic" => Ok(Self::Isotonic),
+            other => Err(anyhow!(
+                "unknown calibration '{}', expected 'none', 'platt' or 'isotonic'",
+                other
+            )),
+        }
+    }
+}
+
+/// Fitted calibration, stored with the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Calibrator {
+    Identity,
+    /// `sigmoid(a * logit(p) + b)`.
+    Platt { a: f64, b: f64 },
+    /// `values[i]` applies to probabilities up to `thresholds[i]`; both are
+    /// non-decreasing.
+    Isotonic { thresholds: Vec<f64>, values: Vec<f64> },
+}
+
+impl Calibrator {
+    /// Fit `method` on raw probabilities and their 0/1 labels.
+    pub fn fit(method: Calibration, probs: &[f64], labels: &[i32]) -> Self {
+        match method {
+            Calibration::None => Self::Identity,
+            Calibration::Platt => fit_platt(probs, labels),
+            Calibration::Isotonic => fit_isotonic(probs, labels),
+        }
+    }
+
+    pub fn apply(&self, prob: f64) -> f64 {
+        match self {
+            Self::Identity => prob,
+            Self::Platt { a, b } => sigmoid(a * logit(prob) + b),
+            Self::Isotonic { thresholds, values } => {
+                if values.is_empty() {
+                    return prob;
+                }
+                let idx = thresholds.partition_point(|&t| t < prob);
+                values[idx.min(values.len() - 1)]
+            }
+        }
+    }
+}
+
+fn sigmoid(z: f64) -> f64 {
+    1.0 / (1.0 + (-z).exp())
+}
+
+fn logit(p: f64) -> f64 {
+    let p = p.clamp(EPSILON, 1.0 - EPSILON);
+    (p / (1.0 - p)).ln()
+}
+
+/// Platt's method: Newton's method on the log loss, against targets pulled
+/// slightly off 0 and 1 so a separable holdout does not diverge.
+fn fit_platt(probs: &[f64], labels: &[i32]) -> Calibrator {
+    let positives = labels.iter().filter(|&&l| l == 1).count() as f64;
+    let negatives = labels.len() as f64 - positives;
+    let hi = (positives + 1.0) / (positives + 2.0);
+    let lo = 1.0 / (negatives + 2.0);
+    let scores: Vec<f64> = probs.iter().map(|&p| logit(p)).collect();
+    let (mut a, mut b) = (1.0, 0.0);
+    for _ in 0..PLATT_ITERATIONS {
+        let (mut ga, mut gb, mut haa, mut hab, mut hbb) = (0.0, 0.0, 1e-12, 0.0, 1e-12);
+        for (&s, &label) in scores.iter().zip(labels) {
+            let q = sigmoid(a * s + b);
+            let err = q - if label == 1 { hi } else { lo };
+            let w = q * (1.0 - q);
+            ga += err * s;
+            gb += err;
+            haa += w * s * s;
+            hab += w * s;
+            hbb += w;
+        }
+        let det = haa * hbb - hab * hab;
+        if det.abs() < 1e-12 {
+            break;
+        }
+        let da = (hbb * ga - hab * gb) / det;
+        let db = (haa * gb - hab * ga) / det;
+        a -= da;
+        b -= db;
+        if da.abs() < 1e-9 && db.abs() < 1e-9 {
+            break;
+        }
+    }
+    Calibrator::Platt { a, b }
+}
+
+/// Pool-adjacent-violators: merge neighbouring blocks until the up rate is
+/// non-decreasing in the raw probability.
+fn fit_isotonic(probs: &[f64], labels: &[i32]) -> Calibrator {
+    let mut pairs: Vec<(f64, f64)> = probs
+        .iter()
+        .zip(labels)
+        .map(|(&p, &l)| (p, (l == 1) as u8 as f64))
+        .collect();
+    pairs.sort_by(|x, y| x.0.total_cmp(&y.0));
+    // (sum of labels, count, highest probability) per block
+    let mut blocks: Vec<(f64, f64, f64)> = Vec::new();
+    for (p, y) in pairs {
+        blocks.push((y, 1.0, p));
+        while blocks.len() >= 2 {
+            let (s2, n2, p2) = blocks[blocks.len() - 1];
+            let (s1, n1, _) = blocks[blocks.len() - 2];
+            if s1 / n1 <= s2 / n2 {
+                break;
+            }
+            blocks.pop();
+            let last = blocks.len() - 1;
+            blocks[last] = (s1 + s2, n1 + n2, p2);
+        }
+    }
+    Calibrator::Isotonic {
+        thresholds: blocks.iter().map(|b| b.2).collect(),
+        values: blocks.iter().map(|b| b.0 / b.1).collect(),
+    }
+}
+
+/// Mean squared difference between predicted probabilities and 0/1
+/// outcomes: 0 is perfect, 0.25 is a constant 0.5.
+pub fn brier_score(probs: &[f64], labels: &[i32]) -> f64 {
+    if probs.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = probs
+        .iter()
+        .zip(labels)
+        .map(|(&p, &l)| (p - (l == 1) as u8 as f64).powi(2))
+        .sum();
+    sum / probs.len() as f64
+}
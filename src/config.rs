@@ -1,33 +1,1060 @@
 use anyhow::{anyhow, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::{Pubkey, MAX_SEED_LEN};
+use solana_sdk::signature::{Keypair, Signer};
+use std::collections::HashSet;
 use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::calibration::Calibration;
+use crate::escalation::EscalationConfig;
+use crate::features::FeatureSpec;
+use crate::model::{ModelType, TrainOptions};
+use crate::rate_limit::ThrottleMode;
+use crate::schedule::TradingWindow;
+use crate::swap_client::JupiterMode;
+use crate::strategy::{CombinePolicy, ColdStartStrategy, DEFAULT_SIGNAL_THRESHOLD};
+
+/// Upper bound accepted for `slippage_bps` (10%).
+const MAX_SLIPPAGE_BPS: u64 = 1_000;
+/// Stands in for secrets in `BotConfig::redacted`.
+const REDACTED: &str = "<redacted>";
+
+/// Accept either a single string or a list of them.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) if s.is_empty() => Vec::new(),
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Load a keypair from either the path of a Solana CLI keypair file (a JSON
+/// array of 64 bytes, as written by `solana-keygen`) or an inline base58
+/// secret key. Existing files take precedence.
+fn load_keypair(source: &str) -> Result<Keypair> {
+    let bytes = if Path::new(source).is_file() {
+        let content = fs::read_to_string(source)?;
+        serde_json::from_str::<Vec<u8>>(&content)
+            .map_err(|e| anyhow!("keypair file is not a JSON byte array: {}", e))?
+    } else {
+        bs58::decode(source).into_vec()?
+    };
+    if bytes.len() != 64 {
+        return Err(anyhow!("expected 64 bytes, got {}", bytes.len()));
+    }
+    Ok(Keypair::from_bytes(&bytes)?)
+}
+
+/// `url` with its query string, if any, replaced by `REDACTED`.
+fn redact_query(url: &str) -> String {
+    match url.split_once('?') {
+        Some((base, _)) => format!("{}?{}", base, REDACTED),
+        None => url.to_string(),
+    }
+}
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct BotConfig {
+    /// Overridden by `HELIUS_API_KEY`; may be left empty to require it.
+    #[serde(default)]
     pub helius_api_key: String,
-    /// Optional Triton/Yellowstone X-Token for authenticated gRPC access
+    /// Optional Triton/Yellowstone X-Token for authenticated gRPC access.
+    /// Overridden by `YELLOWSTONE_TOKEN`.
     #[serde(default)]
     pub yellowstone_token: Option<String>,
     pub jupiter_api_url: String,
-    pub wallet_keypair: String,
+    /// "hosted" (Jupiter's API, `/v6/...` paths) or "self_hosted"
+    /// (`jupiter-swap-api`, unversioned paths). Defaults to "hosted"
+    #[serde(default)]
+    pub jupiter_mode: JupiterMode,
+    /// API key of the hosted paid tier, sent as `x-api-key`. Overridden by
+    /// `JUPITER_API_KEY`.
+    #[serde(default)]
+    pub jupiter_api_key: Option<String>,
+    /// Jupiter requests per second allowed client-side, quotes and swaps
+    /// alike, in bursts of up to one second's worth. Unlimited when unset.
+    #[serde(default)]
+    pub jupiter_rps: Option<f64>,
+    /// What a Jupiter call does over `jupiter_rps`: "wait" for its turn up to
+    /// `http_timeout_ms`, or "skip" the order. Defaults to "wait"
+    #[serde(default)]
+    pub jupiter_throttle: ThrottleMode,
+    /// Only route swaps through these AMMs (Jupiter's `dexes`, e.g.
+    /// "Orca V2"). Any AMM when unset; exclusive with `exclude_dexes`
+    #[serde(default)]
+    pub dexes: Option<Vec<String>>,
+    /// Never route swaps through these AMMs (Jupiter's `excludeDexes`)
+    #[serde(default)]
+    pub exclude_dexes: Option<Vec<String>>,
+    /// Mints never traded: markets on them are rejected at startup and
+    /// quotes routed through them are refused. Defaults to none
+    #[serde(default)]
+    pub blacklisted_mints: Vec<String>,
+    /// Only accept single-hop routes (Jupiter's `onlyDirectRoutes`).
+    /// Defaults to false
+    #[serde(default)]
+    pub only_direct_routes: Option<bool>,
+    /// Let Jupiter check the wallet's token accounts and create a missing
+    /// output account within the swap (rent is paid by the wallet). When
+    /// false every account must exist already, which is checked at startup
+    /// in live mode, and Jupiter skips its account lookups. Defaults to true
+    #[serde(default)]
+    pub auto_create_ata: Option<bool>,
+    /// Jupiter's `wrapAndUnwrapSol`: swap native SOL by wrapping it into a
+    /// temporary account that is closed after the swap. Each SOL swap then
+    /// carries the create/sync/close instructions (more compute units, and
+    /// rent that is refunded). Set to false to trade from and into your own
+    /// wrapped-SOL account instead. Defaults to true
+    #[serde(default)]
+    pub wrap_unwrap_sol: Option<bool>,
+    /// Path to a Solana CLI keypair file, or an inline base58 secret key, or
+    /// a list of them: live orders then rotate through the wallets. Overridden
+    /// by `WALLET_KEYPAIR` (comma-separated for several); may be left empty to
+    /// require it.
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub wallet_keypair: Vec<String>,
     pub symbols: Vec<String>,
     pub model_path: String,
     pub anchor_cluster: String,
     pub anchor_program_id: String,
-    /// Trade size in base units (e.g. 1 SOL). Defaults to 1.0
+    /// Send a `record_fill` instruction to `anchor_program_id` after every
+    /// confirmed live fill, updating the market's on-chain PnL account, whose
+    /// address is seeded with the market name (at most 32 bytes). Does
+    /// nothing while the program id is empty. Defaults to false
+    #[serde(default)]
+    pub record_fills_onchain: Option<bool>,
+    /// Trade size in base units (e.g. 1 SOL), used for the weakest signals.
+    /// Defaults to 1.0
     #[serde(default)]
     pub trade_amount: Option<f64>,
+    /// Trade size for a fully confident signal; sizes scale linearly from
+    /// `trade_amount` up to it. Defaults to `trade_amount` (fixed size)
+    #[serde(default)]
+    pub max_trade_amount: Option<f64>,
     /// Allowed slippage in basis points (1 bp = 0.01%). Defaults to 50 (0.5%)
     #[serde(default)]
     pub slippage_bps: Option<u64>,
-    /// Max seconds to wait for tx confirmation. Defaults to 30s
+    /// Largest adverse gap, in bps, between a quote's price and the trade
+    /// price that triggered the order; worse quotes are refused. Defaults to 100
+    #[serde(default)]
+    pub max_quote_deviation_bps: Option<u64>,
+    /// Largest price impact, in percent, a quote's route may have; orders
+    /// on thinner liquidity are skipped. Defaults to 1.0
+    #[serde(default)]
+    pub max_price_impact_pct: Option<f64>,
+    /// Base quantity that must rest on the book side an order trades
+    /// against (asks for buys, bids for sells), per the feed's depth. Feeds
+    /// without depth report zero and never pass. Disabled when unset.
+    #[serde(default)]
+    pub min_book_depth: Option<f64>,
+    /// Entry signals are skipped while the spread exceeds this many bps of
+    /// the trade price; exits still go through. Trades of unknown spread are
+    /// not filtered. Disabled when unset
+    #[serde(default)]
+    pub max_spread_bps: Option<f64>,
+    /// How entries are placed: "market" swaps at the quoted price, "limit"
+    /// queues them and swaps once a quote is at or better than the limit
+    /// price. Exits always swap at market. Defaults to "market"
+    #[serde(default)]
+    pub order_type: OrderType,
+    /// Improvement of the limit price over the signal price, in bps (below
+    /// it for buys, above it for sells). Defaults to 0
+    #[serde(default)]
+    pub limit_offset_bps: Option<u64>,
+    /// Milliseconds after which an unfilled limit order is cancelled. Good
+    /// till cancelled (or an opposing signal) when unset
+    #[serde(default)]
+    pub limit_expiry_ms: Option<u64>,
+    /// Retries of a Jupiter HTTP call after a timeout, 429 or 5xx. Defaults to 3
+    #[serde(default)]
+    pub swap_max_retries: Option<u32>,
+    /// First retry delay in milliseconds, doubled on every further retry.
+    /// Defaults to 250
+    #[serde(default)]
+    pub swap_base_delay_ms: Option<u64>,
+    /// Connect and overall timeout of each HTTP or RPC request, in
+    /// milliseconds. Defaults to 10000
+    #[serde(default)]
+    pub http_timeout_ms: Option<u64>,
+    /// Seconds a swap submission may wait to land before it counts as timed
+    /// out. Raised to 100 when lower, so the expiry of its blockhash (60 to
+    /// 90 seconds) is always seen first. Defaults to 100
     #[serde(default)]
     pub tx_confirm_secs: Option<u64>,
+    /// Live orders awaiting confirmation at once. Market data keeps flowing
+    /// while they confirm; signals beyond the limit are skipped. Defaults to 1
+    #[serde(default)]
+    pub max_inflight_orders: Option<usize>,
+    /// Commitment level of the gRPC subscription and of every RPC read.
+    /// Defaults to "confirmed"
+    #[serde(default)]
+    pub commitment: Commitment,
+    /// Commitment a swap must reach to count as filled. Defaults to
+    /// `commitment`
+    #[serde(default)]
+    pub confirm_commitment: Option<Commitment>,
+    /// Times a swap whose blockhash expired before it landed is quoted again
+    /// and resent, as long as the new quote fits the first one's slippage
+    /// bound. Defaults to 2
+    #[serde(default)]
+    pub max_blockhash_retries: Option<u32>,
+    /// Oldest a Jupiter quote may be when its swap is sent, in milliseconds;
+    /// an older quote is dropped and re-fetched. Defaults to 5000
+    #[serde(default)]
+    pub quote_max_age_ms: Option<u64>,
+    /// Total priority fee in lamports paid on each swap. When unset no
+    /// compute-unit-price instruction is added and Jupiter's default applies.
+    #[serde(default)]
+    pub priority_fee_lamports: Option<u64>,
+    /// Raise `slippage_bps` and the priority fee of a market step by step
+    /// while its swaps keep failing to confirm, easing back after fills
+    /// (`[slippage_escalation]` table). Disabled when unset.
+    #[serde(default)]
+    pub slippage_escalation: Option<EscalationConfig>,
+    /// Compute unit limit set on each swap, replacing the one Jupiter chose.
+    /// When unset Jupiter's limit is kept (see `compute_unit_margin_pct`)
+    #[serde(default)]
+    pub compute_unit_limit: Option<u32>,
+    /// Without `compute_unit_limit`, size the limit to the simulated
+    /// consumption plus this percentage. Needs `simulate_before_send`;
+    /// disabled when unset
+    #[serde(default)]
+    pub compute_unit_margin_pct: Option<u32>,
+    /// Enter threshold: model probability above which a buy is signalled
+    /// from flat (sell below `1 - threshold`). Must lie in (0.5, 1.0).
+    /// Defaults to 0.55
+    #[serde(default)]
+    pub signal_threshold: Option<f64>,
+    /// Probability below which a long position is reversed (a short one above
+    /// `1 - exit_threshold`). Must lie in (0, 1 - signal_threshold]; lower
+    /// values need a stronger opposite signal. Defaults to `1 - signal_threshold`
+    #[serde(default)]
+    pub exit_threshold: Option<f64>,
+    /// Minimum delay between two orders (paper or live), in milliseconds.
+    /// Signals arriving sooner are dropped. No cooldown when unset.
+    #[serde(default)]
+    pub min_order_interval_ms: Option<u64>,
+    /// Simulate each signed swap before sending it and abort on failure.
+    /// Defaults to true
+    #[serde(default)]
+    pub simulate_before_send: Option<bool>,
+    /// Simulate orders instead of sending them (`--dry-run` forces it on).
+    /// When unset, paper mode is used on devnet or without `anchor_program_id`
+    #[serde(default)]
+    pub paper_mode: Option<bool>,
+    /// Maximum net base-asset position; buys beyond it are refused. Defaults to 10.0
+    #[serde(default)]
+    pub max_position: Option<f64>,
+    /// Largest notional (amount times the trade price, in quote units) of a
+    /// single entry order; exits are never capped. Unlimited when unset
+    #[serde(default)]
+    pub max_notional_per_order: Option<f64>,
+    /// Shrink orders above `max_notional_per_order` to the cap instead of
+    /// refusing them. Defaults to false
+    #[serde(default)]
+    pub clamp_to_max_notional: Option<bool>,
+    /// SOL the wallet must keep for network fees. Live mode refuses to start
+    /// below it, and it is never counted as spendable on SOL pairs.
+    /// Defaults to 0.01
+    #[serde(default)]
+    pub min_sol_balance: Option<f64>,
+    /// Read the wallet's input token balance before every live order and skip
+    /// the order when it cannot cover the quote. Costs two to three RPC calls
+    /// per order. Defaults to false
+    #[serde(default)]
+    pub check_balance_before_orders: Option<bool>,
+    /// Rule traded while no model is trained yet: "none", "momentum" or
+    /// "mean_reversion". Defaults to "none" (no trading until trained)
+    #[serde(default)]
+    pub cold_start_strategy: ColdStartStrategy,
+    /// How the model is combined with the order-book imbalance: "model"
+    /// (model alone), "and", "or" or "weighted". Defaults to "model"
+    #[serde(default)]
+    pub ensemble_policy: CombinePolicy,
+    /// |Imbalance| beyond which the book votes for a side under the "and"
+    /// and "or" policies. Must lie in [0, 1). Defaults to 0.2
+    #[serde(default)]
+    pub imbalance_threshold: Option<f64>,
+    /// Weight of the model probability under the "weighted" policy, the
+    /// imbalance getting the rest. Must lie in [0, 1]. Defaults to 0.5
+    #[serde(default)]
+    pub ensemble_model_weight: Option<f64>,
+    /// Close the open position once it loses this many bps from its average
+    /// entry price. Disabled when unset.
+    #[serde(default)]
+    pub stop_loss_bps: Option<u64>,
+    /// Close the open position once it gains this many bps from its average
+    /// entry price. Disabled when unset.
+    #[serde(default)]
+    pub take_profit_bps: Option<u64>,
+    /// Fixed fee in quote units charged on every paper fill, on top of the
+    /// estimated network fee and price impact. Defaults to 0.0
+    #[serde(default)]
+    pub paper_fee: Option<f64>,
+    /// Daily realized loss (in quote units, net of fees) after which new orders
+    /// are blocked until the next UTC day. Disabled when unset.
+    #[serde(default)]
+    pub max_daily_loss: Option<f64>,
+    /// Newline-delimited JSON file the training samples are appended to and
+    /// reloaded from on startup. Samples are kept in memory only when unset.
+    #[serde(default)]
+    pub dataset_path: Option<String>,
+    /// Newest samples kept in memory and trained on; older ones are dropped
+    /// (the dataset file keeps them). Unbounded when unset.
+    #[serde(default)]
+    pub max_dataset_samples: Option<usize>,
+    /// New samples between two retrains in paper mode. Defaults to 500
+    #[serde(default)]
+    pub retrain_every: Option<usize>,
+    /// Samples a retrain needs, after holding out the validation set; with
+    /// fewer the current model is kept. Defaults to 10
+    #[serde(default)]
+    pub min_samples_to_train: Option<usize>,
+    /// CSV written by `--record` to train on at startup, so trading starts
+    /// with a warm model rather than after `retrain_every` live samples. It
+    /// must have been recorded with the current feature settings
+    #[serde(default)]
+    pub warmup_data: Option<String>,
+    /// Age, in samples, at which a training sample counts half as much as
+    /// the newest one; weights halve again every further half-life so older
+    /// market regimes fade out. All samples count the same when unset
+    #[serde(default)]
+    pub sample_half_life: Option<f64>,
+    /// How far ahead a sample's label looks: `{ ticks = N }` trades or
+    /// `{ ms = N }` milliseconds later on the same market; the label is 1 if
+    /// the price rose over that span. Defaults to `{ ticks = 1 }` (next trade)
+    #[serde(default)]
+    pub label_horizon: Option<LabelHorizon>,
+    /// Windows, in trades, of the price EMAs appended to the model features.
+    /// Changing them changes the feature width, so the model retrains from
+    /// scratch. Defaults to [10, 50]
+    #[serde(default)]
+    pub ema_windows: Option<Vec<usize>>,
+    /// Trades over which the realized volatility feature is measured. Must
+    /// be at least 2. Defaults to 20
+    #[serde(default)]
+    pub volatility_window: Option<usize>,
+    /// Features passed to the model, by name and in this order: `price`,
+    /// `size`, `spread`, `bid_volume`, `ask_volume`, `imbalance`,
+    /// `ema_<window>` for each of `ema_windows` and `volatility`. The names
+    /// are saved with the model, which is only loaded against the same list.
+    /// Defaults to all of them in that order
+    #[serde(default)]
+    pub features: Option<Vec<String>>,
+    /// CSV file receiving one row per order. Disabled when unset.
+    #[serde(default)]
+    pub trade_log_path: Option<String>,
+    /// CSV file receiving every incoming trade with its features, in the
+    /// format `backtest` and `train` read. Truncated on startup. Disabled when
+    /// unset.
+    #[serde(default)]
+    pub record_path: Option<String>,
+    /// JSON file with each market's PnL and open position, saved after every
+    /// fill and on shutdown and restored on startup. A file written in the
+    /// other mode (paper or live) or by other wallets is neither restored nor
+    /// overwritten. Disabled when unset.
+    #[serde(default)]
+    pub state_path: Option<String>,
+    /// UTC windows (`[[trading_hours]]` tables with `start`, `end` and
+    /// optional `days`) outside which no orders are sent; data is still
+    /// ingested and the model still trains. Always trading when unset.
+    #[serde(default)]
+    pub trading_hours: Vec<TradingWindow>,
+    /// While a file exists at this path, no orders are sent (exits included)
+    /// and open positions are left as they are. Disabled when unset.
+    #[serde(default)]
+    pub kill_switch_path: Option<String>,
+    /// On shutdown in live mode, close every open position with a market
+    /// swap and wait up to `tx_confirm_secs` for it to confirm. Skipped
+    /// while the kill switch or `/halt` blocks orders. Defaults to false
+    #[serde(default)]
+    pub flatten_on_shutdown: Option<bool>,
+    /// Port of the Prometheus `/metrics` endpoint. Disabled when unset.
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    /// Port of the JSON `/status` endpoint and of the admin endpoints:
+    /// `POST /reload-model`, which hot-swaps the shared model from
+    /// `model_path`, and `POST /halt` / `POST /resume`, which stop and restart
+    /// order submission. May equal `metrics_port` to serve everything from
+    /// one server. Disabled when unset.
+    #[serde(default)]
+    pub status_port: Option<u16>,
+    /// Address the `status_port` server listens on, also when it serves
+    /// `metrics_port`. The admin endpoints are unauthenticated: only widen
+    /// this behind a proxy that controls access. Defaults to 127.0.0.1
+    #[serde(default)]
+    pub status_bind_address: Option<IpAddr>,
+    /// Seconds without any trade after which the feed is considered stalled
+    /// and reconnected. Defaults to 120
+    #[serde(default)]
+    pub feed_stale_secs: Option<u64>,
+    /// Connection attempts in a row that fail before any update after which
+    /// the feed gives up and the bot exits with the error. A rejected token
+    /// always stops it at once. Retries forever when unset
+    #[serde(default)]
+    pub feed_max_failures: Option<u32>,
+    /// Reconnects in a row without any frame after which the Helius
+    /// LaserStream feed gives up and the Yellowstone feed takes over.
+    /// Defaults to 10
+    #[serde(default)]
+    pub laserstream_max_retries: Option<u32>,
+    /// Trades buffered between the feed and the trading loop. When the loop
+    /// falls behind, newer trades are dropped (and counted) rather than
+    /// stalling the feed. Defaults to 1024
+    #[serde(default)]
+    pub ingest_queue_size: Option<usize>,
+    /// Where account updates come from: "yellowstone" (Geyser gRPC),
+    /// "rpc_ws" (`accountSubscribe` on the RPC WebSocket, for when no gRPC
+    /// endpoint is available) or "laserstream" (Helius transactions, falling
+    /// back to Yellowstone). Defaults to "yellowstone"
+    #[serde(default)]
+    pub data_source: DataSource,
+    /// WebSocket endpoint of the "rpc_ws" source. Defaults to
+    /// `anchor_cluster` with its http(s) scheme replaced by ws(s)
+    #[serde(default)]
+    pub rpc_ws_url: Option<String>,
+    /// Seconds between keepalive pings on the gRPC subscription. Defaults to 10
+    #[serde(default)]
+    pub grpc_ping_interval_secs: Option<u64>,
+    /// Price levels per book side summed into the depth features. Defaults to 10
+    #[serde(default)]
+    pub book_depth_levels: Option<usize>,
+    /// Share of the most recent samples held out to validate each retrain.
+    /// Must lie in [0, 1); 0 disables validation. Defaults to 0.2
+    #[serde(default)]
+    pub validation_fraction: Option<f64>,
+    /// Validation accuracy a retrained model must exceed to replace the live
+    /// one. Defaults to 0.5
+    #[serde(default)]
+    pub min_validation_accuracy: Option<f64>,
+    /// Model trained and run by the strategy: "logistic" or "decision_tree".
+    /// A saved model file loads as the type it was trained as. Defaults to
+    /// "logistic"
+    #[serde(default)]
+    pub model_type: ModelType,
+    /// L2 regularization strength of the logistic regression. Raise it when
+    /// the model overfits a small dataset. Defaults to 1.0
+    #[serde(default)]
+    pub l2_penalty: Option<f64>,
+    /// Maximum optimizer iterations per training run. Defaults to 100
+    #[serde(default)]
+    pub max_iterations: Option<u64>,
+    /// Gradient norm at which training is considered converged. Defaults to 1e-4
+    #[serde(default)]
+    pub tolerance: Option<f64>,
+    /// Depth limit of the decision tree. Defaults to 6
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Calibration of the model probabilities: "none", "platt" or
+    /// "isotonic", fitted on the newest 20% of each training set. Defaults
+    /// to "none"
+    #[serde(default)]
+    pub calibration: Calibration,
+    /// OpenBook markets to stream (`[[markets]]` tables). Defaults to SOL/USDC.
+    #[serde(default = "default_markets")]
+    pub markets: Vec<MarketConfig>,
+}
+
+/// How settled data must be before the bot acts on it. Lower levels arrive
+/// sooner but may be rolled back by a fork.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    /// Fastest; the block may still be skipped.
+    Processed,
+    /// Voted on by a supermajority; rollbacks are practically unseen.
+    #[default]
+    Confirmed,
+    /// Rooted; roughly 13 seconds behind `processed`.
+    Finalized,
+}
+
+impl Commitment {
+    pub fn config(self) -> CommitmentConfig {
+        match self {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+/// Transport the market account updates are streamed over.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataSource {
+    /// Yellowstone Geyser gRPC subscription.
+    #[default]
+    Yellowstone,
+    /// Standard RPC WebSocket `accountSubscribe`, one subscription per
+    /// account. Carries no block times, so the feed lag is unknown.
+    RpcWs,
+    /// Helius LaserStream `transactionSubscribe` on the markets, switching
+    /// to the Yellowstone feed once `laserstream_max_retries` reconnects in
+    /// a row got nothing.
+    Laserstream,
+}
+
+/// How entry orders are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderType {
+    /// Swap at whatever Jupiter quotes.
+    #[default]
+    Market,
+    /// Re-quote on every trade and swap once the quote reaches the limit.
+    Limit,
+}
+
+/// Span over which a training sample's forward return is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelHorizon {
+    /// Number of later trades on the same market.
+    Ticks(u64),
+    /// Milliseconds of receive time.
+    Ms(u64),
+}
+
+impl Default for LabelHorizon {
+    fn default() -> Self {
+        LabelHorizon::Ticks(1)
+    }
+}
+
+/// Venue a market's data is streamed from.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketSource {
+    /// OpenBook (Serum v3) order book: fills from the event queue.
+    #[default]
+    Openbook,
+    /// Raydium constant-product pool: prices from the vault reserves.
+    Raydium,
+    /// Phoenix order book: fills inferred from the market account's book.
+    Phoenix,
+}
+
+/// Accounts and token parameters of one market.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarketConfig {
+    /// Label attached to every `TradeMsg` from this market, e.g. "SOL/USDC".
+    /// Also the `BASE/QUOTE` pair swapped on Jupiter when trading it.
+    pub name: String,
+    /// "openbook", "raydium" or "phoenix". Defaults to "openbook"
+    #[serde(default)]
+    pub source: MarketSource,
+    /// OpenBook market state account, read once at startup for the lot
+    /// sizes, the Raydium pool id, or the Phoenix market account.
+    pub market: String,
+    /// OpenBook accounts; required for the "openbook" source.
+    #[serde(default)]
+    pub event_queue: String,
+    #[serde(default)]
+    pub bids: String,
+    #[serde(default)]
+    pub asks: String,
+    /// Raydium pool token accounts holding the reserves; required for the
+    /// "raydium" source.
+    #[serde(default)]
+    pub base_vault: String,
+    #[serde(default)]
+    pub quote_vault: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    /// Model used for this market only. It is never retrained; markets
+    /// without one share the top-level `model_path` model.
+    #[serde(default)]
+    pub model_path: Option<String>,
+}
+
+/// The SOL/USDC OpenBook (v1) market on mainnet, used when no market is configured.
+fn default_markets() -> Vec<MarketConfig> {
+    vec![MarketConfig {
+        name: "SOL/USDC".to_string(),
+        source: MarketSource::Openbook,
+        market: "8BnEgHoWFysVcuFFX7QztDmzuH8r5ZFvyP3sYwn1XTh6".to_string(),
+        event_queue: "HxTJgEMDh8Jo6CQwwht6v7qAbKLFXrrHWEM5E9MJ4tSE".to_string(),
+        bids: "9krN9TPCvQhTWZAxkVtxDC6VqeoLyzmKcqJxw5jZA7Ve".to_string(),
+        asks: "EpGvXiuQgmEYBLETymFczwa3oYuoFkyeDXovvrSM7g1D".to_string(),
+        base_vault: String::new(),
+        quote_vault: String::new(),
+        base_mint: "So11111111111111111111111111111111111111112".to_string(),
+        quote_mint: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".to_string(),
+        base_decimals: 9,
+        quote_decimals: 6,
+        model_path: None,
+    }]
 }
 
 impl BotConfig {
     pub fn from_file(path: &str) -> Result<Self> {
         let content = fs::read_to_string(path)?;
-        Ok(toml::from_str(&content).map_err(|e| anyhow!(e))?)
+        let mut cfg: Self = toml::from_str(&content).map_err(|e| anyhow!(e))?;
+        cfg.apply_env_overrides();
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Replace secrets with the `HELIUS_API_KEY`, `YELLOWSTONE_TOKEN` and
+    /// `WALLET_KEYPAIR` environment variables when they are set and non-empty.
+    /// The environment always wins over the file.
+    fn apply_env_overrides(&mut self) {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if let Some(key) = var("HELIUS_API_KEY") {
+            self.helius_api_key = key;
+        }
+        if let Some(token) = var("YELLOWSTONE_TOKEN") {
+            self.yellowstone_token = Some(token);
+        }
+        if let Some(keypairs) = var("WALLET_KEYPAIR") {
+            self.wallet_keypair = keypairs
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(key) = var("JUPITER_API_KEY") {
+            self.jupiter_api_key = Some(key);
+        }
+        // An empty token in the file means "no token" unless the env set one
+        if self.yellowstone_token.as_deref() == Some("") {
+            self.yellowstone_token = None;
+        }
+        if self.jupiter_api_key.as_deref() == Some("") {
+            self.jupiter_api_key = None;
+        }
+    }
+
+    /// Check the configuration for values that would only fail later at
+    /// runtime. Every problem is reported, not just the first.
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.helius_api_key.is_empty() {
+            errors.push("helius_api_key is empty and HELIUS_API_KEY is not set".to_string());
+        }
+        if self.wallet_keypair.is_empty() {
+            errors.push("wallet_keypair is empty and WALLET_KEYPAIR is not set".to_string());
+        }
+        let mut owners = HashSet::new();
+        for (i, source) in self.wallet_keypair.iter().enumerate() {
+            match load_keypair(source) {
+                Ok(keypair) if !owners.insert(keypair.pubkey()) => {
+                    errors.push(format!("wallet_keypair lists wallet {} twice", keypair.pubkey()));
+                }
+                Ok(_) => {}
+                Err(e) => errors.push(format!("wallet_keypair[{}] is not a valid keypair: {}", i, e)),
+            }
+        }
+        if self.symbols.is_empty() {
+            errors.push("symbols must not be empty".to_string());
+        }
+        for (name, value) in [
+            ("jupiter_api_url", &self.jupiter_api_url),
+            ("anchor_cluster", &self.anchor_cluster),
+        ] {
+            if let Err(e) = reqwest::Url::parse(value) {
+                errors.push(format!("{} '{}' is not a valid URL: {}", name, value, e));
+            }
+        }
+        if let Some(url) = &self.rpc_ws_url {
+            if let Err(e) = reqwest::Url::parse(url) {
+                errors.push(format!("rpc_ws_url '{}' is not a valid URL: {}", url, e));
+            }
+        }
+        if let Some(bps) = self.slippage_bps {
+            if bps > MAX_SLIPPAGE_BPS {
+                errors.push(format!(
+                    "slippage_bps must be at most {}, got {}",
+                    MAX_SLIPPAGE_BPS, bps
+                ));
+            }
+        }
+        if let Err(e) = check_writable_parent(&self.model_path) {
+            errors.push(format!("model_path '{}': {}", self.model_path, e));
+        }
+        if let Some(threshold) = self.signal_threshold {
+            // Below 0.5 the buy and sell bands would overlap
+            if !(threshold > 0.5 && threshold < 1.0) {
+                errors.push(format!(
+                    "signal_threshold must be in (0.5, 1.0), got {}",
+                    threshold
+                ));
+            }
+        }
+        if let Some(exit) = self.exit_threshold {
+            let enter = self.signal_threshold.unwrap_or(DEFAULT_SIGNAL_THRESHOLD);
+            if !(exit > 0.0 && exit <= 1.0 - enter) {
+                errors.push(format!(
+                    "exit_threshold must be in (0, {}] for signal_threshold {}, got {}",
+                    1.0 - enter,
+                    enter,
+                    exit
+                ));
+            }
+        }
+        if self.limit_offset_bps.is_some_and(|bps| bps >= 10_000) {
+            errors.push("limit_offset_bps must be below 10000".to_string());
+        }
+        if self.dexes.is_some() && self.exclude_dexes.is_some() {
+            errors.push("dexes and exclude_dexes are mutually exclusive".to_string());
+        }
+        if self.dexes.as_ref().is_some_and(|d| d.is_empty()) {
+            errors.push("dexes must not be empty; omit it to allow every AMM".to_string());
+        }
+        if self.ingest_queue_size == Some(0) {
+            errors.push("ingest_queue_size must be positive".to_string());
+        }
+        if self.max_inflight_orders == Some(0) {
+            errors.push("max_inflight_orders must be positive".to_string());
+        }
+        if self.limit_expiry_ms == Some(0) {
+            errors.push("limit_expiry_ms must be positive".to_string());
+        }
+        if self.record_fills_onchain == Some(true)
+            && !self.anchor_program_id.is_empty()
+            && Pubkey::from_str(&self.anchor_program_id).is_err()
+        {
+            errors.push(format!("anchor_program_id '{}' is not a valid pubkey", self.anchor_program_id));
+        }
+        if self.record_fills_onchain == Some(true) {
+            // The name seeds the market's PnL account, and seeds are capped
+            for market in self.markets.iter().filter(|m| m.name.len() > MAX_SEED_LEN) {
+                errors.push(format!(
+                    "market name '{}' is {} bytes, over the {} an on-chain PnL account seed allows",
+                    market.name,
+                    market.name.len(),
+                    MAX_SEED_LEN
+                ));
+            }
+        }
+        if self.jupiter_rps.is_some_and(|rps| !(rps > 0.0 && rps.is_finite())) {
+            errors.push("jupiter_rps must be positive".to_string());
+        }
+        if self.quote_max_age_ms == Some(0) {
+            errors.push("quote_max_age_ms must be positive".to_string());
+        }
+        if let Some(threshold) = self.imbalance_threshold {
+            if !(0.0..1.0).contains(&threshold) {
+                errors.push(format!("imbalance_threshold must be in [0, 1), got {}", threshold));
+            }
+        }
+        if let Some(weight) = self.ensemble_model_weight {
+            if !(0.0..=1.0).contains(&weight) {
+                errors.push(format!("ensemble_model_weight must be in [0, 1], got {}", weight));
+            }
+        }
+        if matches!(self.label_horizon, Some(LabelHorizon::Ticks(0) | LabelHorizon::Ms(0))) {
+            errors.push("label_horizon must be positive".to_string());
+        }
+        if let Some(limit) = self.compute_unit_limit {
+            if limit == 0 || limit > 1_400_000 {
+                errors.push(format!("compute_unit_limit must be in 1..=1400000, got {}", limit));
+            }
+        }
+        if self.compute_unit_margin_pct.is_some() && self.simulate_before_send == Some(false) {
+            errors.push("compute_unit_margin_pct needs simulate_before_send".to_string());
+        }
+        if self.ema_windows.as_ref().is_some_and(|w| w.contains(&0)) {
+            errors.push("ema_windows must all be positive".to_string());
+        }
+        if self.volatility_window.is_some_and(|w| w < 2) {
+            errors.push("volatility_window must be at least 2".to_string());
+        }
+        if let Err(e) = self.feature_spec().check() {
+            errors.push(e.to_string());
+        }
+        if self.retrain_every == Some(0) {
+            errors.push("retrain_every must be at least 1".to_string());
+        }
+        if self.sample_half_life.is_some_and(|h| h <= 0.0) {
+            errors.push("sample_half_life must be positive".to_string());
+        }
+        if self.max_notional_per_order.is_some_and(|cap| !(cap > 0.0 && cap.is_finite())) {
+            errors.push("max_notional_per_order must be positive".to_string());
+        }
+        if self.max_spread_bps.is_some_and(|bps| bps <= 0.0) {
+            errors.push("max_spread_bps must be positive".to_string());
+        }
+        if self.min_sol_balance.is_some_and(|sol| sol < 0.0) {
+            errors.push("min_sol_balance must not be negative".to_string());
+        }
+        if self.warmup_data.is_some() && self.warmup_data == self.record_path {
+            errors.push("warmup_data must not be the record_path, which is truncated on startup".to_string());
+        }
+        if self.feed_max_failures == Some(0) {
+            errors.push("feed_max_failures must be positive".to_string());
+        }
+        if self.max_dataset_samples == Some(0) {
+            errors.push("max_dataset_samples must be positive".to_string());
+        }
+        if let Some(fraction) = self.validation_fraction {
+            if !(0.0..1.0).contains(&fraction) {
+                errors.push(format!(
+                    "validation_fraction must be in [0, 1), got {}",
+                    fraction
+                ));
+            }
+        }
+        if let Some(esc) = &self.slippage_escalation {
+            if esc.after_failures == Some(0) {
+                errors.push("slippage_escalation.after_failures must be positive".to_string());
+            }
+            if esc.max_slippage_bps.is_some_and(|bps| bps > 10_000) {
+                errors.push("slippage_escalation.max_slippage_bps must be at most 10000".to_string());
+            }
+        }
+        for market in &self.markets {
+            for mint in [&market.base_mint, &market.quote_mint] {
+                if self.blacklisted_mints.contains(mint) {
+                    errors.push(format!("market {}: mint {} is blacklisted", market.name, mint));
+                }
+            }
+        }
+        for window in &self.trading_hours {
+            if window.days.as_ref().is_some_and(|days| days.is_empty()) {
+                errors.push(format!(
+                    "trading_hours window {}-{} has no days; omit days for every day",
+                    window.start, window.end
+                ));
+            }
+        }
+        if let Some(impact) = self.max_price_impact_pct {
+            if !(impact > 0.0 && impact <= 100.0) {
+                errors.push(format!("max_price_impact_pct must be in (0, 100], got {}", impact));
+            }
+        }
+        if let Some(depth) = self.min_book_depth {
+            if depth.is_nan() || depth < 0.0 {
+                errors.push(format!("min_book_depth must be non-negative, got {}", depth));
+            }
+        }
+        if let Some(min) = self.min_validation_accuracy {
+            if !(0.0..=1.0).contains(&min) {
+                errors.push(format!(
+                    "min_validation_accuracy must be in [0, 1], got {}",
+                    min
+                ));
+            }
+        }
+        for market in &self.markets {
+            let required: &[(&str, &str)] = match market.source {
+                MarketSource::Openbook => &[
+                    ("event_queue", &market.event_queue),
+                    ("bids", &market.bids),
+                    ("asks", &market.asks),
+                ],
+                MarketSource::Raydium => &[
+                    ("base_vault", &market.base_vault),
+                    ("quote_vault", &market.quote_vault),
+                ],
+                MarketSource::Phoenix => &[],
+            };
+            for (field, value) in required {
+                if value.is_empty() {
+                    errors.push(format!(
+                        "market {}: {} is required for source {:?}",
+                        market.name, field, market.source
+                    ));
+                }
+            }
+        }
+        for (name, value) in [("l2_penalty", self.l2_penalty), ("tolerance", self.tolerance)] {
+            if let Some(v) = value {
+                if v.is_nan() || v < 0.0 {
+                    errors.push(format!("{} must be non-negative, got {}", name, v));
+                }
+            }
+        }
+        if self.max_depth == Some(0) {
+            errors.push("max_depth must be positive".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("invalid configuration:\n  - {}", errors.join("\n  - ")))
+        }
+    }
+
+    /// Load every wallet listed in `wallet_keypair`, in order.
+    pub fn wallets(&self) -> Result<Vec<Keypair>> {
+        if self.wallet_keypair.is_empty() {
+            return Err(anyhow!("no wallet_keypair configured"));
+        }
+        self.wallet_keypair.iter().map(|source| load_keypair(source)).collect()
+    }
+
+    /// Copy safe to print: API keys and tokens are masked, as are inline
+    /// wallet secret keys (keypair file paths are kept), and the query string
+    /// of every URL, where providers put their keys.
+    pub fn redacted(&self) -> Self {
+        let mask = |secret: &str| if secret.is_empty() { String::new() } else { REDACTED.to_string() };
+        let mut cfg = self.clone();
+        cfg.helius_api_key = mask(&self.helius_api_key);
+        cfg.yellowstone_token = self.yellowstone_token.as_deref().map(mask);
+        cfg.jupiter_api_key = self.jupiter_api_key.as_deref().map(mask);
+        cfg.wallet_keypair = self
+            .wallet_keypair
+            .iter()
+            .map(|source| if Path::new(source).is_file() { source.clone() } else { mask(source) })
+            .collect();
+        cfg.anchor_cluster = redact_query(&self.anchor_cluster);
+        cfg.jupiter_api_url = redact_query(&self.jupiter_api_url);
+        cfg.rpc_ws_url = self.rpc_ws_url.as_deref().map(redact_query);
+        cfg
+    }
+
+    /// WebSocket endpoint of the "rpc_ws" data source.
+    pub fn ws_url(&self) -> String {
+        if let Some(url) = &self.rpc_ws_url {
+            return url.clone();
+        }
+        if let Some(rest) = self.anchor_cluster.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else if let Some(rest) = self.anchor_cluster.strip_prefix("http://") {
+            format!("ws://{}", rest)
+        } else {
+            self.anchor_cluster.clone()
+        }
+    }
+
+    /// Timeout applied to every HTTP and RPC request.
+    pub fn http_timeout(&self) -> Duration {
+        Duration::from_millis(self.http_timeout_ms.unwrap_or(10_000))
     }
+
+    /// Rolling features and the selection fed to the model, with defaults
+    /// filled in.
+    pub fn feature_spec(&self) -> FeatureSpec {
+        let defaults = FeatureSpec::default();
+        FeatureSpec {
+            ema_windows: self.ema_windows.clone().unwrap_or(defaults.ema_windows),
+            volatility_window: self.volatility_window.unwrap_or(defaults.volatility_window),
+            selected: self.features.clone(),
+        }
+    }
+
+    /// Optimizer settings for model training, with defaults filled in.
+    pub fn train_options(&self) -> TrainOptions {
+        let defaults = TrainOptions::default();
+        TrainOptions {
+            model_type: self.model_type,
+            l2_penalty: self.l2_penalty.unwrap_or(defaults.l2_penalty),
+            max_iterations: self.max_iterations.unwrap_or(defaults.max_iterations),
+            tolerance: self.tolerance.unwrap_or(defaults.tolerance),
+            max_depth: self.max_depth.unwrap_or(defaults.max_depth),
+            calibration: self.calibration,
+            features: self.feature_spec().names(),
+        }
+    }
+
+    /// Point every file the bot writes (models, dataset, state, trade log and
+    /// recording) into `dir`, so a `--sim` run leaves the live ones untouched.
+    /// Existing models are copied there first and still seed the run.
+    pub fn sandbox(&mut self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let inside = |path: &str, prefix: &str| {
+            let name = Path::new(path).file_name().map_or("file".into(), |n| n.to_string_lossy());
+            dir.join(format!("{}{}", prefix, name)).to_string_lossy().into_owned()
+        };
+        let copy = |from: &str, to: &str| -> Result<()> {
+            if Path::new(from).is_file() {
+                fs::copy(from, to).map_err(|e| anyhow!("copying model '{}': {}", from, e))?;
+            }
+            Ok(())
+        };
+        let model_path = inside(&self.model_path, "");
+        copy(&self.model_path, &model_path)?;
+        self.model_path = model_path;
+        for (i, market) in self.markets.iter_mut().enumerate() {
+            if let Some(path) = &market.model_path {
+                // Markets may share a file name in different directories
+                let sandboxed = inside(path, &format!("market{}-", i));
+                copy(path, &sandboxed)?;
+                market.model_path = Some(sandboxed);
+            }
+        }
+        for path in [
+            &mut self.dataset_path,
+            &mut self.state_path,
+            &mut self.trade_log_path,
+            &mut self.record_path,
+        ] {
+            if let Some(p) = path {
+                *p = inside(p, "");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `signal_threshold` and `exit_threshold` of a config file, read without
+/// requiring or validating anything else in it.
+#[derive(Debug, Default, Deserialize)]
+pub struct Thresholds {
+    pub signal_threshold: Option<f64>,
+    pub exit_threshold: Option<f64>,
+}
+
+impl Thresholds {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).map_err(|e| anyhow!(e))
+    }
+}
+
+/// Fail unless the directory that will hold `path` exists and is writable.
+fn check_writable_parent(path: &str) -> Result<()> {
+    let parent = match Path::new(path).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let meta = fs::metadata(parent)
+        .map_err(|e| anyhow!("directory '{}' is not accessible: {}", parent.display(), e))?;
+    if !meta.is_dir() {
+        return Err(anyhow!("'{}' is not a directory", parent.display()));
+    }
+    if meta.permissions().readonly() {
+        return Err(anyhow!("directory '{}' is read-only", parent.display()));
+    }
+    Ok(())
+}
+
+/// Paper-mode config on the default market with a fresh wallet, no model
+/// file and unreachable endpoints, for tests; `extra` is appended TOML.
+#[cfg(test)]
+pub fn test_config(extra: &str) -> BotConfig {
+    let model_path = std::env::temp_dir().join(format!("botsolana-{}-no-model.bin", std::process::id()));
+    let content = format!(
+        r#"
+        jupiter_api_url = "http://127.0.0.1:9"
+        wallet_keypair = "{}"
+        symbols = ["SOL", "USDC"]
+        model_path = "{}"
+        anchor_cluster = "http://127.0.0.1:9"
+        anchor_program_id = ""
+        paper_mode = true
+        {}
+        "#,
+        bs58::encode(Keypair::new().to_bytes()).into_string(),
+        model_path.display(),
+        extra
+    );
+    toml::from_str(&content).unwrap()
 }
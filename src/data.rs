@@ -1,22 +1,117 @@
 use anyhow::Result;
+use futures_util::future::BoxFuture;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
 
-#[derive(Debug, Deserialize, Clone)]
+use crate::config::{BotConfig, MarketConfig};
+use crate::grpc_stream::{FeedError, TradeStream};
+
+/// Wrapped SOL mint, whose balance may move as native lamports instead.
+const WSOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct TradeMsg {
     pub price: f64,
     pub size: f64,
     pub side: String,
     pub ts: i64,
-    /// Optional spread (best ask - best bid) in USDC. Zero if unknown.
+    /// Spread (best ask - best bid) in USDC. `None` when a side of the book
+    /// is unknown, as on AMMs or before the book accounts were decoded.
+    #[serde(default)]
+    pub spread: Option<f64>,
+    /// Name of the market the trade happened on (see `MarketConfig::name`).
+    #[serde(default)]
+    pub market: String,
+    /// Base quantity resting on the top bid levels. Zero if unknown.
+    #[serde(default)]
+    pub bid_volume: f64,
+    /// Base quantity resting on the top ask levels. Zero if unknown.
+    #[serde(default)]
+    pub ask_volume: f64,
+    /// Order-book imbalance `(bid - ask) / (bid + ask)` in [-1, 1].
     #[serde(default)]
-    pub spread: f64,
+    pub imbalance: f64,
+    /// On-chain time of the event in ms, estimated from block times. `ts` is
+    /// when it was received, so `ts - event_ts` is the feed lag.
+    #[serde(default)]
+    pub event_ts: Option<i64>,
 }
 
+/// Length of the vector returned by `TradeMsg::features`.
+pub const N_FEATURES: usize = 6;
+/// Names of the `TradeMsg::features` columns, as listed in `features`.
+pub const FEATURE_NAMES: [&str; N_FEATURES] = ["price", "size", "spread", "bid_volume", "ask_volume", "imbalance"];
+
+impl TradeMsg {
+    /// False when a number is NaN or infinite, the price is not positive or
+    /// the size negative, as decoded from malformed account data.
+    pub fn is_valid(&self) -> bool {
+        let values = [self.price, self.size, self.bid_volume, self.ask_volume, self.imbalance];
+        let spread_ok = match self.spread {
+            Some(spread) => spread.is_finite(),
+            None => true,
+        };
+        values.iter().all(|v| v.is_finite()) && spread_ok && self.price > 0.0 && self.size >= 0.0
+    }
+
+    /// Feature vector fed to the model, shared by live trading and backtests.
+    /// `spread` replaces `self.spread`, which may be unknown; see
+    /// `RollingFeatures::push` for how it is imputed.
+    pub fn features(&self, spread: f64) -> Vec<f64> {
+        vec![
+            self.price,
+            self.size,
+            spread,
+            self.bid_volume,
+            self.ask_volume,
+            self.imbalance,
+        ]
+    }
+}
+
+/// One row of a recorded-trades CSV: the `TradeMsg` columns plus the raw value
+/// of the optional `label` column.
+pub struct CsvRow {
+    pub trade: TradeMsg,
+    pub label: Option<String>,
+}
+
+/// Read a CSV with the `TradeMsg` columns. Shared by the backtester and the
+/// `train` subcommand so both build features the same way.
+pub fn read_trade_csv(path: &str) -> Result<Vec<CsvRow>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let label_idx = headers.iter().position(|h| h == "label");
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let trade: TradeMsg = record.deserialize(Some(&headers))?;
+        let label = label_idx.and_then(|i| record.get(i)).map(|v| v.trim().to_string());
+        rows.push(CsvRow { trade, label });
+    }
+    Ok(rows)
+}
+
+/// Minimum delay between two "unparseable frame" warnings.
+const PARSE_WARN_INTERVAL: Duration = Duration::from_secs(30);
+/// First delay before reconnecting a dropped Helius websocket.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for the exponential reconnect backoff.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Reconnects in a row without a single frame before giving up, by default.
+const DEFAULT_MAX_RETRIES: u32 = 10;
+
 pub struct LaserStream {
+    /// Websocket host, without the API key, for logs and errors.
+    host: String,
     url: String,
+    max_retries: u32,
 }
 
 impl LaserStream {
@@ -30,34 +125,371 @@ impl LaserStream {
             "wss://mainnet.helius-rpc.com"
         };
         Self {
-            url: format!("{host}/?api-key={api_key}")
+            host: host.to_string(),
+            url: format!("{host}/?api-key={api_key}"),
+            max_retries: DEFAULT_MAX_RETRIES,
         }
     }
 
-    pub async fn connect(&self, symbols: &[String]) -> Result<impl futures_util::Stream<Item = TradeMsg>> {
-        log::info!("Connecting to Helius WS: {}", self.url);
-        let (ws, _) = match connect_async(&self.url).await {
-            Ok(ok) => ok,
-            Err(e) => {
-                log::error!("WebSocket connection error: {}", e);
-                return Err(e.into());
-            }
-        };
-        let (mut write, read) = ws.split();
+    pub fn from_config(cfg: &BotConfig) -> Self {
+        let mut stream = Self::new(&cfg.helius_api_key, &cfg.anchor_cluster);
+        stream.max_retries = cfg.laserstream_max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        stream
+    }
+
+    /// Subscribe to transactions touching the given market accounts through
+    /// Helius' `transactionSubscribe` and turn each into a `TradeMsg` from the
+    /// fee payer's base/quote balance changes.
+    ///
+    /// A dropped websocket is reconnected and resubscribed with exponential
+    /// backoff. After `max_retries` reconnects in a row that receive nothing
+    /// the stream yields `FeedError::Unreachable` and ends, so the caller can
+    /// fall back to the gRPC feed with `with_fallback`.
+    pub async fn connect(&self, markets: &[MarketConfig]) -> Result<TradeStream> {
+        let accounts: Vec<&str> = markets.iter().map(|m| m.market.as_str()).collect();
         let sub_msg = json!({
-            "type": "subscribe",
-            "channels": [{"name": "trades", "symbols": symbols}]
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "transactionSubscribe",
+            "params": [
+                {"accountInclude": accounts, "failed": false},
+                {
+                    "commitment": "confirmed",
+                    "encoding": "jsonParsed",
+                    "transactionDetails": "full",
+                    "maxSupportedTransactionVersion": 0
+                }
+            ]
         })
         .to_string();
-        log::info!("Sending subscription: {}", sub_msg);
-        write
-            .send(tokio_tungstenite::tungstenite::Message::Text(sub_msg))
-            .await?;
-        Ok(read.filter_map(|msg| async move {
-            match msg.ok()?.into_text().ok().and_then(|t| serde_json::from_str::<TradeMsg>(&t).ok()) {
-                Some(tm) => Some(tm),
-                None => None,
+
+        let markets = markets.to_vec();
+        let (host, url, max_retries) = (self.host.clone(), self.url.clone(), self.max_retries);
+        let (tx, rx) = mpsc::channel::<TradeMsg>(1024);
+        let (failed_tx, failed_rx) = oneshot::channel::<FeedError>();
+        tokio::spawn(async move {
+            let mut warn = RateLimitedWarn::default();
+            let mut backoff = INITIAL_BACKOFF;
+            let mut failures = 0u32;
+            loop {
+                let mut frames = 0u64;
+                let last = match run_session(&url, &sub_msg, &markets, &tx, &mut warn, &mut frames).await {
+                    Ok(()) => {
+                        log::warn!("Helius WS closed after {} frames", frames);
+                        "connection closed".to_string()
+                    }
+                    Err(e) => {
+                        log::error!("Helius WS error after {} frames: {:#}", frames, e);
+                        format!("{:#}", e)
+                    }
+                };
+                if tx.is_closed() {
+                    log::info!("TradeMsg consumer dropped; stopping Helius WS");
+                    break;
+                }
+                if frames > 0 {
+                    backoff = INITIAL_BACKOFF;
+                    failures = 0;
+                } else {
+                    failures += 1;
+                }
+                if failures > max_retries {
+                    let _ = failed_tx.send(FeedError::Unreachable {
+                        endpoint: host,
+                        attempts: failures,
+                        last,
+                    });
+                    break;
+                }
+                log::info!("Reconnecting to {} in {:?}", host, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
             }
-        }))
+        });
+        // The task's verdict, if any, follows the last trade it forwarded
+        let failure = futures_util::stream::once(failed_rx).filter_map(|res| async move { res.ok().map(Err) });
+        Ok(Box::pin(ReceiverStream::new(rx).map(Ok).chain(failure)))
+    }
+}
+
+/// Feed followed by `with_fallback`.
+enum Feed {
+    Primary(TradeStream, BoxFuture<'static, Result<TradeStream>>),
+    Fallback(TradeStream),
+}
+
+/// Trades of `primary` until it gives up with `FeedError::Unreachable`, then
+/// of the stream `fallback` connects to. Other errors end the stream as
+/// usual, as does the original error when the fallback cannot connect.
+pub fn with_fallback(primary: TradeStream, fallback: BoxFuture<'static, Result<TradeStream>>) -> TradeStream {
+    let feed = Some(Feed::Primary(primary, fallback));
+    Box::pin(futures_util::stream::unfold(feed, |feed| async move {
+        let mut stream = match feed? {
+            Feed::Primary(mut stream, fallback) => match stream.next().await? {
+                Err(e @ FeedError::Unreachable { .. }) => {
+                    log::error!("{}; falling back to the gRPC feed", e);
+                    match fallback.await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            log::error!("gRPC fallback failed to connect: {:#}", err);
+                            return Some((Err(e), None));
+                        }
+                    }
+                }
+                item => return Some((item, Some(Feed::Primary(stream, fallback)))),
+            },
+            Feed::Fallback(stream) => stream,
+        };
+        let item = stream.next().await?;
+        Some((item, Some(Feed::Fallback(stream))))
+    }))
+}
+
+/// Connect, send the subscription and forward trades into `tx` until the
+/// socket closes or errors. `frames` counts the frames received so the
+/// caller can tell a healthy session from a failed connect.
+async fn run_session(
+    url: &str,
+    sub_msg: &str,
+    markets: &[MarketConfig],
+    tx: &mpsc::Sender<TradeMsg>,
+    warn: &mut RateLimitedWarn,
+    frames: &mut u64,
+) -> Result<()> {
+    log::info!("Connecting to Helius WS");
+    let (ws, _) = connect_async(url).await?;
+    let (mut write, mut read) = ws.split();
+    log::info!("Sending subscription: {}", sub_msg);
+    write.send(Message::Text(sub_msg.to_string())).await?;
+
+    loop {
+        let msg = tokio::select! {
+            msg = read.next() => match msg {
+                Some(msg) => msg?,
+                None => return Ok(()),
+            },
+            // The consumer dropped the stream
+            _ = tx.closed() => return Ok(()),
+        };
+        *frames += 1;
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Ping(payload) => {
+                let _ = write.send(Message::Pong(payload)).await;
+                continue;
+            }
+            Message::Close(frame) => {
+                log::warn!("Helius WS closed: {:?}", frame);
+                return Ok(());
+            }
+            _ => continue,
+        };
+        match serde_json::from_str::<WsFrame>(&text) {
+            Ok(WsFrame::Error { error }) => {
+                log::error!("Helius WS error {}: {}", error.code, error.message);
+            }
+            Ok(WsFrame::Notification { params }) => {
+                let Some(trade) = trade_from_notification(&params.result, markets) else {
+                    continue;
+                };
+                if tx.send(trade).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(WsFrame::Response { id, result }) => {
+                log::info!("Helius subscription {} confirmed: id {}", id, result);
+            }
+            Err(e) => warn.warn(&e),
+        }
+    }
+}
+
+/// Frames sent by the Helius websocket. Variant order matters: serde tries
+/// them in turn.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WsFrame {
+    Error { error: RpcErrorBody },
+    /// `transactionNotification` pushed for each matching transaction.
+    Notification { params: NotificationParams },
+    /// Reply to our request; `result` is the subscription id.
+    Response { id: u64, result: serde_json::Value },
+}
+
+#[derive(Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    result: TxNotification,
+}
+
+#[derive(Deserialize)]
+struct TxNotification {
+    transaction: EncodedTx,
+}
+
+#[derive(Deserialize)]
+struct EncodedTx {
+    meta: Option<TxMeta>,
+    transaction: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TxMeta {
+    err: Option<serde_json::Value>,
+    fee: u64,
+    pre_balances: Vec<u64>,
+    post_balances: Vec<u64>,
+    #[serde(default)]
+    pre_token_balances: Vec<TokenBalance>,
+    #[serde(default)]
+    post_token_balances: Vec<TokenBalance>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenBalance {
+    mint: String,
+    owner: Option<String>,
+    ui_token_amount: UiTokenAmount,
+}
+
+#[derive(Deserialize)]
+struct UiTokenAmount {
+    amount: String,
+}
+
+/// Build a trade from the fee payer's net change in base and quote tokens.
+/// Native SOL is used for a SOL base when no wrapped-SOL balance moved.
+fn trade_from_notification(tx: &TxNotification, markets: &[MarketConfig]) -> Option<TradeMsg> {
+    let meta = tx.transaction.meta.as_ref()?;
+    if meta.err.is_some() {
+        return None;
+    }
+    // jsonParsed keys are objects; the first one is the fee payer.
+    let payer = tx.transaction.transaction["message"]["accountKeys"][0]["pubkey"].as_str()?;
+    for market in markets {
+        let mut base = token_delta(meta, payer, &market.base_mint);
+        if base == 0 && market.base_mint == WSOL_MINT {
+            // The payer also paid the fee in lamports; add it back
+            base = *meta.post_balances.first()? as i128 - *meta.pre_balances.first()? as i128
+                + meta.fee as i128;
+        }
+        let quote = token_delta(meta, payer, &market.quote_mint);
+        // A swap moves base and quote in opposite directions
+        if base == 0 || quote == 0 || base.signum() == quote.signum() {
+            continue;
+        }
+        let size = base.unsigned_abs() as f64 / 10f64.powi(market.base_decimals as i32);
+        let notional = quote.unsigned_abs() as f64 / 10f64.powi(market.quote_decimals as i32);
+        return Some(TradeMsg {
+            price: notional / size,
+            size,
+            side: if base > 0 { "bid" } else { "ask" }.to_string(),
+            ts: chrono::Utc::now().timestamp_millis(),
+            spread: None,
+            market: market.name.clone(),
+            bid_volume: 0.0,
+            ask_volume: 0.0,
+            imbalance: 0.0,
+            event_ts: None,
+        });
+    }
+    None
+}
+
+/// Net change of `owner`'s `mint` balance in atomic units.
+fn token_delta(meta: &TxMeta, owner: &str, mint: &str) -> i128 {
+    let sum = |balances: &[TokenBalance]| -> i128 {
+        balances
+            .iter()
+            .filter(|b| b.mint == mint && b.owner.as_deref() == Some(owner))
+            .filter_map(|b| b.ui_token_amount.amount.parse::<i128>().ok())
+            .sum()
+    };
+    sum(&meta.post_token_balances) - sum(&meta.pre_token_balances)
+}
+
+/// Logs frames that fail to parse at most once per `PARSE_WARN_INTERVAL`,
+/// with the number of failures since the previous warning.
+#[derive(Default)]
+struct RateLimitedWarn {
+    last: Option<Instant>,
+    suppressed: u64,
+}
+
+impl RateLimitedWarn {
+    fn warn(&mut self, err: &serde_json::Error) {
+        if self.last.is_some_and(|t| t.elapsed() < PARSE_WARN_INTERVAL) {
+            self.suppressed += 1;
+            return;
+        }
+        log::warn!(
+            "Unparseable Helius frame ({} more since last warning): {}",
+            self.suppressed,
+            err
+        );
+        self.last = Some(Instant::now());
+        self.suppressed = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(price: f64) -> Result<TradeMsg, FeedError> {
+        Ok(TradeMsg { price, size: 1.0, ..Default::default() })
+    }
+
+    fn feed(items: Vec<Result<TradeMsg, FeedError>>) -> TradeStream {
+        Box::pin(futures_util::stream::iter(items))
+    }
+
+    fn connects(stream: TradeStream) -> BoxFuture<'static, Result<TradeStream>> {
+        Box::pin(async move { Ok::<_, anyhow::Error>(stream) })
+    }
+
+    fn unreachable() -> Result<TradeMsg, FeedError> {
+        Err(FeedError::Unreachable {
+            endpoint: "wss://mainnet.helius-rpc.com".to_string(),
+            attempts: 11,
+            last: "connection refused".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn falls_back_once_the_primary_gives_up() {
+        let primary = feed(vec![trade(1.0), unreachable()]);
+        let fallback = feed(vec![trade(2.0), trade(3.0)]);
+        let prices: Vec<f64> = with_fallback(primary, connects(fallback))
+            .map(|item| item.unwrap().price)
+            .collect()
+            .await;
+        assert_eq!(prices, [1.0, 2.0, 3.0]);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_error_when_the_fallback_cannot_connect() {
+        let primary = feed(vec![unreachable()]);
+        let fallback = Box::pin(async { Err::<TradeStream, _>(anyhow::anyhow!("no markets configured")) });
+        let items: Vec<_> = with_fallback(primary, fallback).collect().await;
+        assert!(matches!(items[..], [Err(FeedError::Unreachable { .. })]));
+    }
+
+    #[tokio::test]
+    async fn other_errors_do_not_fall_back() {
+        let rejected = Err(FeedError::Unauthorized {
+            endpoint: "wss://mainnet.helius-rpc.com".to_string(),
+            message: "invalid api key".to_string(),
+        });
+        let fallback = connects(feed(vec![trade(2.0)]));
+        let items: Vec<_> = with_fallback(feed(vec![rejected]), fallback).collect().await;
+        assert!(matches!(items[..], [Err(FeedError::Unauthorized { .. })]));
     }
 }
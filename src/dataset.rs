@@ -0,0 +1,86 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+/// One training sample: feature vector and its 0/1 label.
+pub type Sample = (Vec<f64>, f64);
+
+#[derive(Serialize, Deserialize)]
+struct SampleRecord {
+    features: Vec<f64>,
+    label: f64,
+}
+
+/// Append-only newline-delimited JSON file holding the training dataset.
+pub struct DatasetFile {
+    path: String,
+    /// Appends are serialized through this lock so lines never interleave.
+    file: Mutex<File>,
+}
+
+impl DatasetFile {
+    /// Open (or create) the dataset file for appending.
+    pub fn open(path: &str) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        // A crash mid-append can leave a partial last line; terminate it so the
+        // next sample starts on a fresh line (the partial one is skipped on load).
+        if file.metadata()?.len() > 0 {
+            let mut last = [0u8; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+        Ok(Self {
+            path: path.to_string(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Read every sample from disk, skipping malformed lines with a warning.
+    pub fn load(&self) -> Result<Vec<Sample>> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut samples = Vec::new();
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<SampleRecord>(&line) {
+                Ok(rec) => samples.push((rec.features, rec.label)),
+                Err(e) => log::warn!("{}:{}: skipping malformed sample: {}", self.path, idx + 1, e),
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Append a single sample as one JSON line.
+    pub fn append(&self, features: &[f64], label: f64) -> Result<()> {
+        let mut line = serde_json::to_string(&SampleRecord {
+            features: features.to_vec(),
+            label,
+        })?;
+        line.push('\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Flush appended samples through to the disk.
+    pub fn sync(&self) -> Result<()> {
+        let file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.sync_data()?;
+        Ok(())
+    }
+}
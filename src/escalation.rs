@@ -0,0 +1,114 @@
+//! Temporary widening of slippage and priority fee on markets whose swaps
+//! keep failing to land, unwound again by successful fills.
+
+use serde::Deserialize;
+
+/// Failed confirmations in a row that raise one step, by default.
+const DEFAULT_AFTER_FAILURES: u32 = 2;
+/// Slippage added per step, in bps, by default.
+const DEFAULT_STEP_BPS: u64 = 25;
+/// Slippage ceiling, in bps, by default.
+const DEFAULT_MAX_SLIPPAGE_BPS: u64 = 300;
+/// Priority fee ceiling, in lamports, by default.
+const DEFAULT_MAX_PRIORITY_FEE_LAMPORTS: u64 = 1_000_000;
+
+/// The `[slippage_escalation]` table.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EscalationConfig {
+    /// Consecutive failed or timed-out confirmations on a market that raise
+    /// its slippage and priority fee by one step. Defaults to 2
+    #[serde(default)]
+    pub after_failures: Option<u32>,
+    /// Slippage added per step, in bps. Defaults to 25
+    #[serde(default)]
+    pub step_bps: Option<u64>,
+    /// Slippage never escalates past this, in bps. Defaults to 300
+    #[serde(default)]
+    pub max_slippage_bps: Option<u64>,
+    /// Priority fee added per step, in lamports. Defaults to 0 (slippage only)
+    #[serde(default)]
+    pub priority_fee_step_lamports: Option<u64>,
+    /// Priority fee never escalates past this, in lamports. Defaults to 1000000
+    #[serde(default)]
+    pub max_priority_fee_lamports: Option<u64>,
+}
+
+/// Escalation policy with defaults filled in, around the configured
+/// baseline slippage and priority fee.
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    after_failures: u32,
+    base_slippage_bps: u64,
+    step_bps: u64,
+    max_slippage_bps: u64,
+    base_priority_fee: Option<u64>,
+    fee_step: u64,
+    max_priority_fee: u64,
+}
+
+impl Escalation {
+    pub fn new(cfg: &EscalationConfig, base_slippage_bps: u64, base_priority_fee: Option<u64>) -> Self {
+        Self {
+            after_failures: cfg.after_failures.unwrap_or(DEFAULT_AFTER_FAILURES),
+            base_slippage_bps,
+            step_bps: cfg.step_bps.unwrap_or(DEFAULT_STEP_BPS),
+            max_slippage_bps: cfg.max_slippage_bps.unwrap_or(DEFAULT_MAX_SLIPPAGE_BPS).max(base_slippage_bps),
+            base_priority_fee,
+            fee_step: cfg.priority_fee_step_lamports.unwrap_or(0),
+            max_priority_fee: cfg.max_priority_fee_lamports.unwrap_or(DEFAULT_MAX_PRIORITY_FEE_LAMPORTS),
+        }
+    }
+
+    /// Slippage tolerance after `steps` escalations.
+    pub fn slippage_bps(&self, steps: u32) -> u64 {
+        (self.base_slippage_bps + self.step_bps * steps as u64).min(self.max_slippage_bps)
+    }
+
+    /// Priority fee after `steps` escalations; `None` keeps Jupiter's default.
+    pub fn priority_fee(&self, steps: u32) -> Option<u64> {
+        if steps == 0 || self.fee_step == 0 {
+            return self.base_priority_fee;
+        }
+        let base = self.base_priority_fee.unwrap_or(0);
+        Some((base + self.fee_step * steps as u64).min(self.max_priority_fee.max(base)))
+    }
+
+    /// Whether another step would still raise the slippage or the fee.
+    fn can_escalate(&self, steps: u32) -> bool {
+        self.slippage_bps(steps + 1) > self.slippage_bps(steps)
+            || self.priority_fee(steps + 1) > self.priority_fee(steps)
+    }
+}
+
+/// Escalation of one market.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EscalationState {
+    /// Failed confirmations since the last success or escalation.
+    failures: u32,
+    /// Escalation steps currently applied.
+    pub steps: u32,
+}
+
+impl EscalationState {
+    /// Count a failed confirmation. Returns true when it raised a step.
+    pub fn on_failure(&mut self, policy: &Escalation) -> bool {
+        self.failures += 1;
+        if self.failures < policy.after_failures || !policy.can_escalate(self.steps) {
+            return false;
+        }
+        self.failures = 0;
+        self.steps += 1;
+        true
+    }
+
+    /// Count a confirmed fill, which resets the failure streak and takes one
+    /// step back towards the baseline. Returns true when it lowered a step.
+    pub fn on_success(&mut self) -> bool {
+        self.failures = 0;
+        if self.steps == 0 {
+            return false;
+        }
+        self.steps -= 1;
+        true
+    }
+}
@@ -0,0 +1,216 @@
+//! Rolling features derived from a market's recent trades, appended to the
+//! single-trade `TradeMsg::features`.
+//!
+//! Every statistic is updated incrementally on each trade, so the cost per
+//! tick does not depend on the window lengths.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+
+use crate::data::{TradeMsg, FEATURE_NAMES};
+
+/// EMA windows, in trades, used when `ema_windows` is not configured.
+pub const DEFAULT_EMA_WINDOWS: [usize; 2] = [10, 50];
+/// Volatility window, in trades, used when `volatility_window` is not configured.
+pub const DEFAULT_VOLATILITY_WINDOW: usize = 20;
+
+/// Which rolling features to compute, and which features reach the model.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeatureSpec {
+    pub ema_windows: Vec<usize>,
+    pub volatility_window: usize,
+    /// Names of the features passed to the model, in that order. Every
+    /// computed feature when `None`.
+    pub selected: Option<Vec<String>>,
+}
+
+impl Default for FeatureSpec {
+    fn default() -> Self {
+        Self {
+            ema_windows: DEFAULT_EMA_WINDOWS.to_vec(),
+            volatility_window: DEFAULT_VOLATILITY_WINDOW,
+            selected: None,
+        }
+    }
+}
+
+impl FeatureSpec {
+    /// Every feature computed, in order: the `TradeMsg::features` columns,
+    /// `ema_<window>` for each EMA, then `volatility`.
+    pub fn available(&self) -> Vec<String> {
+        FEATURE_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.ema_windows.iter().map(|w| format!("ema_{}", w)))
+            .chain(std::iter::once("volatility".to_string()))
+            .collect()
+    }
+
+    /// Names of the features in the vectors returned by
+    /// `RollingFeatures::push`, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.selected.clone().unwrap_or_else(|| self.available())
+    }
+
+    /// Length of the vectors returned by `RollingFeatures::push`.
+    pub fn width(&self) -> usize {
+        self.names().len()
+    }
+
+    /// Fail on an empty selection or one naming a feature that is not
+    /// computed, or the same feature twice.
+    pub fn check(&self) -> Result<()> {
+        let Some(selected) = &self.selected else {
+            return Ok(());
+        };
+        if selected.is_empty() {
+            return Err(anyhow!("features must not be empty; omit it to use every feature"));
+        }
+        let available = self.available();
+        let mut seen = HashSet::new();
+        for name in selected {
+            if !available.contains(name) {
+                return Err(anyhow!(
+                    "unknown feature '{}', expected one of {}",
+                    name,
+                    available.join(", ")
+                ));
+            }
+            if !seen.insert(name) {
+                return Err(anyhow!("feature '{}' is listed twice", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Positions of the selected features among the computed ones; `None`
+    /// when every feature is passed. Unknown names, rejected by `check`,
+    /// are left out.
+    fn columns(&self) -> Option<Vec<usize>> {
+        let available = self.available();
+        let selected = self.selected.as_ref()?;
+        Some(
+            selected
+                .iter()
+                .filter_map(|name| available.iter().position(|a| a == name))
+                .collect(),
+        )
+    }
+}
+
+/// Exponential moving average of the price over `window` trades.
+#[derive(Debug, Clone)]
+struct Ema {
+    window: usize,
+    value: f64,
+    /// Trades seen, up to `window`.
+    count: usize,
+}
+
+impl Ema {
+    fn update(&mut self, price: f64) -> f64 {
+        if self.count < self.window {
+            // Seed with the plain average of the first trades so early values
+            // are not dragged towards zero
+            self.count += 1;
+            self.value += (price - self.value) / self.count as f64;
+        } else {
+            self.value += 2.0 / (self.window as f64 + 1.0) * (price - self.value);
+        }
+        self.value
+    }
+}
+
+/// Standard deviation of the last `window` log returns.
+#[derive(Debug, Clone)]
+struct Volatility {
+    window: usize,
+    returns: VecDeque<f64>,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl Volatility {
+    fn update(&mut self, ret: f64) -> f64 {
+        self.returns.push_back(ret);
+        self.sum += ret;
+        self.sum_sq += ret * ret;
+        if self.returns.len() > self.window {
+            if let Some(old) = self.returns.pop_front() {
+                self.sum -= old;
+                self.sum_sq -= old * old;
+            }
+        }
+        let n = self.returns.len() as f64;
+        if n < 2.0 {
+            return 0.0;
+        }
+        let mean = self.sum / n;
+        // Rounding in the running sums can leave a tiny negative variance
+        ((self.sum_sq / n - mean * mean) * n / (n - 1.0)).max(0.0).sqrt()
+    }
+}
+
+/// Rolling feature state of one market.
+#[derive(Debug, Clone)]
+pub struct RollingFeatures {
+    emas: Vec<Ema>,
+    volatility: Volatility,
+    last_price: Option<f64>,
+    /// Last known spread, standing in for unknown ones.
+    last_spread: Option<f64>,
+    /// Computed features kept, in order; all of them when `None`.
+    columns: Option<Vec<usize>>,
+}
+
+impl RollingFeatures {
+    pub fn new(spec: &FeatureSpec) -> Self {
+        Self {
+            emas: spec
+                .ema_windows
+                .iter()
+                .map(|&window| Ema { window, value: 0.0, count: 0 })
+                .collect(),
+            volatility: Volatility {
+                window: spec.volatility_window,
+                returns: VecDeque::with_capacity(spec.volatility_window + 1),
+                sum: 0.0,
+                sum_sq: 0.0,
+            },
+            last_price: None,
+            last_spread: None,
+            columns: spec.columns(),
+        }
+    }
+
+    /// Fold `trade` into the rolling state and return its feature vector:
+    /// `TradeMsg::features`, then the relative distance of the price to each
+    /// EMA, then the volatility of log returns. With a selection in the spec
+    /// only the selected features are returned, in the selected order.
+    ///
+    /// An unknown spread is imputed with the market's last known one, so a
+    /// briefly missing book side does not read as a zero spread. A market
+    /// that never reports one gets a constant 0, which carries no signal.
+    pub fn push(&mut self, trade: &TradeMsg) -> Vec<f64> {
+        let price = trade.price;
+        if trade.spread.is_some() {
+            self.last_spread = trade.spread;
+        }
+        let mut features = trade.features(self.last_spread.unwrap_or(0.0));
+        for ema in &mut self.emas {
+            let value = ema.update(price);
+            features.push(if value > 0.0 { price / value - 1.0 } else { 0.0 });
+        }
+        let volatility = match self.last_price {
+            Some(last) if last > 0.0 && price > 0.0 => self.volatility.update((price / last).ln()),
+            _ => 0.0,
+        };
+        features.push(volatility);
+        self.last_price = Some(price);
+        match &self.columns {
+            Some(columns) => columns.iter().map(|&i| features[i]).collect(),
+            None => features,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Cost model applied to simulated fills so paper PnL matches what a live
+//! swap would have realized.
+
+use solana_sdk::signature::Signature;
+
+use crate::strategy::OrderSide;
+use crate::swap_client::TxMeta;
+
+/// Network fee of a transaction with a single signature, in lamports.
+pub const BASE_FEE_LAMPORTS: u64 = 5_000;
+
+/// Estimated costs of a paper fill, in the components `FillResult` reports
+/// for a live one.
+#[derive(Debug, Clone, Copy)]
+pub struct PaperCosts {
+    /// Expected price impact as a fraction of the notional.
+    pub impact: f64,
+    /// Network fee of the swap transaction in quote units.
+    pub network_fee: f64,
+    /// Fixed extra fee per fill in quote units (`paper_fee`).
+    pub extra_fee: f64,
+}
+
+impl PaperCosts {
+    /// Impact of crossing half the `spread` at `price`, bounded by the
+    /// `slippage_bps` a live swap would tolerate; the whole tolerance when
+    /// the spread is unknown.
+    pub fn impact(price: f64, spread: Option<f64>, slippage_bps: u64) -> f64 {
+        let max = slippage_bps as f64 / 10_000.0;
+        match spread {
+            Some(spread) if price > 0.0 => (spread / 2.0 / price).clamp(0.0, max),
+            _ => max,
+        }
+    }
+}
+
+/// Price and costs of one paper fill.
+#[derive(Debug, Clone, Copy)]
+pub struct SimFill {
+    /// Price per base unit before costs: the signal price, as a live fill is
+    /// booked at its executed price with the costs backed out.
+    pub price: f64,
+    pub network_fee: f64,
+    /// Cost of the expected price impact.
+    pub route_fee: f64,
+    pub extra_fee: f64,
+}
+
+impl SimFill {
+    /// Fees in quote units, on top of `price * amount`.
+    pub fn total_fee(&self) -> f64 {
+        self.network_fee + self.route_fee + self.extra_fee
+    }
+}
+
+/// Fill `amount` at the signal `price`, charging what a live swap would
+/// cost: its network fee and the expected price impact, plus the extra fee.
+pub fn simulate_fill(amount: f64, price: f64, costs: &PaperCosts) -> SimFill {
+    SimFill {
+        price,
+        network_fee: costs.network_fee,
+        route_fee: amount * price * costs.impact,
+        extra_fee: costs.extra_fee,
+    }
+}
+
+/// A confirmed live swap with its costs, all in quote units.
+#[derive(Debug, Clone)]
+pub struct FillResult {
+    pub signature: Signature,
+    pub side: OrderSide,
+    /// Base amount bought or sold.
+    pub amount: f64,
+    /// Executed price with the route and platform fees backed out, so that
+    /// `net()` is what the swap really moved.
+    pub price: f64,
+    /// Price the quote promised, fees included.
+    pub quoted_price: f64,
+    /// Transaction fee paid to the network.
+    pub network_fee: f64,
+    /// Cost of the route's price impact.
+    pub route_fee: f64,
+    /// Jupiter platform fee, if the route charged one.
+    pub platform_fee: f64,
+    /// Slot, block time and balance changes read back from the chain;
+    /// `None` if the transaction could not be fetched.
+    pub onchain: Option<TxMeta>,
+}
+
+impl FillResult {
+    /// Price before costs of a fill of `amount` that `executed` at a price
+    /// already paying `costs`: better than `executed` by `costs / amount`.
+    pub fn price_before_costs(side: OrderSide, amount: f64, executed: f64, costs: f64) -> f64 {
+        let per_unit = if amount > 0.0 { costs / amount } else { 0.0 };
+        match side {
+            OrderSide::Buy => executed - per_unit,
+            OrderSide::Sell => executed + per_unit,
+        }
+    }
+
+    pub fn total_fee(&self) -> f64 {
+        self.network_fee + self.route_fee + self.platform_fee
+    }
+
+    /// Cash flow of the fill before fees: negative for buys.
+    pub fn gross(&self) -> f64 {
+        match self.side {
+            OrderSide::Buy => -self.amount * self.price,
+            OrderSide::Sell => self.amount * self.price,
+        }
+    }
+
+    /// Cash flow of the fill after fees.
+    pub fn net(&self) -> f64 {
+        self.gross() - self.total_fee()
+    }
+}
@@ -1,52 +1,110 @@
 //! Streaming market data using the public Yellowstone gRPC endpoint.
 //!
-//! This first iteration keeps the API identical to the previous `LaserStream` so
-//! that the rest of the codebase requires minimal changes: it exposes a
-//! `connect()` async method that returns an `impl Stream<Item = TradeMsg>`.
+//! It exposes a `connect()` async method, like the previous `LaserStream`,
+//! returning a stream of `TradeMsg` that ends with a `FeedError` when the
+//! subscription fails for good.
 //!
 //! Under the hood we connect to `https://solana-yellowstone-grpc.publicnode.com:10000`
-//! and subscribe to **account updates** for the OpenBook Event Queue of the
-//! SOL/USDC market. Parsing the event queue into individual trades will be
-//! tackled incrementally; for now the stream simply yields the **mid-price**
-//! derived from the best bid/ask whenever the event queue changes so that the
-//! rest of the pipeline (ML, strategy, etc.) keeps working.
+//! and subscribe to **account updates** for the OpenBook event queue, bids and
+//! asks of every market listed in the config (`[[markets]]`, SOL/USDC by
+//! default). Each fill is emitted as a `TradeMsg` tagged with its market name.
+//! Markets with `source = "raydium"` subscribe to the pool's two vaults
+//! instead and emit a `TradeMsg` whenever the reserves change; `"phoenix"`
+//! markets subscribe to the market account and emit the fills inferred from
+//! its book.
+//!
+//! With `data_source = "rpc_ws"` the same accounts are followed through
+//! `accountSubscribe` on the RPC WebSocket instead and decoded by the same
+//! code; those updates carry no block time. `data_source = "laserstream"`
+//! starts on the Helius feed of `data.rs` and ends up here when it fails.
 //!
 //! Dependencies:
 //!   - yellowstone-grpc-client (async gRPC client)
 //!   - tokio-stream (wrap mpsc receiver)
 //!
-//! Future work: replace the naive mid-price extraction with proper decoding of
-//! `Event::Fill` using the `openbook-dex` crate so that we have real trade size
-//! and side information.
+//! Fills are decoded with the `openbook-dex` event queue types; the market's lot
+//! sizes are fetched once over RPC when connecting.
 
 use anyhow::{anyhow, Result};
 use byteorder::{ByteOrder, LittleEndian};
-use futures_util::{Stream, StreamExt};
+use futures_util::{SinkExt, Stream, StreamExt};
 use std::pin::Pin;
 use std::collections::HashMap;
 use solana_sdk::pubkey::Pubkey;
 use std::str::FromStr;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 
+use openbook_dex::critbit::Slab;
+use openbook_dex::matching::Side;
+use openbook_dex::state::{Event, EventView, MarketState};
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use yellowstone_grpc_proto::tonic::{Code, Status};
+use yellowstone_grpc_proto::geyser::{
+    subscribe_update, CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
+    SubscribeRequestFilterBlocksMeta, SubscribeRequestPing,
+};
 
-use yellowstone_grpc_proto::geyser::{subscribe_update, SubscribeRequest, SubscribeRequestFilterAccounts};
-
+use crate::config::{Commitment, DataSource, MarketConfig, MarketSource};
 use crate::data::TradeMsg;
+use crate::phoenix::PhoenixMarket;
+use crate::raydium::{RaydiumPool, Vault};
+
+/// Serum/OpenBook accounts are wrapped in a 5-byte head and 7-byte tail padding.
+const ACCOUNT_HEAD_PADDING: usize = 5;
+const ACCOUNT_TAIL_PADDING: usize = 7;
+/// `EventQueueHeader` is four little-endian u64: flags, head, count, seq_num.
+const EVENT_QUEUE_HEADER_LEN: usize = 32;
+/// `OrderBookStateHeader` (account flags) preceding the bids/asks slab.
+const ORDER_BOOK_HEADER_LEN: usize = 8;
+/// Price levels per side summed into the depth features by default.
+const DEFAULT_BOOK_DEPTH_LEVELS: usize = 10;
+/// First delay before reconnecting a dropped gRPC stream.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound for the exponential reconnect backoff.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Default interval between subscription pings.
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(10);
+/// HTTP/2 keepalive ping interval and the time to wait for its ack.
+const HTTP2_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const HTTP2_KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// TCP keepalive probe interval on the gRPC channel.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(30);
+/// Target slot time, used to extrapolate block times between block updates.
+const SLOT_MS: i64 = 400;
+
+/// Why the feed stopped for good; the stream yields it as its last item.
+#[derive(Debug, thiserror::Error)]
+pub enum FeedError {
+    /// The endpoint refused the credentials, which a reconnect will not fix.
+    #[error("{endpoint} rejected the subscription: {message}")]
+    Unauthorized { endpoint: String, message: String },
+    /// The configured number of sessions in a row failed before any update.
+    #[error("{attempts} connection attempts to {endpoint} failed in a row, last error: {last}")]
+    Unreachable { endpoint: String, attempts: u32, last: String },
+}
 
-/// Hard-coded SOL/USDC OpenBook **event queue** account (v1) on mainnet.
-/// NOTE: if this ever changes you can move the value to the config file.
-const SOL_USDC_EVENT_QUEUE: &str = "HxTJgEMDh8Jo6CQwwht6v7qAbKLFXrrHWEM5E9MJ4tSE";
-/// SOL/USDC bids and asks order book accounts (slab v1)
-const SOL_USDC_BIDS: &str = "9krN9TPCvQhTWZAxkVtxDC6VqeoLyzmKcqJxw5jZA7Ve";
-const SOL_USDC_ASKS: &str = "EpGvXiuQgmEYBLETymFczwa3oYuoFkyeDXovvrSM7g1D";
-/// Each price lot equals this many USDC per SOL (approx).
-const PRICE_LOT_MULT: f64 = 0.0001;
+/// Trades of the feed, ending with a `FeedError` if it gave up.
+pub type TradeStream = Pin<Box<dyn Stream<Item = Result<TradeMsg, FeedError>> + Send>>;
 
+#[derive(Clone)]
 pub struct GrpcStream {
     endpoint: String,
-    event_queue: Pubkey,
+    rpc_url: String,
+    rpc_timeout: Duration,
+    markets: Vec<MarketConfig>,
     x_token: Option<String>,
+    depth_levels: usize,
+    ping_interval: Duration,
+    commitment: Commitment,
+    data_source: DataSource,
+    ws_url: String,
+    /// Failed sessions in a row after which the feed gives up; never when unset.
+    max_failures: Option<u32>,
 }
 
 impl GrpcStream {
@@ -54,185 +112,922 @@ impl GrpcStream {
     pub fn from_config(cfg: &crate::config::BotConfig) -> Self {
         Self {
             endpoint: "https://solana-yellowstone-grpc.publicnode.com:443".to_string(),
-            event_queue: Pubkey::from_str(SOL_USDC_EVENT_QUEUE)
-                .expect("valid SOL/USDC event queue pubkey"),
+            rpc_url: cfg.anchor_cluster.clone(),
+            rpc_timeout: cfg.http_timeout(),
+            markets: cfg.markets.clone(),
             x_token: cfg.yellowstone_token.clone(),
+            depth_levels: cfg.book_depth_levels.unwrap_or(DEFAULT_BOOK_DEPTH_LEVELS),
+            ping_interval: cfg
+                .grpc_ping_interval_secs
+                .map_or(DEFAULT_PING_INTERVAL, Duration::from_secs),
+            commitment: cfg.commitment,
+            // Yellowstone is what a LaserStream setup falls back to
+            data_source: match cfg.data_source {
+                DataSource::Laserstream => DataSource::Yellowstone,
+                source => source,
+            },
+            ws_url: cfg.ws_url(),
+            max_failures: cfg.feed_max_failures,
         }
     }
 
     /// Connect and return an async stream of `TradeMsg`.
-    pub async fn connect(&self) -> Result<Pin<Box<dyn Stream<Item = TradeMsg> + Send>>> {
-        // Build the gRPC client using the updated Yellowstone builder API
-        let tls_cfg = yellowstone_grpc_client::ClientTlsConfig::new();
-        let mut builder = yellowstone_grpc_client::GeyserGrpcClient::build_from_shared(self.endpoint.clone())?
-            .tls_config(tls_cfg)?;
-        if let Some(ref token) = self.x_token {
-            builder = builder.x_token(token.clone())?;
-        }
-        let mut client = builder.connect().await?;
-
-        // Build SubscribeRequest filtering on the single event queue account.
-        let sub_req = {
-            let filter_accounts = SubscribeRequestFilterAccounts {
-                account: vec![self.event_queue.to_string()],
-                owner: vec![],
-                filters: vec![],
-                nonempty_txn_signature: Some(false),
-            };
-            let mut req = SubscribeRequest::default();
-            req.accounts = {
-                let mut map = HashMap::new();
-                map.insert("event_queue".to_string(), filter_accounts.clone());
-                // also subscribe to bids & asks for context features
-                let mut bids_filter = filter_accounts.clone();
-                bids_filter.account = vec![Pubkey::from_str(SOL_USDC_BIDS).unwrap().to_string()];
-                map.insert("bids".to_string(), bids_filter);
-                let mut asks_filter = filter_accounts;
-                asks_filter.account = vec![Pubkey::from_str(SOL_USDC_ASKS).unwrap().to_string()];
-                map.insert("asks".to_string(), asks_filter);
-                map
+    ///
+    /// The subscription runs in a background task which transparently
+    /// reconnects with exponential backoff whenever the gRPC stream errors or
+    /// ends. It only gives up when the endpoint rejects the credentials or
+    /// `feed_max_failures` sessions in a row fail without an update; the
+    /// stream then yields the `FeedError` and ends.
+    pub async fn connect(&self) -> Result<TradeStream> {
+        if self.markets.is_empty() {
+            return Err(anyhow!("no markets configured"));
+        }
+        let rpc = RpcClient::new_with_timeout_and_commitment(
+            self.rpc_url.clone(),
+            self.rpc_timeout,
+            self.commitment.config(),
+        );
+
+        // One account filter per market, plus a reverse lookup from account
+        // address to (market index, role) used to dispatch updates.
+        let mut filters = HashMap::new();
+        let mut roles = HashMap::new();
+        let mut markets = Vec::with_capacity(self.markets.len());
+        for (idx, cfg) in self.markets.iter().enumerate() {
+            let (accounts, venue) = match cfg.source {
+                MarketSource::Openbook => {
+                    let event_queue = parse_pubkey(&cfg.name, &cfg.event_queue)?;
+                    let bids = parse_pubkey(&cfg.name, &cfg.bids)?;
+                    let asks = parse_pubkey(&cfg.name, &cfg.asks)?;
+                    let info = MarketInfo::load(&rpc, cfg).await?;
+                    log::info!("Loaded market {}: {:?}", cfg.name, info);
+                    // Start from the queue's current position so the backlog is not replayed.
+                    let queue_data = rpc.get_account_data(&event_queue).await?;
+                    let last_seq = decode_queue(&queue_data, &info, u64::MAX)
+                        .ok_or_else(|| anyhow!("invalid event queue account {}", event_queue))?
+                        .seq_num;
+                    let accounts = vec![
+                        (event_queue, AccountRole::EventQueue),
+                        (bids, AccountRole::Bids),
+                        (asks, AccountRole::Asks),
+                    ];
+                    let venue = Venue::OpenBook(OpenBookStream {
+                        info,
+                        bids: None,
+                        asks: None,
+                        last_seq,
+                    });
+                    (accounts, venue)
+                }
+                MarketSource::Raydium => {
+                    let base_vault = parse_pubkey(&cfg.name, &cfg.base_vault)?;
+                    let quote_vault = parse_pubkey(&cfg.name, &cfg.quote_vault)?;
+                    let pool = RaydiumPool::load(&rpc, cfg).await?;
+                    log::info!("Loaded Raydium pool {}: {:?}", cfg.name, pool);
+                    let accounts = vec![
+                        (base_vault, AccountRole::Vault(Vault::Base)),
+                        (quote_vault, AccountRole::Vault(Vault::Quote)),
+                    ];
+                    (accounts, Venue::Raydium(pool))
+                }
+                MarketSource::Phoenix => {
+                    let market = parse_pubkey(&cfg.name, &cfg.market)?;
+                    let phoenix = PhoenixMarket::load(&rpc, cfg).await?;
+                    log::info!("Loaded Phoenix market {}: {:?}", cfg.name, phoenix);
+                    (vec![(market, AccountRole::PhoenixMarket)], Venue::Phoenix(phoenix))
+                }
             };
-            req
+            for (key, role) in &accounts {
+                roles.insert(key.to_string(), (idx, *role));
+            }
+            filters.insert(
+                cfg.name.clone(),
+                SubscribeRequestFilterAccounts {
+                    account: accounts.iter().map(|(key, _)| key.to_string()).collect(),
+                    owner: vec![],
+                    filters: vec![],
+                    nonempty_txn_signature: Some(false),
+                },
+            );
+            markets.push(MarketStream {
+                name: cfg.name.clone(),
+                venue,
+            });
+        }
+
+        let mut sub_req = SubscribeRequest::default();
+        sub_req.accounts = filters;
+        // Block metadata carries the block times used to date account updates.
+        sub_req
+            .blocks_meta
+            .insert("clock".to_string(), SubscribeRequestFilterBlocksMeta::default());
+        let level = match self.commitment {
+            Commitment::Processed => CommitmentLevel::Processed,
+            Commitment::Confirmed => CommitmentLevel::Confirmed,
+            Commitment::Finalized => CommitmentLevel::Finalized,
         };
+        sub_req.commitment = Some(level as i32);
 
         // We will forward parsed `TradeMsg` through an mpsc channel.
         let (tx, rx) = mpsc::channel::<TradeMsg>(4096);
+        let session = SessionConfig {
+            endpoint: self.endpoint.clone(),
+            x_token: self.x_token.clone(),
+            depth_levels: self.depth_levels,
+            ping_interval: self.ping_interval,
+            ws_url: self.ws_url.clone(),
+            commitment: self.commitment,
+        };
+        let source = self.data_source;
+        let max_failures = self.max_failures;
+        let (failed_tx, failed_rx) = oneshot::channel::<FeedError>();
 
-        // Spawn background task handling the gRPC stream.
+        // Spawn background task handling the stream and its reconnection.
         tokio::spawn(async move {
-            match client.subscribe_once(sub_req).await {
-                Ok(mut stream) => {
-                    // Keep running best bid/ask across updates
-                    let mut best_bid: Option<f64> = None;
-                    let mut best_ask: Option<f64> = None;
-            
-                    while let Some(update_res) = stream.next().await {
-                        match update_res {
-                            Ok(update) => {
-                                if let Some(subscribe_update::UpdateOneof::Account(acct)) = update.update_oneof {
-                                    if let Some(info) = acct.account {
-                                        let pk = acct.pubkey.clone();
-                                         if pk == self.event_queue.to_string() {
-                                             if let Some((price, size, side)) = decode_last_fill(&info.data) {
-                                                 let spread_now = if let (Some(bid), Some(ask)) = (best_bid, best_ask) { ask - bid } else { 0.0 };
-                                                 let _ = tx.send(TradeMsg {
-                                                     price,
-                                                     size,
-                                                     side: side.to_string(),
-                                                     ts: chrono::Utc::now().timestamp_millis(),
-                                                     spread: spread_now,
-                                                 }).await;
-                                                 log::info!("fill {} size {} (spread {})", price, size, spread_now);
-                                             }
-                                         } else if pk == Pubkey::from_str(SOL_USDC_BIDS).unwrap().to_string() {
-                                             if let Some(p) = decode_best_price(&info.data, true) { best_bid = Some(p); }
-                                         } else if pk == Pubkey::from_str(SOL_USDC_ASKS).unwrap().to_string() {
-                                             if let Some(p) = decode_best_price(&info.data, false) { best_ask = Some(p); }
-                                         }   }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                log::error!("gRPC stream item error: {err}");
-                            }
+            // `markets` keeps book summaries and fill cursors across reconnects
+            let mut backoff = INITIAL_BACKOFF;
+            let mut failures = 0u32;
+            let endpoint = match source {
+                DataSource::Yellowstone | DataSource::Laserstream => &session.endpoint,
+                DataSource::RpcWs => &session.ws_url,
+            };
+            loop {
+                let mut updates = 0u64;
+                let res = match source {
+                    DataSource::Yellowstone | DataSource::Laserstream => {
+                        run_session(&session, sub_req.clone(), &roles, &mut markets, &tx, &mut updates).await
+                    }
+                    DataSource::RpcWs => run_ws_session(&session, &roles, &mut markets, &tx, &mut updates).await,
+                };
+                let last = match res {
+                    Ok(()) => {
+                        log::warn!("{:?} stream ended after {} updates", source, updates);
+                        "stream ended".to_string()
+                    }
+                    Err(err) => {
+                        log::error!("{:?} stream error after {} updates: {err}", source, updates);
+                        if let Some(status) = auth_failure(&err) {
+                            let _ = failed_tx.send(FeedError::Unauthorized {
+                                endpoint: endpoint.clone(),
+                                message: status.message().to_string(),
+                            });
+                            break;
                         }
+                        format!("{:#}", err)
                     }
+                };
+                if tx.is_closed() {
+                    log::info!("TradeMsg consumer dropped; stopping {:?} stream", source);
+                    break;
+                }
+                if updates > 0 {
+                    backoff = INITIAL_BACKOFF;
+                    failures = 0;
+                } else {
+                    failures += 1;
                 }
-                Err(err) => {
-                    log::error!("gRPC subscribe_once error: {err}");
+                if max_failures.is_some_and(|max| failures >= max) {
+                    let _ = failed_tx.send(FeedError::Unreachable {
+                        endpoint: endpoint.clone(),
+                        attempts: failures,
+                        last,
+                    });
+                    break;
                 }
+                log::info!("Reconnecting to {} in {:?}", endpoint, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
             }
         });
 
-        Ok(Box::pin(ReceiverStream::new(rx)))
+        // The task's verdict, if any, follows the last trade it forwarded
+        let failure = futures_util::stream::once(failed_rx).filter_map(|res| async move { res.ok().map(Err) });
+        Ok(Box::pin(ReceiverStream::new(rx).map(Ok).chain(failure)))
     }
 }
 
-/// Very rough helper that looks at the first 16 bytes of the account to read the
-/// best bid/ask price lots and compute the mid-price. This is **NOT** precise –
-/// it’s only meant to keep the pipeline functional until we implement full
-/// `EventQueue` decoding.
-/// Decode the most recent `Fill` event in the OpenBook EventQueue and
-/// return `(price, size, side)` if at least one fill is available.
-/// We read the queue header to locate the last written node and parse it
-/// according to Serum/OpenBook layout. Errors are ignored and logged because
-/// malformed data should not bring the whole stream down.
-fn decode_last_fill(raw: &[u8]) -> Option<(f64, f64, &'static str)> {
-    // Layout constants
-    const HEADER_LEN: usize = 5 + 8 + 4 + 4 + 4 + 4; // account flags (5) + padding + head + padding + count + padding + seq + padding
-    const NODE_SIZE: usize = 88; // FillEvent size
+/// The gRPC status of an error rejecting the credentials, if it is one.
+fn auth_failure(err: &anyhow::Error) -> Option<&Status> {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<Status>())
+        .find(|status| matches!(status.code(), Code::Unauthenticated | Code::PermissionDenied))
+}
 
-    if raw.len() < HEADER_LEN {
-        return None;
+fn parse_pubkey(market: &str, key: &str) -> Result<Pubkey> {
+    Pubkey::from_str(key).map_err(|e| anyhow!("market {}: invalid account '{}': {}", market, key, e))
+}
+
+/// Role of a subscribed account within its market.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AccountRole {
+    EventQueue,
+    Bids,
+    Asks,
+    /// Reserve vault of a Raydium pool.
+    Vault(Vault),
+    PhoenixMarket,
+}
+
+/// Static market parameters needed to turn native fill quantities into prices.
+#[derive(Debug, Clone, Copy)]
+struct MarketInfo {
+    base_lot_size: u64,
+    quote_lot_size: u64,
+    base_decimals: u8,
+    quote_decimals: u8,
+}
+
+impl MarketInfo {
+    /// Fetch the market account for its lot sizes, checking that the
+    /// configured accounts and mints really belong to it.
+    async fn load(rpc: &RpcClient, cfg: &MarketConfig) -> Result<Self> {
+        let market = parse_pubkey(&cfg.name, &cfg.market)?;
+        let data = rpc.get_account_data(&market).await?;
+        let start = ACCOUNT_HEAD_PADDING;
+        let end = start + std::mem::size_of::<MarketState>();
+        if data.len() < end {
+            return Err(anyhow!("market account {} too short", market));
+        }
+        let state: MarketState = bytemuck::try_pod_read_unaligned(&data[start..end])
+            .map_err(|e| anyhow!("invalid market account {}: {:?}", market, e))?;
+
+        let expected = [
+            ("event_queue", &cfg.event_queue, state.event_q),
+            ("bids", &cfg.bids, state.bids),
+            ("asks", &cfg.asks, state.asks),
+            ("base_mint", &cfg.base_mint, state.coin_mint),
+            ("quote_mint", &cfg.quote_mint, state.pc_mint),
+        ];
+        for (field, configured, actual) in expected {
+            let actual = Pubkey::new_from_array(bytemuck::cast(actual));
+            if actual.to_string() != *configured {
+                return Err(anyhow!(
+                    "market {}: configured {} {} does not match on-chain {}",
+                    cfg.name,
+                    field,
+                    configured,
+                    actual
+                ));
+            }
+        }
+        Ok(Self {
+            base_lot_size: state.coin_lot_size,
+            quote_lot_size: state.pc_lot_size,
+            base_decimals: cfg.base_decimals,
+            quote_decimals: cfg.quote_decimals,
+        })
     }
-    // head and count are little-endian u32 located right after the account-flags (5+3 pad =8)
-    let head_off = 8;
-    let count_off = 16;
-    let head = LittleEndian::read_u32(&raw[head_off..head_off + 4]) as usize;
-    let count = LittleEndian::read_u32(&raw[count_off..count_off + 4]) as usize;
 
-    // capacity of circular buffer
-    let capacity = (raw.len() - HEADER_LEN) / NODE_SIZE;
-    if capacity == 0 || count == 0 {
-        return None;
+    /// Convert a price in quote lots per base lot into quote tokens per base token.
+    fn price_lots_to_number(&self, price_lots: f64) -> f64 {
+        price_lots * self.quote_lot_size as f64 * 10f64.powi(self.base_decimals as i32)
+            / (self.base_lot_size as f64 * 10f64.powi(self.quote_decimals as i32))
+    }
+
+    /// Convert a quantity of base lots into base tokens.
+    fn base_lots_to_number(&self, lots: f64) -> f64 {
+        lots * self.base_lot_size as f64 / 10f64.powi(self.base_decimals as i32)
+    }
+}
+
+/// A single fill decoded from the event queue.
+#[derive(Debug, Clone, Copy)]
+struct Fill {
+    price: f64,
+    size: f64,
+    side: &'static str,
+}
+
+/// Summary of the top levels of one order book side.
+#[derive(Debug, Clone, Copy)]
+struct BookSide {
+    best_price: f64,
+    /// Base-token quantity resting on the top levels.
+    volume: f64,
+}
+
+/// Per-market stream state surviving reconnects.
+#[derive(Debug)]
+struct MarketStream {
+    name: String,
+    venue: Venue,
+}
+
+#[derive(Debug)]
+enum Venue {
+    OpenBook(OpenBookStream),
+    Raydium(RaydiumPool),
+    Phoenix(PhoenixMarket),
+}
+
+#[derive(Debug)]
+struct OpenBookStream {
+    info: MarketInfo,
+    /// Latest decoded bids/asks slabs.
+    bids: Option<BookSide>,
+    asks: Option<BookSide>,
+    /// Sequence number of the newest event already processed.
+    last_seq: u64,
+}
+
+/// Connection settings shared by every session of the reconnect loop.
+struct SessionConfig {
+    endpoint: String,
+    x_token: Option<String>,
+    depth_levels: usize,
+    /// Interval of the subscription pings keeping idle streams open.
+    ping_interval: Duration,
+    /// Endpoint and commitment of the RPC WebSocket source.
+    ws_url: String,
+    commitment: Commitment,
+}
+
+/// Connect to the endpoint, subscribe with `req` and forward decoded fills
+/// into `tx` until the stream ends or errors. `updates` counts the updates
+/// received so the caller can tell a healthy session from a failed connect.
+async fn run_session(
+    session: &SessionConfig,
+    req: SubscribeRequest,
+    roles: &HashMap<String, (usize, AccountRole)>,
+    markets: &mut [MarketStream],
+    tx: &mpsc::Sender<TradeMsg>,
+    updates: &mut u64,
+) -> Result<()> {
+    // Build the gRPC client using the updated Yellowstone builder API
+    let tls_cfg = yellowstone_grpc_client::ClientTlsConfig::new();
+    let mut builder = yellowstone_grpc_client::GeyserGrpcClient::build_from_shared(session.endpoint.clone())?
+        .tls_config(tls_cfg)?
+        .tcp_keepalive(Some(TCP_KEEPALIVE))
+        .http2_keep_alive_interval(HTTP2_KEEPALIVE_INTERVAL)
+        .keep_alive_timeout(HTTP2_KEEPALIVE_TIMEOUT)
+        .keep_alive_while_idle(true);
+    if let Some(token) = &session.x_token {
+        builder = builder.x_token(token.clone())?;
     }
-    // Index of last element written
-    let last_idx = (head + count - 1) % capacity;
-    let node_off = HEADER_LEN + last_idx * NODE_SIZE;
-    if node_off + NODE_SIZE > raw.len() {
+    let mut client = builder.connect().await?;
+    let (mut sink, mut stream) = client.subscribe_with_request(Some(req)).await?;
+
+    // Quiet markets can go minutes without an update; the public endpoint
+    // drops streams that look idle, so ping on a fixed interval.
+    let mut ping = tokio::time::interval(session.ping_interval);
+    ping.tick().await;
+    let mut ping_id = 0;
+    // Latest (slot, block time in ms) seen on the stream.
+    let mut clock: Option<(u64, i64)> = None;
+    loop {
+        let update_res = tokio::select! {
+            _ = ping.tick() => {
+                ping_id += 1;
+                let req = SubscribeRequest {
+                    ping: Some(SubscribeRequestPing { id: ping_id }),
+                    ..Default::default()
+                };
+                sink.send(req).await.map_err(|e| anyhow!("failed to send ping: {}", e))?;
+                continue;
+            }
+            update_res = stream.next() => match update_res {
+                Some(update_res) => update_res,
+                None => break,
+            },
+            // The consumer dropped the stream, e.g. to force a reconnect
+            _ = tx.closed() => return Ok(()),
+        };
+        let update = update_res?;
+        *updates += 1;
+        let acct = match update.update_oneof {
+            Some(subscribe_update::UpdateOneof::Account(acct)) => acct,
+            Some(subscribe_update::UpdateOneof::BlockMeta(meta)) => {
+                if let Some(time) = meta.block_time {
+                    if clock.map_or(true, |(slot, _)| meta.slot > slot) {
+                        clock = Some((meta.slot, time.timestamp * 1000));
+                    }
+                }
+                continue;
+            }
+            Some(subscribe_update::UpdateOneof::Ping(_)) => {
+                // Server-initiated ping: answer so load balancers see traffic both ways
+                let req = SubscribeRequest {
+                    ping: Some(SubscribeRequestPing { id: ping_id }),
+                    ..Default::default()
+                };
+                sink.send(req).await.map_err(|e| anyhow!("failed to answer ping: {}", e))?;
+                continue;
+            }
+            _ => continue,
+        };
+        let event_ts = clock.map(|(slot, ms)| ms + (acct.slot as i64 - slot as i64) * SLOT_MS);
+        let Some(info) = acct.account else {
+            continue;
+        };
+        let pubkey = bs58::encode(&info.pubkey).into_string();
+        let update = AccountUpdate {
+            pubkey: &pubkey,
+            slot: acct.slot,
+            data: &info.data,
+            event_ts,
+        };
+        if !dispatch(update, roles, markets, session.depth_levels, tx).await {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Subscribe to every account in `roles` over the RPC WebSocket and forward
+/// decoded fills into `tx` until a subscription ends or the consumer goes
+/// away. Counts updates into `updates` like `run_session`.
+async fn run_ws_session(
+    session: &SessionConfig,
+    roles: &HashMap<String, (usize, AccountRole)>,
+    markets: &mut [MarketStream],
+    tx: &mpsc::Sender<TradeMsg>,
+    updates: &mut u64,
+) -> Result<()> {
+    let client = PubsubClient::new(&session.ws_url).await?;
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(session.commitment.config()),
+        ..Default::default()
+    };
+    let mut subscriptions = Vec::with_capacity(roles.len());
+    let mut unsubscribes = Vec::with_capacity(roles.len());
+    for key in roles.keys() {
+        let pubkey = Pubkey::from_str(key)?;
+        let (stream, unsubscribe) = client.account_subscribe(&pubkey, Some(config.clone())).await?;
+        subscriptions.push(stream.map(move |response| (key.as_str(), response)));
+        unsubscribes.push(unsubscribe);
+    }
+    // The server drops every subscription with the socket, so one ending
+    // means the session is over.
+    let mut stream = futures_util::stream::select_all(subscriptions);
+    loop {
+        let (pubkey, response) = tokio::select! {
+            item = stream.next() => match item {
+                Some(item) => item,
+                None => break,
+            },
+            _ = tx.closed() => break,
+        };
+        *updates += 1;
+        let Some(data) = response.value.data.decode() else {
+            log::debug!("Undecodable account data for {}", pubkey);
+            continue;
+        };
+        let update = AccountUpdate {
+            pubkey,
+            slot: response.context.slot,
+            data: &data,
+            event_ts: None,
+        };
+        if !dispatch(update, roles, markets, session.depth_levels, tx).await {
+            break;
+        }
+    }
+    drop(stream);
+    for unsubscribe in unsubscribes {
+        unsubscribe().await;
+    }
+    client.shutdown().await?;
+    Ok(())
+}
+
+/// A raw account update, whichever transport it arrived on.
+struct AccountUpdate<'a> {
+    pubkey: &'a str,
+    slot: u64,
+    data: &'a [u8],
+    /// Estimated block time of `slot` in ms, when the transport provides it.
+    event_ts: Option<i64>,
+}
+
+/// Decode `update` with the state of the market owning the account and
+/// forward the resulting trades into `tx`. Returns false once the consumer
+/// has gone away.
+async fn dispatch(
+    update: AccountUpdate<'_>,
+    roles: &HashMap<String, (usize, AccountRole)>,
+    markets: &mut [MarketStream],
+    depth_levels: usize,
+    tx: &mpsc::Sender<TradeMsg>,
+) -> bool {
+    let Some(&(idx, role)) = roles.get(update.pubkey) else {
+        return true;
+    };
+    let MarketStream { name, venue } = &mut markets[idx];
+    match (role, venue) {
+        (AccountRole::EventQueue, Venue::OpenBook(state)) => {
+            let Some(queue) = decode_queue(update.data, &state.info, state.last_seq) else {
+                return true;
+            };
+            if queue.seq_num < state.last_seq {
+                log::warn!("{}: event queue seq_num went back from {} to {}; resetting cursor", name, state.last_seq, queue.seq_num);
+                state.last_seq = queue.seq_num;
+                return true;
+            }
+            if queue.missed > 0 {
+                log::warn!("{}: {} events were consumed before being observed", name, queue.missed);
+            }
+            state.last_seq = queue.seq_num;
+            // Unknown until both book sides have been decoded
+            let spread_now = match (state.bids, state.asks) {
+                (Some(bid), Some(ask)) => Some(ask.best_price - bid.best_price),
+                _ => None,
+            };
+            let bid_volume = state.bids.map_or(0.0, |b| b.volume);
+            let ask_volume = state.asks.map_or(0.0, |a| a.volume);
+            let total_volume = bid_volume + ask_volume;
+            let imbalance = if total_volume > 0.0 { (bid_volume - ask_volume) / total_volume } else { 0.0 };
+            for fill in queue.fills {
+                if tx
+                    .send(TradeMsg {
+                        price: fill.price,
+                        size: fill.size,
+                        side: fill.side.to_string(),
+                        ts: chrono::Utc::now().timestamp_millis(),
+                        spread: spread_now,
+                        market: name.clone(),
+                        bid_volume,
+                        ask_volume,
+                        imbalance,
+                        event_ts: update.event_ts,
+                    })
+                    .await
+                    .is_err()
+                {
+                    return false;
+                }
+                log::info!("{} fill {} size {} (spread {:?})", name, fill.price, fill.size, spread_now);
+            }
+        }
+        (AccountRole::Bids, Venue::OpenBook(state)) => {
+            if let Some(side) = decode_book_side(update.data, &state.info, true, depth_levels) { state.bids = Some(side); }
+        }
+        (AccountRole::Asks, Venue::OpenBook(state)) => {
+            if let Some(side) = decode_book_side(update.data, &state.info, false, depth_levels) { state.asks = Some(side); }
+        }
+        (AccountRole::Vault(vault), Venue::Raydium(pool)) => {
+            let Some(trade) = pool.update(vault, update.slot, update.data) else {
+                return true;
+            };
+            // An AMM has no book: the spread is unknown and depth stays zero
+            let msg = TradeMsg {
+                price: trade.price,
+                size: trade.size,
+                side: trade.side.to_string(),
+                ts: chrono::Utc::now().timestamp_millis(),
+                spread: None,
+                market: name.clone(),
+                bid_volume: 0.0,
+                ask_volume: 0.0,
+                imbalance: 0.0,
+                event_ts: update.event_ts,
+            };
+            if tx.send(msg).await.is_err() {
+                return false;
+            }
+            log::info!("{} pool trade {} size {}", name, trade.price, trade.size);
+        }
+        (AccountRole::PhoenixMarket, Venue::Phoenix(market)) => {
+            let Some(book) = market.update(update.data, depth_levels) else {
+                return true;
+            };
+            let total_volume = book.bid_volume + book.ask_volume;
+            let imbalance = if total_volume > 0.0 { (book.bid_volume - book.ask_volume) / total_volume } else { 0.0 };
+            for fill in book.fills {
+                let msg = TradeMsg {
+                    price: fill.price,
+                    size: fill.size,
+                    side: fill.side.to_string(),
+                    ts: chrono::Utc::now().timestamp_millis(),
+                    spread: book.spread,
+                    market: name.clone(),
+                    bid_volume: book.bid_volume,
+                    ask_volume: book.ask_volume,
+                    imbalance,
+                    event_ts: update.event_ts,
+                };
+                if tx.send(msg).await.is_err() {
+                    return false;
+                }
+                log::info!("{} fill {} size {} (spread {:?})", name, fill.price, fill.size, book.spread);
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+/// Result of decoding an event queue snapshot.
+struct QueueSnapshot {
+    /// Fills newer than the cursor, oldest first.
+    fills: Vec<Fill>,
+    /// Total number of events ever pushed to the queue.
+    seq_num: u64,
+    /// Events pushed after the cursor but already consumed (cranked) before
+    /// this snapshot, hence lost.
+    missed: u64,
+}
+
+/// Decode every `Fill` still held in the OpenBook event queue whose sequence
+/// number is greater than `last_seq`.
+///
+/// The header's `seq_num` counts every event ever pushed, so numbering events
+/// from 1, the `count` unconsumed events starting at buffer index `head` carry
+/// sequence numbers `seq_num - count + 1 ..= seq_num`. Indices wrap around the
+/// circular buffer. Malformed data yields `None` rather than an error because
+/// it should not bring the whole stream down.
+fn decode_queue(raw: &[u8], market: &MarketInfo, last_seq: u64) -> Option<QueueSnapshot> {
+    let header_end = ACCOUNT_HEAD_PADDING + EVENT_QUEUE_HEADER_LEN;
+    if raw.len() < header_end + ACCOUNT_TAIL_PADDING {
         return None;
     }
-    let node = &raw[node_off..node_off + NODE_SIZE];
+    let header = &raw[ACCOUNT_HEAD_PADDING..header_end];
+    let head = LittleEndian::read_u64(&header[8..16]);
+    let count = LittleEndian::read_u64(&header[16..24]);
+    let seq_num = LittleEndian::read_u64(&header[24..32]);
 
-    // event_flags byte 0
-    let flags = node[0];
-    let fill_flag = flags & 0x1 != 0;
-    if !fill_flag {
+    let body = &raw[header_end..raw.len() - ACCOUNT_TAIL_PADDING];
+    let capacity = body.len() / std::mem::size_of::<Event>();
+    if capacity == 0 || count > capacity as u64 {
         return None;
     }
-    let bid_flag = flags & 0x4 != 0; // third bit
-    let side = if bid_flag { "bid" } else { "ask" };
+    let events: &[Event] = bytemuck::try_cast_slice(&body[..capacity * std::mem::size_of::<Event>()]).ok()?;
 
-    // native_quantity_paid (qty user paid) is at offset 16
-    let qty_paid = LittleEndian::read_u64(&node[16..24]) as f64;
-    // native_quantity_released at 8
-    let qty_received = LittleEndian::read_u64(&node[8..16]) as f64;
+    let oldest_seq = seq_num.checked_sub(count)? + 1;
+    let missed = oldest_seq.saturating_sub(last_seq.saturating_add(1));
+    let mut fills = Vec::new();
+    for i in 0..count {
+        let seq = oldest_seq + i;
+        if seq <= last_seq {
+            continue;
+        }
+        let idx = ((head + i) % capacity as u64) as usize;
+        if let Ok(view) = events[idx].as_view() {
+            if let Some(fill) = fill_from_view(view, market) {
+                fills.push(fill);
+            }
+        }
+    }
+    Some(QueueSnapshot { fills, seq_num, missed })
+}
 
-    // For SOL/USDC we treat qty_paid as USDC volume and qty_received as SOL size (for ask fill); need price
-    // Price lots per SOL: price = qty_paid / qty_received, fallback
-    if qty_received == 0.0 {
+/// Turn a `Fill` event into a human-readable price/size, adding back or
+/// removing fees so the price reflects the order's limit (as serum-ts does).
+fn fill_from_view(view: EventView, market: &MarketInfo) -> Option<Fill> {
+    let EventView::Fill {
+        side,
+        maker,
+        native_qty_paid,
+        native_qty_received,
+        native_fee_or_rebate,
+        ..
+    } = view
+    else {
+        return None;
+    };
+    let (base_native, quote_native, side) = match side {
+        Side::Bid => {
+            let quote = if maker {
+                native_qty_paid.checked_add(native_fee_or_rebate)?
+            } else {
+                native_qty_paid.checked_sub(native_fee_or_rebate)?
+            };
+            (native_qty_received, quote, "bid")
+        }
+        Side::Ask => {
+            let quote = if maker {
+                native_qty_received.checked_sub(native_fee_or_rebate)?
+            } else {
+                native_qty_received.checked_add(native_fee_or_rebate)?
+            };
+            (native_qty_paid, quote, "ask")
+        }
+    };
+    if base_native == 0 || market.base_lot_size == 0 || market.quote_lot_size == 0 {
         return None;
     }
-    let price = qty_paid / qty_received / 1_000_000f64; // assuming USDC has 6 decimals
-    let size = qty_received / 1_000_000f64; // SOL has 9 decimals; approximate
-    Some((price, size, side))
+    let base_lots = base_native as f64 / market.base_lot_size as f64;
+    let quote_lots = quote_native as f64 / market.quote_lot_size as f64;
+    Some(Fill {
+        price: market.price_lots_to_number(quote_lots / base_lots),
+        size: market.base_lots_to_number(base_lots),
+        side,
+    })
 }
 
-fn decode_best_price(raw: &[u8], _is_bid: bool) -> Option<f64> {
-    if raw.len() < 8 {
+/// Decode the best `levels` price levels of a bids (`is_bid`) or asks slab,
+/// returning the best price and the total base quantity resting on them.
+/// `None` for an empty or malformed book.
+fn decode_book_side(raw: &[u8], market: &MarketInfo, is_bid: bool, levels: usize) -> Option<BookSide> {
+    let start = ACCOUNT_HEAD_PADDING + ORDER_BOOK_HEADER_LEN;
+    if raw.len() < start + ACCOUNT_TAIL_PADDING + std::mem::size_of::<u64>() {
         return None;
     }
-    let price_lots = LittleEndian::read_u64(&raw[0..8]);
-    if price_lots == 0 {
-        return None;
+    // The slab is walked by popping leaves, so work on an 8-byte aligned copy.
+    let slab_bytes = &raw[start..raw.len() - ACCOUNT_TAIL_PADDING];
+    let mut words = vec![0u64; slab_bytes.len().div_ceil(8)];
+    let buf = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut words)[..slab_bytes.len()];
+    buf.copy_from_slice(slab_bytes);
+    let slab = Slab::new(buf);
+
+    let mut best_lots = None;
+    let mut last_price = None;
+    let mut seen_levels = 0;
+    let mut quantity_lots = 0u64;
+    loop {
+        let leaf = if is_bid { slab.remove_max() } else { slab.remove_min() };
+        let Some(leaf) = leaf else {
+            break;
+        };
+        let price = leaf.price().get();
+        if last_price != Some(price) {
+            seen_levels += 1;
+            if seen_levels > levels {
+                break;
+            }
+            last_price = Some(price);
+        }
+        best_lots.get_or_insert(price);
+        quantity_lots = quantity_lots.saturating_add(leaf.quantity());
     }
-    Some(price_lots as f64 * PRICE_LOT_MULT)
+
+    Some(BookSide {
+        best_price: market.price_lots_to_number(best_lots? as f64),
+        volume: market.base_lots_to_number(quantity_lots as f64),
+    })
 }
 
-fn extract_mid_price(raw: &[u8]) -> Result<f64> {
-    if raw.len() < 16 {
-        return Err(anyhow!("account data too short"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openbook_dex::fees::FeeTier;
+
+    /// Lot sizes of the SOL/USDC OpenBook market: 0.1 SOL and 0.0001 USDC.
+    const SOL_USDC: MarketInfo = MarketInfo {
+        base_lot_size: 100_000_000,
+        quote_lot_size: 100,
+        base_decimals: 9,
+        quote_decimals: 6,
+    };
+
+    fn fill(side: Side, maker: bool, paid: u64, received: u64, fee: u64) -> Event {
+        Event::new(EventView::Fill {
+            side,
+            maker,
+            native_qty_paid: paid,
+            native_qty_received: received,
+            native_fee_or_rebate: fee,
+            order_id: 0,
+            owner: [0; 4],
+            owner_slot: 0,
+            fee_tier: FeeTier::Base,
+            client_order_id: None,
+        })
+    }
+
+    fn out(side: Side) -> Event {
+        Event::new(EventView::Out {
+            side,
+            release_funds: false,
+            native_qty_unlocked: 0,
+            native_qty_still_locked: 0,
+            order_id: 0,
+            owner: [0; 4],
+            owner_slot: 0,
+            client_order_id: None,
+        })
+    }
+
+    /// Taker buy of `sol` SOL for `usdc` USDC, fee included in the amount paid.
+    fn taker_bid(sol: u64, usdc: u64) -> Event {
+        let quote = usdc * 1_000_000;
+        let fee = quote / 2_500;
+        fill(Side::Bid, false, quote + fee, sol * 1_000_000_000, fee)
+    }
+
+    /// Event queue account holding `events` from buffer index `head` on, in
+    /// a buffer of `capacity` slots, after `seq_num` events in all.
+    fn queue(capacity: usize, head: u64, seq_num: u64, events: &[Event]) -> Vec<u8> {
+        let mut slots = vec![out(Side::Bid); capacity];
+        for (i, event) in events.iter().enumerate() {
+            slots[(head as usize + i) % capacity] = *event;
+        }
+        let mut raw = b"serum".to_vec();
+        for word in [0, head, events.len() as u64, seq_num] {
+            raw.extend_from_slice(&word.to_le_bytes());
+        }
+        for slot in &slots {
+            raw.extend_from_slice(bytemuck::bytes_of(slot));
+        }
+        raw.extend_from_slice(b"padding");
+        raw
+    }
+
+    /// Account bytes of a hex fixture, skipping whitespace and `#` comments.
+    fn hex_fixture(text: &str) -> Vec<u8> {
+        let digits: String = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .flat_map(|line| line.chars().filter(|c| !c.is_whitespace()))
+            .collect();
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn assert_fill(fill: &Fill, price: f64, size: f64, side: &str) {
+        assert!((fill.price - price).abs() < 1e-9, "price {} != {}", fill.price, price);
+        assert!((fill.size - size).abs() < 1e-9, "size {} != {}", fill.size, size);
+        assert_eq!(fill.side, side);
     }
-    // For now assume little-endian u64 bid price lots followed by ask price lots.
-    let bid_lots = u64::from_le_bytes(raw[0..8].try_into()?);
-    let ask_lots = u64::from_le_bytes(raw[8..16].try_into()?);
-    if bid_lots == 0 || ask_lots == 0 {
-        return Err(anyhow!("invalid lots"));
+
+    #[test]
+    fn decodes_event_queue_fixture() {
+        let raw = hex_fixture(include_str!("../tests/fixtures/openbook_sol_usdc_event_queue.hex"));
+        assert_eq!(raw.len(), 5 + 32 + 4 * std::mem::size_of::<Event>() + 7);
+
+        // Wrapped: the maker ask in the last slot, then the taker bid in the
+        // first; the trailing Out event and the consumed slot yield nothing
+        let snapshot = decode_queue(&raw, &SOL_USDC, 1000).unwrap();
+        assert_eq!(snapshot.seq_num, 1003);
+        assert_eq!(snapshot.missed, 0);
+        assert_eq!(snapshot.fills.len(), 2);
+        assert_fill(&snapshot.fills[0], 150.25, 1.5, "ask");
+        assert_fill(&snapshot.fills[1], 150.25, 1.5, "bid");
+
+        let snapshot = decode_queue(&raw, &SOL_USDC, 1001).unwrap();
+        assert_eq!(snapshot.fills.len(), 1);
+        assert_fill(&snapshot.fills[0], 150.25, 1.5, "bid");
+        assert!(decode_queue(&raw, &SOL_USDC, 1003).unwrap().fills.is_empty());
+    }
+
+    #[test]
+    fn decodes_fills_after_cursor() {
+        let raw = queue(8, 2, 12, &[taker_bid(1, 20), taker_bid(2, 42), taker_bid(1, 21)]);
+        let snapshot = decode_queue(&raw, &SOL_USDC, 10).unwrap();
+        assert_eq!(snapshot.seq_num, 12);
+        assert_eq!(snapshot.missed, 0);
+        assert_eq!(snapshot.fills.len(), 2);
+        assert_fill(&snapshot.fills[0], 21.0, 2.0, "bid");
+        assert_fill(&snapshot.fills[1], 21.0, 1.0, "bid");
+    }
+
+    #[test]
+    fn decodes_wrapped_queue_in_order() {
+        // head + count > capacity: the newest events sit at the buffer start
+        let events = [taker_bid(1, 20), taker_bid(1, 21), taker_bid(1, 22), taker_bid(1, 23)];
+        let raw = queue(4, 2, 104, &events);
+        let snapshot = decode_queue(&raw, &SOL_USDC, 100).unwrap();
+        let prices: Vec<f64> = snapshot.fills.iter().map(|f| f.price).collect();
+        assert_eq!(prices.len(), 4);
+        for (price, expected) in prices.iter().zip([20.0, 21.0, 22.0, 23.0]) {
+            assert!((price - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn counts_events_cranked_before_snapshot() {
+        let raw = queue(8, 0, 20, &[taker_bid(1, 20)]);
+        let snapshot = decode_queue(&raw, &SOL_USDC, 15).unwrap();
+        assert_eq!(snapshot.missed, 4);
+        assert_eq!(snapshot.fills.len(), 1);
+    }
+
+    #[test]
+    fn skips_events_that_are_not_fills() {
+        let raw = queue(8, 6, 3, &[taker_bid(1, 20), out(Side::Ask)]);
+        let snapshot = decode_queue(&raw, &SOL_USDC, 0).unwrap();
+        assert_eq!(snapshot.seq_num, 3);
+        assert_eq!(snapshot.fills.len(), 1);
+        assert_fill(&snapshot.fills[0], 20.0, 1.0, "bid");
+        assert!(fill_from_view(out(Side::Ask).as_view().unwrap(), &SOL_USDC).is_none());
+    }
+
+    #[test]
+    fn rejects_count_above_capacity() {
+        let mut raw = queue(2, 0, 2, &[taker_bid(1, 20), taker_bid(1, 21)]);
+        let count = ACCOUNT_HEAD_PADDING + 16;
+        raw[count..count + 8].copy_from_slice(&3u64.to_le_bytes());
+        assert!(decode_queue(&raw, &SOL_USDC, 0).is_none());
+    }
+
+    #[test]
+    fn converts_native_amounts_with_market_decimals() {
+        // Taker sells 2.5 SOL (9 decimals) and receives 51.357 USDC (6
+        // decimals) after a 0.0205 USDC fee: 51.3775 / 2.5 = 20.551
+        let event = fill(Side::Ask, false, 2_500_000_000, 51_357_000, 20_500);
+        let fill = fill_from_view(event.as_view().unwrap(), &SOL_USDC).unwrap();
+        assert_fill(&fill, 20.551, 2.5, "ask");
+        assert!((SOL_USDC.price_lots_to_number(1.0) - 0.001).abs() < 1e-12);
+        assert!((SOL_USDC.base_lots_to_number(1.0) - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn maker_ask_adds_back_its_rebate() {
+        // A maker ask received 20 USDC plus a 0.002 USDC rebate for 1 SOL
+        let event = fill(Side::Ask, true, 1_000_000_000, 20_002_000, 2_000);
+        let fill = fill_from_view(event.as_view().unwrap(), &SOL_USDC).unwrap();
+        assert_fill(&fill, 20.0, 1.0, "ask");
     }
-    // Each lot on OpenBook SOL/USDC equals 0.0001 SOL; convert to SOL price in USDC
-    // This is a simplification; proper decoding will use `quote_lot_size/base_lot_size`.
-    let bid = bid_lots as f64 * 0.0001;
-    let ask = ask_lots as f64 * 0.0001;
-    Ok((bid + ask) / 2.0)
 }
@@ -0,0 +1,87 @@
+//! Logger setup: `env_logger` text lines, or one JSON object per line for
+//! log aggregators.
+//!
+//! Events may carry structured fields through the `log` key-value syntax
+//! (`log::info!(side:? = side, price; "...")`). JSON lines emit them as
+//! top-level keys next to `ts`, `level`, `target` and `msg`.
+
+use anyhow::{anyhow, Result};
+use chrono::{SecondsFormat, Utc};
+use env_logger::{Env, WriteStyle};
+use log::kv::{self, Key, Value, VisitSource};
+use serde_json::{Map, Value as Json};
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    /// Human-readable `env_logger` lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow!("unknown log format '{}', expected 'text' or 'json'", other)),
+        }
+    }
+}
+
+/// Install the global logger. `level` is the filter used when `RUST_LOG` is
+/// unset, in the same syntax (e.g. `info` or `info,solana_hft_bot=debug`).
+pub fn init(level: Option<&str>, format: LogFormat) {
+    let env = match level {
+        Some(level) => Env::default().default_filter_or(level),
+        None => Env::default(),
+    };
+    let mut builder = env_logger::Builder::from_env(env);
+    if format == LogFormat::Json {
+        builder.write_style(WriteStyle::Never).format(|buf, record| {
+            let mut line = Map::new();
+            line.insert(
+                "ts".into(),
+                Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into(),
+            );
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("msg".into(), record.args().to_string().into());
+            // Fields never clash with the fixed keys above
+            let mut fields = Fields(Map::new());
+            let _ = record.key_values().visit(&mut fields);
+            for (key, value) in fields.0 {
+                line.entry(key).or_insert(value);
+            }
+            writeln!(buf, "{}", Json::Object(line))
+        });
+    }
+    builder.init();
+}
+
+/// Collects an event's key-value pairs as JSON values.
+struct Fields(Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let json = if let Some(v) = value.to_bool() {
+            Json::from(v)
+        } else if let Some(v) = value.to_i64() {
+            Json::from(v)
+        } else if let Some(v) = value.to_u64() {
+            Json::from(v)
+        } else if let Some(v) = value.to_f64() {
+            // Non-finite floats have no JSON form and become null
+            Json::from(v)
+        } else {
+            Json::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), json);
+        Ok(())
+    }
+}
@@ -6,16 +6,39 @@
 //! - ML signal (logistic regression) via Linfa
 //! - On-chain interactions via Anchor client
 
+mod backtest;
+mod calibration;
 mod config;
 mod data;
+mod dataset;
+mod escalation;
+mod features;
+mod fill;
 mod grpc_stream;
+mod logging;
+mod metrics;
 mod model;
+mod onchain;
+mod performance;
+mod phoenix;
+mod quote;
+mod rate_limit;
+mod raydium;
+mod recorder;
+mod schedule;
+mod sim;
+mod state;
+mod status;
 mod strategy;
+mod train;
 mod trader;
 mod swap_client;
+mod trade_log;
 
 use anyhow::Result;
 use config::BotConfig;
+use solana_sdk::signature::Signer;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::signal;
 use trader::Trader;
@@ -26,18 +49,385 @@ struct Cli {
     /// Path to config file
     #[structopt(short, long, default_value = "bot.toml")]
     config: String,
+    /// Simulate every order instead of sending it, even on mainnet
+    #[structopt(long)]
+    dry_run: bool,
+    /// Record every incoming trade and its features to this CSV, for replay with `backtest`
+    #[structopt(long)]
+    record: Option<String>,
+    /// Log filter used when RUST_LOG is unset (e.g. "info" or "info,solana_hft_bot=debug")
+    #[structopt(long)]
+    log_level: Option<String>,
+    /// Log line format: "text" or "json"
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    log_format: logging::LogFormat,
+    #[structopt(flatten)]
+    sim: SimArgs,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Replay a CSV of recorded trades through the strategy
+    Backtest {
+        /// CSV file with `TradeMsg` columns
+        #[structopt(long)]
+        data: String,
+        /// Saved model to evaluate
+        #[structopt(long, default_value = "model.bin")]
+        model: String,
+        /// Signal probability threshold; the config's `signal_threshold` when unset
+        #[structopt(long)]
+        threshold: Option<f64>,
+        /// Probability a long exits below; the config's `exit_threshold` when unset
+        #[structopt(long)]
+        exit_threshold: Option<f64>,
+        /// Base amount per simulated order
+        #[structopt(long, default_value = "1.0")]
+        trade_amount: f64,
+        /// Amount for a fully confident signal; sizes scale up from trade_amount
+        #[structopt(long)]
+        max_trade_amount: Option<f64>,
+        /// Maximum net position; further buys are skipped
+        #[structopt(long, default_value = "10.0")]
+        max_position: f64,
+        /// Close the position once it loses this many bps
+        #[structopt(long)]
+        stop_loss_bps: Option<u64>,
+        /// Close the position once it gains this many bps
+        #[structopt(long)]
+        take_profit_bps: Option<u64>,
+        #[structopt(flatten)]
+        features: FeatureArgs,
+        /// Periods per year the Sharpe ratio is scaled to, in trades (1 = per trade)
+        #[structopt(long, default_value = "1.0")]
+        annualization: f64,
+        /// Write the full report (config, summary, closed trades) as JSON
+        #[structopt(long)]
+        out: Option<String>,
+    },
+    /// Fit a model offline from a labelled CSV and save it
+    Train {
+        /// CSV file with `TradeMsg` columns plus a 0/1 `label` column
+        #[structopt(long)]
+        data: String,
+        /// Where to write the trained model
+        #[structopt(long, default_value = "model.bin")]
+        out: String,
+        /// L2 regularization strength
+        #[structopt(long, default_value = "1.0")]
+        l2_penalty: f64,
+        /// Maximum optimizer iterations
+        #[structopt(long, default_value = "100")]
+        max_iterations: u64,
+        /// Convergence tolerance on the gradient norm
+        #[structopt(long, default_value = "1e-4")]
+        tolerance: f64,
+        /// Model to fit: "logistic" or "decision_tree"
+        #[structopt(long, default_value = "logistic", possible_values = &["logistic", "decision_tree"])]
+        model_type: model::ModelType,
+        /// Depth limit of a decision tree
+        #[structopt(long, default_value = "6")]
+        max_depth: usize,
+        /// Calibrate probabilities on the newest 20% of the data: "none", "platt" or "isotonic"
+        #[structopt(long, default_value = "none", possible_values = &["none", "platt", "isotonic"])]
+        calibration: calibration::Calibration,
+        #[structopt(flatten)]
+        features: FeatureArgs,
+    },
+    /// Validate the config file and print the settings the bot would run with, secrets redacted
+    CheckConfig,
+    /// Compare Jupiter quotes for one market across slippage settings, without trading
+    Quote {
+        /// Configured market name, e.g. "SOL/USDC"
+        #[structopt(long)]
+        symbol: String,
+        /// Base amount to buy (or sell with --sell)
+        #[structopt(long)]
+        amount: f64,
+        /// Quote a sell instead of a buy
+        #[structopt(long)]
+        sell: bool,
+        /// Slippage settings to compare, in bps, comma-separated
+        #[structopt(long, use_delimiter = true, default_value = "10,50,100,300")]
+        slippage_bps: Vec<u64>,
+    },
+}
+
+/// Synthetic feed replacing the configured one, for offline runs.
+#[derive(StructOpt, Debug)]
+struct SimArgs {
+    /// Trade on a synthetic random-walk feed in paper mode, without any network
+    #[structopt(long)]
+    sim: bool,
+    /// Mean log return per synthetic trade
+    #[structopt(long, default_value = "0.0")]
+    sim_drift: f64,
+    /// Standard deviation of the log return per synthetic trade
+    #[structopt(long, default_value = "0.001")]
+    sim_volatility: f64,
+    /// Share of the gap to the start price closed each trade; 0 for a plain random walk
+    #[structopt(long, default_value = "0.0")]
+    sim_mean_reversion: f64,
+    /// Price every synthetic market starts at
+    #[structopt(long, default_value = "100.0")]
+    sim_start_price: f64,
+    /// Seed of the generator; the same seed replays the same prices
+    #[structopt(long, default_value = "42")]
+    sim_seed: u64,
+    /// Stop after this many trades instead of running until ctrl-c
+    #[structopt(long)]
+    sim_trades: Option<u64>,
+    /// Pause between synthetic trades in ms
+    #[structopt(long, default_value = "10")]
+    sim_interval_ms: u64,
+}
+
+impl SimArgs {
+    fn params(&self) -> Result<sim::SimParams> {
+        if !(self.sim_volatility >= 0.0 && self.sim_volatility.is_finite()) || !self.sim_drift.is_finite() {
+            anyhow::bail!("--sim-drift must be finite and --sim-volatility non-negative");
+        }
+        if !(0.0..=1.0).contains(&self.sim_mean_reversion) {
+            anyhow::bail!("--sim-mean-reversion must be between 0 and 1");
+        }
+        if !(self.sim_start_price > 0.0 && self.sim_start_price.is_finite()) {
+            anyhow::bail!("--sim-start-price must be positive");
+        }
+        Ok(sim::SimParams {
+            drift: self.sim_drift,
+            volatility: self.sim_volatility,
+            mean_reversion: self.sim_mean_reversion,
+            start_price: self.sim_start_price,
+            seed: self.sim_seed,
+            trades: self.sim_trades,
+            interval: Duration::from_millis(self.sim_interval_ms),
+        })
+    }
+}
+
+/// Rolling feature settings; must match `ema_windows` and
+/// `volatility_window` in the bot config.
+#[derive(StructOpt, Debug)]
+struct FeatureArgs {
+    /// Windows of the price EMA features, comma-separated
+    #[structopt(long, use_delimiter = true, default_value = "10,50")]
+    ema_windows: Vec<usize>,
+    /// Trades over which the volatility feature is measured
+    #[structopt(long, default_value = "20")]
+    volatility_window: usize,
+    /// Features passed to the model, comma-separated names in order; all
+    /// of them by default
+    #[structopt(long, use_delimiter = true)]
+    features: Option<Vec<String>>,
+}
+
+impl FeatureArgs {
+    fn spec(self) -> Result<features::FeatureSpec> {
+        if self.ema_windows.contains(&0) || self.volatility_window < 2 {
+            anyhow::bail!("--ema-windows must be positive and --volatility-window at least 2");
+        }
+        let spec = features::FeatureSpec {
+            ema_windows: self.ema_windows,
+            volatility_window: self.volatility_window,
+            selected: self.features,
+        };
+        spec.check()?;
+        Ok(spec)
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
     let args = Cli::from_args();
-    let cfg = BotConfig::from_file(&args.config)?;
+    logging::init(args.log_level.as_deref(), args.log_format);
+
+    match args.cmd {
+        Some(Command::Backtest {
+            data,
+            model,
+            threshold,
+            exit_threshold,
+            trade_amount,
+            max_trade_amount,
+            max_position,
+            stop_loss_bps,
+            take_profit_bps,
+            features,
+            annualization,
+            out,
+        }) => {
+            if annualization <= 0.0 {
+                anyhow::bail!("--annualization must be positive");
+            }
+            // Replay the live strategy's thresholds unless overridden; the
+            // rest of the config (wallets, keys) is neither needed nor checked
+            let live = match std::path::Path::new(&args.config).exists() {
+                true => config::Thresholds::from_file(&args.config)?,
+                false => config::Thresholds::default(),
+            };
+            let threshold = threshold
+                .or(live.signal_threshold)
+                .unwrap_or(strategy::DEFAULT_SIGNAL_THRESHOLD);
+            let exit_threshold = exit_threshold.or(live.exit_threshold);
+            if !(threshold > 0.5 && threshold < 1.0) {
+                anyhow::bail!("--threshold / signal_threshold must be in (0.5, 1.0), got {}", threshold);
+            }
+            if exit_threshold.is_some_and(|exit| !(exit > 0.0 && exit <= 1.0 - threshold)) {
+                anyhow::bail!("--exit-threshold / exit_threshold must be in (0, {}]", 1.0 - threshold);
+            }
+            let params = backtest::BacktestParams {
+                data_path: &data,
+                model_path: &model,
+                threshold,
+                exit_threshold,
+                trade_amount,
+                max_trade_amount,
+                max_position,
+                stop_loss_bps,
+                take_profit_bps,
+                features: features.spec()?,
+                annualization,
+            };
+            let report = backtest::run(&params)?;
+            println!("PnL:          {:.4}", report.pnl);
+            println!("Trades:       {}", report.trades);
+            println!("Win rate:     {:.2}%", report.win_rate * 100.0);
+            println!("Max drawdown: {:.4}", report.max_drawdown);
+            println!("Return:       {:.2}%", report.total_return * 100.0);
+            println!("Sharpe:       {:.3}", report.sharpe);
+            if let Some(path) = out {
+                backtest::write_json(&report, &params, &path)?;
+                println!("Report written to {}", path);
+            }
+            return Ok(());
+        }
+        Some(Command::Train { data, out, l2_penalty, max_iterations, tolerance, model_type, max_depth, calibration, features }) => {
+            if l2_penalty < 0.0 || tolerance < 0.0 {
+                anyhow::bail!("--l2-penalty and --tolerance must be non-negative");
+            }
+            if max_depth == 0 {
+                anyhow::bail!("--max-depth must be positive");
+            }
+            let features = features.spec()?;
+            let summary = train::run(&train::TrainParams {
+                data_path: &data,
+                out_path: &out,
+                options: model::TrainOptions {
+                    model_type,
+                    l2_penalty,
+                    max_iterations,
+                    tolerance,
+                    max_depth,
+                    calibration,
+                    features: features.names(),
+                },
+                features,
+            })?;
+            let report = &summary.report;
+            let [[tn, fp], [fn_, tp]] = report.confusion;
+            println!("Model:     {:?}", model_type);
+            println!("Samples:   {}", report.samples);
+            println!("Features:  {}", summary.n_features);
+            println!("Accuracy:  {:.2}%", report.accuracy * 100.0);
+            println!("Precision: {:.2}%", report.precision * 100.0);
+            println!("Recall:    {:.2}%", report.recall * 100.0);
+            println!("Brier:     {:.4}", report.brier);
+            println!("Confusion: actual down -> {} down / {} up", tn, fp);
+            println!("           actual up   -> {} down / {} up", fn_, tp);
+            println!("Model saved to {}", out);
+            return Ok(());
+        }
+        Some(Command::CheckConfig) => {
+            // Applies the environment overrides and fails on any invalid value
+            let cfg = BotConfig::from_file(&args.config)?;
+            println!("{} is valid.", args.config);
+            for wallet in cfg.wallets()? {
+                println!("Wallet:   {}", wallet.pubkey());
+            }
+            println!("Features: {}", cfg.feature_spec().names().join(", "));
+            println!("{:#?}", cfg.redacted());
+            return Ok(());
+        }
+        Some(Command::Quote { symbol, amount, sell, slippage_bps }) => {
+            if amount <= 0.0 {
+                anyhow::bail!("--amount must be positive");
+            }
+            let cfg = BotConfig::from_file(&args.config)?;
+            let rows = quote::run(
+                &cfg,
+                &quote::QuoteParams {
+                    symbol: &symbol,
+                    amount,
+                    sell,
+                    slippage_bps: &slippage_bps,
+                },
+            )
+            .await?;
+            // Buys fix the output, so the slippage bound is a maximum input
+            let bound = if sell { "min out" } else { "max in" };
+            println!(
+                "{:>8}  {:>14}  {:>14}  {:>14}  {:>8}  {:>12}  route",
+                "slippage", "in", "out", bound, "impact", "price"
+            );
+            for row in rows {
+                match row.quote {
+                    Ok(q) => {
+                        let in_amount = q.in_amount as f64 / 10f64.powi(q.input_decimals as i32);
+                        let out_amount = q.out_amount as f64 / 10f64.powi(q.output_decimals as i32);
+                        let bound_decimals = if sell { q.output_decimals } else { q.input_decimals };
+                        let threshold = q.other_amount_threshold as f64 / 10f64.powi(bound_decimals as i32);
+                        let price = q.implied_price(sell).map_or("-".to_string(), |p| format!("{:.6}", p));
+                        println!(
+                            "{:>5} bp  {:>14.6}  {:>14.6}  {:>14.6}  {:>7.3}%  {:>12}  {}",
+                            row.slippage_bps,
+                            in_amount,
+                            out_amount,
+                            threshold,
+                            q.price_impact_pct * 100.0,
+                            price,
+                            q.route()
+                        );
+                    }
+                    Err(e) => println!("{:>5} bp  quote failed: {:#}", row.slippage_bps, e),
+                }
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let mut cfg = BotConfig::from_file(&args.config)?;
+    if args.dry_run || args.sim.sim {
+        cfg.paper_mode = Some(true);
+    }
+    if args.record.is_some() {
+        cfg.record_path = args.record;
+    }
+
+    let sim_feed = if args.sim.sim {
+        let markets = cfg.markets.iter().map(|m| m.name.clone()).collect();
+        log::warn!("*** SIM MODE: trading on a synthetic feed (seed {}) ***", args.sim.sim_seed);
+        // Synthetic positions and models must never reach the live files
+        let dir = std::env::temp_dir().join(format!("botsolana-sim-{}", std::process::id()));
+        cfg.sandbox(&dir)?;
+        log::warn!("Sim run writes its model, dataset, state and logs to {}", dir.display());
+        Some(sim::stream(args.sim.params()?, markets))
+    } else {
+        None
+    };
 
     let mut trader = Trader::new(cfg).await?;
 
+    let run = async {
+        match sim_feed {
+            Some(feed) => trader.run_on(feed).await,
+            None => trader.run().await,
+        }
+    };
     tokio::select! {
-        res = trader.run() => res?,
+        res = run => res?,
         _ = signal::ctrl_c() => {
             log::info!("Shutdown signal received");
         }
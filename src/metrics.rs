@@ -0,0 +1,198 @@
+//! Prometheus metrics and the optional HTTP server exposing them on `/metrics`.
+//! The server also hosts other read-only endpoints such as `/status`.
+
+use anyhow::Result;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter, Opts, Registry,
+    TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+pub struct Metrics {
+    registry: Registry,
+    pub trades_processed: IntCounter,
+    pub signals_generated: IntCounter,
+    pub orders_submitted: IntCounter,
+    pub orders_confirmed: IntCounter,
+    pub pnl: Gauge,
+    /// PnL of closed volume net of fees, and of the open positions marked
+    /// at the last price; together they equal the marked PnL.
+    pub realized_pnl: Gauge,
+    pub unrealized_pnl: Gauge,
+    /// Risk-adjusted ratios of per-trade returns and the marked PnL drawdown.
+    pub sharpe: Gauge,
+    pub sortino: Gauge,
+    pub max_drawdown: Gauge,
+    /// Net base-asset position, labelled by `market`.
+    pub position: GaugeVec,
+    pub model_retrains: IntCounter,
+    /// Reconnects forced because the feed went quiet.
+    pub feed_stale: IntCounter,
+    /// Feed trades dropped because the trading loop fell behind.
+    pub ticks_dropped: IntCounter,
+    /// Feed trades rejected for a NaN, infinite or non-positive value, or
+    /// whose features were not finite.
+    pub ticks_invalid: IntCounter,
+    /// Entry signals skipped because the spread exceeded `max_spread_bps`.
+    pub wide_spread_skips: IntCounter,
+    /// Jupiter calls delayed or skipped by `jupiter_rps`.
+    pub jupiter_throttled: IntCounter,
+    /// Seconds from receiving the triggering trade to sending the swap.
+    pub order_latency: Histogram,
+    /// Seconds between an event's on-chain time and its arrival.
+    pub feed_event_age: Histogram,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str| -> Result<IntCounter> {
+            let c = IntCounter::new(name, help)?;
+            registry.register(Box::new(c.clone()))?;
+            Ok(c)
+        };
+        let trades_processed = counter("bot_trades_processed_total", "Market trades received from the feed")?;
+        let signals_generated = counter("bot_signals_generated_total", "Buy/sell signals emitted by the strategy")?;
+        let orders_submitted = counter("bot_orders_submitted_total", "Swap transactions sent")?;
+        let orders_confirmed = counter("bot_orders_confirmed_total", "Swap transactions confirmed on-chain")?;
+        let model_retrains = counter("bot_model_retrains_total", "Successful model retrainings")?;
+        let feed_stale = counter("bot_feed_stale_total", "Market data feed stalls that forced a reconnect")?;
+        let ticks_dropped = counter("bot_ticks_dropped_total", "Feed trades dropped while the trading loop was busy")?;
+        let wide_spread_skips = counter("bot_wide_spread_skips_total", "Entry signals skipped for a spread above max_spread_bps")?;
+        let jupiter_throttled = counter("bot_jupiter_throttled_total", "Jupiter calls delayed or skipped by jupiter_rps")?;
+        let ticks_invalid = counter("bot_ticks_invalid_total", "Feed trades rejected for non-finite values or features")?;
+
+        let gauge = |name: &str, help: &str| -> Result<Gauge> {
+            let g = Gauge::new(name, help)?;
+            registry.register(Box::new(g.clone()))?;
+            Ok(g)
+        };
+        let pnl = gauge("bot_pnl", "Running PnL in quote units")?;
+        let realized_pnl = gauge("bot_realized_pnl", "PnL of closed position volume, net of fees")?;
+        let unrealized_pnl = gauge("bot_unrealized_pnl", "Open positions marked at the last price against their entry")?;
+        let sharpe = gauge("bot_sharpe_ratio", "Sharpe ratio of per-trade returns")?;
+        let sortino = gauge("bot_sortino_ratio", "Sortino ratio of per-trade returns")?;
+        let max_drawdown = gauge("bot_max_drawdown", "Largest decline of the marked PnL from its peak")?;
+        let position = GaugeVec::new(Opts::new("bot_position", "Net base-asset position"), &["market"])?;
+        registry.register(Box::new(position.clone()))?;
+
+        let histogram = |name: &str, help: &str, buckets: Vec<f64>| -> Result<Histogram> {
+            let h = Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))?;
+            registry.register(Box::new(h.clone()))?;
+            Ok(h)
+        };
+        let order_latency = histogram(
+            "bot_order_latency_seconds",
+            "Time from receiving a trade to submitting the order it triggered",
+            exponential_buckets(0.005, 2.0, 12)?,
+        )?;
+        let feed_event_age = histogram(
+            "bot_feed_event_age_seconds",
+            "Age of market events when received, from their block time",
+            exponential_buckets(0.1, 2.0, 10)?,
+        )?;
+
+        Ok(Self {
+            registry,
+            trades_processed,
+            signals_generated,
+            orders_submitted,
+            orders_confirmed,
+            pnl,
+            realized_pnl,
+            unrealized_pnl,
+            sharpe,
+            sortino,
+            max_drawdown,
+            position,
+            model_retrains,
+            feed_stale,
+            ticks_dropped,
+            ticks_invalid,
+            wide_spread_skips,
+            jupiter_throttled,
+            order_latency,
+            feed_event_age,
+        })
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Serves one path: returns `None` for requests it does not handle.
+pub type Handler = Arc<dyn Fn(&Request<Body>) -> Option<Response<Body>> + Send + Sync>;
+
+/// Serve requests on `addr` with the first of `handlers` that accepts them,
+/// until `shutdown` is cancelled.
+pub fn spawn_server(handlers: Vec<Handler>, addr: SocketAddr, shutdown: CancellationToken) -> JoinHandle<()> {
+    let handlers = Arc::new(handlers);
+    tokio::spawn(async move {
+        let make_svc = make_service_fn(move |_conn| {
+            let handlers = handlers.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let handlers = handlers.clone();
+                    async move { Ok::<_, Infallible>(route(&handlers, req)) }
+                }))
+            }
+        });
+        let server = match Server::try_bind(&addr) {
+            Ok(builder) => builder.serve(make_svc),
+            Err(e) => {
+                log::error!("HTTP server failed to bind {}: {}", addr, e);
+                return;
+            }
+        };
+        log::info!("HTTP server listening on http://{}", addr);
+        let graceful = server.with_graceful_shutdown(async move { shutdown.cancelled().await });
+        if let Err(e) = graceful.await {
+            log::error!("HTTP server error on {}: {}", addr, e);
+        }
+        log::info!("HTTP server on {} stopped", addr);
+    })
+}
+
+fn route(handlers: &[Handler], req: Request<Body>) -> Response<Body> {
+    handlers
+        .iter()
+        .find_map(|handler| handler(&req))
+        .unwrap_or_else(|| status_response(StatusCode::NOT_FOUND))
+}
+
+/// Handler for `/metrics`.
+pub fn handler(metrics: Arc<Metrics>) -> Handler {
+    Arc::new(move |req: &Request<Body>| {
+        (req.method() == Method::GET && req.uri().path() == "/metrics").then(|| render_response(&metrics))
+    })
+}
+
+fn render_response(metrics: &Metrics) -> Response<Body> {
+    match metrics.render() {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(body))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(e) => {
+            log::error!("Failed to render metrics: {:#}", e);
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub fn status_response(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = status;
+    resp
+}
@@ -1,50 +1,759 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
-use ndarray::{Array1, Array2};
+use ndarray::{Array1, Array2, Axis};
 use linfa::prelude::*;
 use linfa_logistic::LogisticRegression;
+use linfa_trees::{DecisionTree, TreeNode};
 use log;
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::calibration::{self, Calibration, Calibrator};
+
+/// Per-feature standardization `(x - mean) / std` fitted on the training set.
+/// Features with zero variance are passed through unscaled.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct FeatureScaler {
+    mean: Vec<f64>,
+    std: Vec<f64>,
+}
+
+impl FeatureScaler {
+    pub fn fit(x: &Array2<f64>) -> Self {
+        let mean = x
+            .mean_axis(Axis(0))
+            .map(|m| m.to_vec())
+            .unwrap_or_default();
+        let std = x.std_axis(Axis(0), 0.0).to_vec();
+        Self { mean, std }
+    }
+
+    pub fn transform(&self, features: &[f64]) -> Vec<f64> {
+        features
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| match (self.mean.get(i), self.std.get(i)) {
+                (Some(mean), Some(std)) if *std > 0.0 => (v - mean) / std,
+                _ => v,
+            })
+            .collect()
+    }
+
+    fn transform_matrix(&self, x: &mut Array2<f64>) {
+        for mut row in x.rows_mut() {
+            let scaled = self.transform(&row.to_vec());
+            row.assign(&Array1::from(scaled));
+        }
+    }
+}
+
+/// Default L2 penalty (linfa's `alpha`).
+pub const DEFAULT_L2_PENALTY: f64 = 1.0;
+/// Default cap on optimizer iterations.
+pub const DEFAULT_MAX_ITERATIONS: u64 = 100;
+/// Default gradient norm at which the optimizer stops.
+pub const DEFAULT_TOLERANCE: f64 = 1e-4;
+/// Default depth limit of a decision tree.
+pub const DEFAULT_MAX_DEPTH: usize = 6;
+/// Share of the training samples, the newest, held out to fit the calibration.
+const CALIBRATION_FRACTION: f64 = 0.2;
+/// Held-out samples below which the calibration is skipped.
+const MIN_CALIBRATION_SAMPLES: usize = 20;
+/// Seed of the weighted resampling, so a retrain on the same data gives the
+/// same model.
+const RESAMPLE_SEED: u64 = 0;
+
+/// Kind of model trained and stored in the model file.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelType {
+    #[default]
+    Logistic,
+    DecisionTree,
+}
+
+impl FromStr for ModelType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "logistic" => Ok(Self::Logistic),
+            "decision_tree" => Ok(Self::DecisionTree),
+            other => Err(anyhow!(
+                "unknown model type '{}', expected 'logistic' or 'decision_tree'",
+                other
+            )),
+        }
+    }
+}
+
+/// Training settings for `train`.
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    pub model_type: ModelType,
+    /// L2 regularization strength; higher values shrink the weights more.
+    pub l2_penalty: f64,
+    pub max_iterations: u64,
+    /// Convergence threshold on the gradient norm.
+    pub tolerance: f64,
+    /// Depth limit of a decision tree.
+    pub max_depth: usize,
+    /// Calibration fitted on the newest training samples, which the model
+    /// itself is then not trained on.
+    pub calibration: Calibration,
+    /// Names of the feature columns, saved with the model so loading it
+    /// against other features is caught. Empty to save none.
+    pub features: Vec<String>,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        Self {
+            model_type: ModelType::default(),
+            l2_penalty: DEFAULT_L2_PENALTY,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            tolerance: DEFAULT_TOLERANCE,
+            max_depth: DEFAULT_MAX_DEPTH,
+            calibration: Calibration::default(),
+            features: Vec::new(),
+        }
+    }
+}
+
+/// Classification metrics of a fitted model at a 0.5 probability cutoff.
+#[derive(Debug, Clone, Default)]
+pub struct TrainReport {
+    /// Number of evaluated samples.
+    pub samples: usize,
+    pub accuracy: f64,
+    /// Share of predicted ups that were ups. Zero if the model never predicts up.
+    pub precision: f64,
+    /// Share of actual ups predicted as ups. Zero if there are no ups.
+    pub recall: f64,
+    /// `confusion[actual][predicted]`, with class 1 meaning "up".
+    pub confusion: [[usize; 2]; 2],
+    /// Mean squared error of the predicted probabilities; lower is better
+    /// calibrated, 0.25 matches always predicting 0.5.
+    pub brier: f64,
+}
+
+impl fmt::Display for TrainReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [[tn, fp], [fn_, tp]] = self.confusion;
+        write!(
+            f,
+            "accuracy {:.3}, precision {:.3}, recall {:.3}, brier {:.4} (n={}, tn={} fp={} fn={} tp={})",
+            self.accuracy, self.precision, self.recall, self.brier, self.samples, tn, fp, fn_, tp
+        )
+    }
+}
+
+/// A model turning a feature vector into the probability of an up move.
+pub trait Predictor: Send + Sync {
+    /// Fit on the rows of `x` and their 0/1 labels `y`, replacing any
+    /// previous fit. `weights`, one per row, scale how much each row counts;
+    /// all rows count the same when `None`.
+    fn train(&mut self, x: &Array2<f64>, y: &[i32], weights: Option<&[f64]>, opts: &TrainOptions) -> Result<()>;
+
+    /// Probability in [0, 1] that the price moves up. 0.5 when untrained,
+    /// given a feature vector of the wrong width or one with a NaN or
+    /// infinite value.
+    fn predict(&self, features: &[f64]) -> f64;
+
+    /// False for a model that always predicts 0.5.
+    fn is_trained(&self) -> bool;
+
+    /// Number of features the model was trained on.
+    fn n_features(&self) -> usize;
+
+    /// Names of the features the model was trained on, in order. Empty for
+    /// models saved without them.
+    fn feature_names(&self) -> &[String] {
+        &[]
+    }
+
+    fn model_type(&self) -> ModelType;
+
+    /// Copy of the model in its stored form.
+    fn to_saved(&self) -> SavedModel;
+
+    /// Compare predictions on the rows of `x` against the 0/1 labels `y`.
+    fn evaluate(&self, x: &Array2<f64>, y: &[i32]) -> TrainReport {
+        let mut confusion = [[0usize; 2]; 2];
+        let mut probs = Vec::with_capacity(y.len());
+        for (row, &label) in x.rows().into_iter().zip(y) {
+            let prob = self.predict(&row.to_vec());
+            confusion[(label == 1) as usize][(prob >= 0.5) as usize] += 1;
+            probs.push(prob);
+        }
+        let [[tn, fp], [fn_, tp]] = confusion;
+        let samples = tn + fp + fn_ + tp;
+        let ratio = |num: usize, den: usize| if den == 0 { 0.0 } else { num as f64 / den as f64 };
+        TrainReport {
+            samples,
+            accuracy: ratio(tn + tp, samples),
+            precision: ratio(tp, tp + fp),
+            recall: ratio(tp, tp + fn_),
+            confusion,
+            brier: calibration::brier_score(&probs, y),
+        }
+    }
+
+    /// Write the model through a temporary file in the same directory,
+    /// renamed over `path` once complete, so a crash mid-write leaves the
+    /// previous model intact.
+    fn save(&self, path: &str) -> Result<()> {
+        let data = bincode::serialize(&self.to_saved())?;
+        let tmp = format!("{}.tmp", path);
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Model shared by the strategies; cloning it is cheap.
+pub type Model = Arc<dyn Predictor>;
+
+/// Contents of a model file, tagged with the model type so loading builds
+/// the right implementation.
 #[derive(Serialize, Deserialize)]
-pub struct MlModel {
-    params: Vec<f64>,
+pub enum SavedModel {
+    Logistic(LogisticModel),
+    DecisionTree(TreeModel),
+    Calibrated {
+        calibrator: Calibrator,
+        model: Box<SavedModel>,
+    },
+    Named {
+        features: Vec<String>,
+        model: Box<SavedModel>,
+    },
+}
+
+impl SavedModel {
+    fn into_predictor(self) -> Box<dyn Predictor> {
+        match self {
+            Self::Logistic(model) => Box::new(model),
+            Self::DecisionTree(model) => Box::new(model),
+            Self::Calibrated { calibrator, model } => Box::new(CalibratedModel {
+                inner: model.into_predictor(),
+                calibrator,
+            }),
+            Self::Named { features, model } => Box::new(NamedModel {
+                inner: model.into_predictor(),
+                features,
+            }),
+        }
+    }
+}
+
+/// Model used before the first training: always neutral.
+pub fn untrained() -> Model {
+    Arc::new(LogisticModel::untrained())
+}
+
+/// Fit a model of `opts.model_type` on `x`/`y`, optionally weighting each
+/// row, and report metrics on `eval` when given, otherwise on the training
+/// set itself.
+pub fn train(
+    x: Array2<f64>,
+    y: Vec<i32>,
+    weights: Option<&[f64]>,
+    eval: Option<(&Array2<f64>, &[i32])>,
+    opts: &TrainOptions,
+) -> Result<(Model, TrainReport)> {
+    let mut model: Box<dyn Predictor> = match opts.model_type {
+        ModelType::Logistic => Box::new(LogisticModel::untrained()),
+        ModelType::DecisionTree => Box::new(TreeModel::untrained()),
+    };
+    if opts.calibration != Calibration::None {
+        model = Box::new(CalibratedModel {
+            inner: model,
+            calibrator: Calibrator::Identity,
+        });
+    }
+    if let Some(weights) = weights {
+        if weights.len() != x.nrows() {
+            return Err(anyhow!("{} sample weights for {} samples", weights.len(), x.nrows()));
+        }
+    }
+    if !opts.features.is_empty() {
+        if opts.features.len() != x.ncols() {
+            return Err(anyhow!("{} feature names for {} features", opts.features.len(), x.ncols()));
+        }
+        model = Box::new(NamedModel {
+            inner: model,
+            features: opts.features.clone(),
+        });
+    }
+    model.train(&x, &y, weights, opts)?;
+    let report = match eval {
+        Some((ex, ey)) => model.evaluate(ex, ey),
+        None => model.evaluate(&x, &y),
+    };
+    Ok((model.into(), report))
+}
+
+/// Draw `x.nrows()` rows with replacement, each with probability
+/// proportional to its weight, for models whose fit takes no weights.
+fn resample(x: &Array2<f64>, y: &[i32], weights: &[f64]) -> Result<(Array2<f64>, Vec<i32>)> {
+    let dist = WeightedIndex::new(weights).map_err(|e| anyhow!("invalid sample weights: {}", e))?;
+    let mut rng = StdRng::seed_from_u64(RESAMPLE_SEED);
+    let rows: Vec<usize> = (0..x.nrows()).map(|_| dist.sample(&mut rng)).collect();
+    let labels = rows.iter().map(|&i| y[i]).collect();
+    Ok((x.select(Axis(0), &rows), labels))
+}
+
+/// Fail unless a trained `model` fits the feature `names`: the same names in
+/// the same order when it recorded them, the same count otherwise. An
+/// untrained model fits any features.
+pub fn check_features(model: &Model, names: &[String]) -> Result<()> {
+    if !model.is_trained() {
+        return Ok(());
+    }
+    let recorded = model.feature_names();
+    if !recorded.is_empty() && recorded != names {
+        return Err(anyhow!(
+            "model was trained on features [{}], configured are [{}]",
+            recorded.join(", "),
+            names.join(", ")
+        ));
+    }
+    if model.n_features() != names.len() {
+        return Err(anyhow!(
+            "model has {} features, {} are configured",
+            model.n_features(),
+            names.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Decode a model file. Files written before model types were stored hold a
+/// bare logistic model.
+fn decode(bytes: &[u8]) -> Result<Model> {
+    match bincode::deserialize::<SavedModel>(bytes) {
+        Ok(saved) => Ok(saved.into_predictor().into()),
+        Err(e) => match bincode::deserialize::<LogisticModel>(bytes) {
+            Ok(model) => Ok(Arc::new(model)),
+            Err(_) => Err(e.into()),
+        },
+    }
+}
+
+/// Read a saved model, failing on a missing or undecodable file.
+pub fn read(path: &str) -> Result<Model> {
+    decode(&fs::read(path)?)
+}
+
+/// Read a saved model for startup. A missing file, or a corrupt one
+/// (moved aside to `<path>.corrupt`), yields an untrained model so the
+/// bot can still start and retrain.
+pub fn load(path: &str) -> Result<Model> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::warn!("Model file '{}' not found. Using an untrained model until first training.", path);
+            return Ok(untrained());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match decode(&bytes) {
+        Ok(model) => Ok(model),
+        Err(e) => {
+            let backup = format!("{}.corrupt", path);
+            log::error!(
+                "Model file '{}' is corrupt ({}); moving it to '{}' and using an untrained model",
+                path,
+                e,
+                backup
+            );
+            if let Err(e) = fs::rename(path, &backup) {
+                log::error!("Failed to move corrupt model file '{}': {}", path, e);
+            }
+            Ok(untrained())
+        }
+    }
+}
+
+/// Logistic regression on standardized features.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogisticModel {
+    /// Number of features the model was trained on.
+    n_features: usize,
+    intercept: f64,
+    weights: Vec<f64>,
+    scaler: FeatureScaler,
 }
 
-impl MlModel {
-    pub fn train(x: Array2<f64>, y: Vec<i32>) -> Result<Self> {
+impl LogisticModel {
+    pub fn untrained() -> Self {
+        Self {
+            n_features: 0,
+            intercept: 0.0,
+            weights: Vec::new(),
+            scaler: FeatureScaler::default(),
+        }
+    }
+}
+
+impl Predictor for LogisticModel {
+    fn train(&mut self, x: &Array2<f64>, y: &[i32], weights: Option<&[f64]>, opts: &TrainOptions) -> Result<()> {
+        // linfa's logistic regression ignores dataset weights, so weighted
+        // rows are drawn in proportion instead
+        let (x, y) = match weights {
+            Some(weights) => resample(x, y, weights)?,
+            None => (x.clone(), y.to_vec()),
+        };
+        // Standardize features so price, size and spread share a common scale
+        let scaler = FeatureScaler::fit(&x);
+        let mut scaled = x.clone();
+        scaler.transform_matrix(&mut scaled);
         // y must be 1-D array of class labels (0/1)
-        let y = Array1::<i32>::from(y);
-        let ds = Dataset::new(x, y);
-        let model = LogisticRegression::default().fit(&ds)?;
-        let params = model.params().to_vec();
-        Ok(Self { params })
+        let ds = Dataset::new(scaled, Array1::<i32>::from(y));
+        let fitted = LogisticRegression::default()
+            .alpha(opts.l2_penalty)
+            .max_iterations(opts.max_iterations)
+            .gradient_tolerance(opts.tolerance)
+            .fit(&ds)?;
+        *self = Self {
+            n_features: x.ncols(),
+            intercept: fitted.intercept(),
+            weights: fitted.params().to_vec(),
+            scaler,
+        };
+        Ok(())
     }
 
-    pub fn predict(&self, features: &[f64]) -> f64 {
-        if self.params.is_empty() {
-            return 0.5; // Safety fallback
+    fn predict(&self, features: &[f64]) -> f64 {
+        if self.weights.is_empty() {
+            return 0.5; // Untrained model
+        }
+        if features.len() != self.n_features || self.weights.len() != self.n_features {
+            log::warn!(
+                "Model expects {} features but got {}; returning neutral prediction",
+                self.n_features,
+                features.len()
+            );
+            return 0.5;
         }
-        let (bias, weights) = self.params.split_first().unwrap();
-        let z: f64 = *bias + weights.iter().zip(features).map(|(w, x)| w * x).sum::<f64>();
-        1.0 / (1.0 + (-z).exp())
+        let features = self.scaler.transform(features);
+        let z: f64 = self.intercept + self.weights.iter().zip(&features).map(|(w, x)| w * x).sum::<f64>();
+        let prob = 1.0 / (1.0 + (-z).exp());
+        if !z.is_finite() || !prob.is_finite() {
+            log::warn!("Non-finite model output (logit {}); returning neutral prediction", z);
+            return 0.5;
+        }
+        prob
     }
 
-    pub fn save(&self, path: &str) -> Result<()> {
-        let data = bincode::serialize(self)?;
-        fs::write(path, data)?;
+    /// False for the untrained placeholder or a model with all-zero weights.
+    fn is_trained(&self) -> bool {
+        self.weights.iter().any(|w| *w != 0.0)
+    }
+
+    fn n_features(&self) -> usize {
+        self.n_features
+    }
+
+    fn model_type(&self) -> ModelType {
+        ModelType::Logistic
+    }
+
+    fn to_saved(&self) -> SavedModel {
+        SavedModel::Logistic(self.clone())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+enum Node {
+    Leaf {
+        /// Smoothed share of the training samples reaching this leaf that
+        /// were ups.
+        p_up: f64,
+    },
+    /// Rows with `features[feature] < threshold` go left.
+    Split {
+        feature: usize,
+        threshold: f64,
+        left: usize,
+        right: usize,
+    },
+}
+
+/// Decision tree over the raw features. Leaves predict the share of ups
+/// among the training samples they hold rather than a hard class, so the
+/// signal threshold keeps its meaning.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TreeModel {
+    n_features: usize,
+    /// Flattened tree, root first.
+    nodes: Vec<Node>,
+}
+
+impl TreeModel {
+    pub fn untrained() -> Self {
+        Self {
+            n_features: 0,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Copy the fitted linfa tree under `node` into `self.nodes`, returning
+    /// the index of its root.
+    fn flatten(&mut self, node: &TreeNode<f64, usize>) -> usize {
+        let idx = self.nodes.len();
+        self.nodes.push(Node::Leaf { p_up: 0.5 });
+        let children = node.children();
+        if let (false, [Some(left), Some(right)]) = (node.is_leaf(), children.as_slice()) {
+            let (feature, threshold, _) = node.split();
+            let left = self.flatten(left);
+            let right = self.flatten(right);
+            self.nodes[idx] = Node::Split { feature, threshold, left, right };
+        }
+        idx
+    }
+
+    /// Index of the leaf `features` falls into.
+    fn leaf(&self, features: &[f64]) -> usize {
+        let mut idx = 0;
+        while let Node::Split { feature, threshold, left, right } = self.nodes[idx] {
+            idx = if features[feature] < threshold { left } else { right };
+        }
+        idx
+    }
+}
+
+impl Predictor for TreeModel {
+    fn train(&mut self, x: &Array2<f64>, y: &[i32], weights: Option<&[f64]>, opts: &TrainOptions) -> Result<()> {
+        let labels: Vec<usize> = y.iter().map(|&label| (label == 1) as usize).collect();
+        let mut ds = Dataset::new(x.clone(), Array1::from(labels));
+        if let Some(weights) = weights {
+            ds = ds.with_weights(weights.iter().map(|&w| w as f32).collect());
+        }
+        let fitted = DecisionTree::params().max_depth(Some(opts.max_depth)).fit(&ds)?;
+        let root = fitted
+            .iter_nodes()
+            .next()
+            .ok_or_else(|| anyhow!("decision tree has no nodes"))?;
+        *self = Self {
+            n_features: x.ncols(),
+            nodes: Vec::new(),
+        };
+        self.flatten(root);
+        // Laplace-smoothed weighted share of ups per leaf, so small leaves
+        // stay near 0.5
+        let mut counts = vec![(0.0, 0.0); self.nodes.len()];
+        for (i, (row, &label)) in x.rows().into_iter().zip(y).enumerate() {
+            let leaf = self.leaf(&row.to_vec());
+            let weight = weights.map_or(1.0, |w| w[i]);
+            if label == 1 {
+                counts[leaf].0 += weight;
+            }
+            counts[leaf].1 += weight;
+        }
+        for (node, (ups, total)) in self.nodes.iter_mut().zip(counts) {
+            if let Node::Leaf { p_up } = node {
+                *p_up = (ups + 1.0) / (total + 2.0);
+            }
+        }
         Ok(())
     }
 
-    pub fn load(path: &str) -> Result<Self> {
-        match fs::read(path) {
-            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                log::warn!("Model file '{}' not found. Using zero weights until first training.", path);
-                Ok(Self { params: vec![0.0, 0.0, 0.0] })
+    fn predict(&self, features: &[f64]) -> f64 {
+        if self.nodes.is_empty() {
+            return 0.5; // Untrained model
+        }
+        if features.len() != self.n_features {
+            log::warn!(
+                "Model expects {} features but got {}; returning neutral prediction",
+                self.n_features,
+                features.len()
+            );
+            return 0.5;
+        }
+        // A NaN fails every split test and would always land on the right
+        if features.iter().any(|f| !f.is_finite()) {
+            log::warn!("Non-finite features; returning neutral prediction");
+            return 0.5;
+        }
+        match self.nodes[self.leaf(features)] {
+            Node::Leaf { p_up } => p_up,
+            Node::Split { .. } => 0.5,
+        }
+    }
+
+    fn is_trained(&self) -> bool {
+        !self.nodes.is_empty()
+    }
+
+    fn n_features(&self) -> usize {
+        self.n_features
+    }
+
+    fn model_type(&self) -> ModelType {
+        ModelType::DecisionTree
+    }
+
+    fn to_saved(&self) -> SavedModel {
+        SavedModel::DecisionTree(self.clone())
+    }
+}
+
+/// A model whose probabilities go through a calibrator fitted on samples it
+/// was not trained on.
+pub struct CalibratedModel {
+    inner: Box<dyn Predictor>,
+    calibrator: Calibrator,
+}
+
+impl Predictor for CalibratedModel {
+    /// Train the inner model on all but the newest `CALIBRATION_FRACTION` of
+    /// the rows and fit the calibrator on those. With too few rows to hold
+    /// out, the model is trained on everything and left uncalibrated.
+    fn train(&mut self, x: &Array2<f64>, y: &[i32], weights: Option<&[f64]>, opts: &TrainOptions) -> Result<()> {
+        let n_cal = (x.nrows() as f64 * CALIBRATION_FRACTION).round() as usize;
+        if n_cal < MIN_CALIBRATION_SAMPLES {
+            log::warn!(
+                "Only {} samples to calibrate on (need {}); training uncalibrated",
+                n_cal,
+                MIN_CALIBRATION_SAMPLES
+            );
+            self.calibrator = Calibrator::Identity;
+            return self.inner.train(x, y, weights, opts);
+        }
+        let n_fit = x.nrows() - n_cal;
+        let x_fit = x.slice(ndarray::s![..n_fit, ..]).to_owned();
+        self.inner.train(&x_fit, &y[..n_fit], weights.map(|w| &w[..n_fit]), opts)?;
+        let probs: Vec<f64> = x
+            .rows()
+            .into_iter()
+            .skip(n_fit)
+            .map(|row| self.inner.predict(&row.to_vec()))
+            .collect();
+        self.calibrator = Calibrator::fit(opts.calibration, &probs, &y[n_fit..]);
+        Ok(())
+    }
+
+    fn predict(&self, features: &[f64]) -> f64 {
+        // Keep the neutral 0.5 of an untrained model or a width mismatch
+        if !self.inner.is_trained() || features.len() != self.inner.n_features() {
+            return self.inner.predict(features);
+        }
+        self.calibrator.apply(self.inner.predict(features))
+    }
+
+    fn is_trained(&self) -> bool {
+        self.inner.is_trained()
+    }
+
+    fn n_features(&self) -> usize {
+        self.inner.n_features()
+    }
+
+    fn model_type(&self) -> ModelType {
+        self.inner.model_type()
+    }
+
+    fn to_saved(&self) -> SavedModel {
+        SavedModel::Calibrated {
+            calibrator: self.calibrator.clone(),
+            model: Box::new(self.inner.to_saved()),
+        }
+    }
+}
+
+/// A model saved with the names of its features, so loading it against
+/// other features is caught by `check_features`.
+pub struct NamedModel {
+    inner: Box<dyn Predictor>,
+    features: Vec<String>,
+}
+
+impl Predictor for NamedModel {
+    fn train(&mut self, x: &Array2<f64>, y: &[i32], weights: Option<&[f64]>, opts: &TrainOptions) -> Result<()> {
+        self.inner.train(x, y, weights, opts)
+    }
+
+    fn predict(&self, features: &[f64]) -> f64 {
+        self.inner.predict(features)
+    }
+
+    fn is_trained(&self) -> bool {
+        self.inner.is_trained()
+    }
+
+    fn n_features(&self) -> usize {
+        self.inner.n_features()
+    }
+
+    fn feature_names(&self) -> &[String] {
+        &self.features
+    }
+
+    fn model_type(&self) -> ModelType {
+        self.inner.model_type()
+    }
+
+    fn to_saved(&self) -> SavedModel {
+        SavedModel::Named {
+            features: self.features.clone(),
+            model: Box::new(self.inner.to_saved()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path in the temp dir unique to this process and `name`.
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("botsolana-{}-{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Two features; the label is the sign of the first.
+    fn separable() -> (Array2<f64>, Vec<i32>) {
+        let values: Vec<f64> = (-20..20).map(|i| i as f64 + 0.5).collect();
+        let x = Array2::from_shape_fn((values.len(), 2), |(i, j)| if j == 0 { values[i] } else { 1.0 });
+        let y = values.iter().map(|v| (*v > 0.0) as i32).collect();
+        (x, y)
+    }
+
+    #[test]
+    fn non_finite_features_predict_neutral() {
+        for model_type in [ModelType::Logistic, ModelType::DecisionTree] {
+            let (x, y) = separable();
+            let opts = TrainOptions { model_type, ..TrainOptions::default() };
+            let (model, _) = train(x, y, None, None, &opts).unwrap();
+            assert!(model.predict(&[10.0, 1.0]) > 0.5, "{:?}", model_type);
+            for bad in [[f64::NAN, 1.0], [f64::INFINITY, 1.0], [f64::NEG_INFINITY, 1.0], [1.0, f64::NAN]] {
+                assert_eq!(model.predict(&bad), 0.5, "{:?} on {:?}", model_type, bad);
             }
-            Err(e) => Err(e.into()),
         }
     }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let path = temp_path("corrupt.bin");
+        let backup = format!("{}.corrupt", path);
+        let _ = fs::remove_file(&backup);
+        fs::write(&path, b"not a model").unwrap();
+
+        let model = load(&path).unwrap();
+        assert!(!model.is_trained());
+        assert_eq!(model.predict(&[1.0, 2.0]), 0.5);
+        assert!(!std::path::Path::new(&path).exists());
+        assert_eq!(fs::read(&backup).unwrap(), b"not a model");
+        fs::remove_file(&backup).unwrap();
+    }
 }
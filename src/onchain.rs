@@ -0,0 +1,111 @@
+//! Record of confirmed fills on the Anchor program at `anchor_program_id`.
+//!
+//! The program is expected to expose a `record_fill` instruction taking
+//! `(side: u8, amount: u64, price: f64, realized_pnl: f64)` over the
+//! accounts `[pnl (writable), authority (signer, writable), system_program]`,
+//! where `pnl` is the PDA of seeds `["pnl", authority, market name]` holding
+//! the running PnL of that market.
+
+use anchor_client::solana_sdk::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_program,
+};
+use anchor_client::{Client, Cluster, Program};
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::config::BotConfig;
+use crate::strategy::OrderSide;
+
+/// Seed prefix of the per-market PnL account.
+const PNL_SEED: &[u8] = b"pnl";
+/// Anchor method name of the instruction sent after each fill.
+const RECORD_FILL: &str = "record_fill";
+
+/// A confirmed fill as recorded on-chain.
+#[derive(Debug, Clone)]
+pub struct OnchainFill {
+    pub market: String,
+    pub side: OrderSide,
+    /// Base amount in atomic units.
+    pub amount: u64,
+    pub price: f64,
+    /// Realized PnL of the market after the fill, net of fees.
+    pub realized_pnl: f64,
+}
+
+/// Client of the on-chain program, signing as the authority of its PnL
+/// accounts.
+pub struct FillRecorder {
+    program: Program<Arc<Keypair>>,
+    program_id: Pubkey,
+    authority: Pubkey,
+}
+
+impl FillRecorder {
+    /// `None` unless `record_fills_onchain` is set and `anchor_program_id`
+    /// is not empty. `authority` pays for and signs every record.
+    pub fn from_config(cfg: &BotConfig, authority: Arc<Keypair>) -> Result<Option<Self>> {
+        if !cfg.record_fills_onchain.unwrap_or(false) || cfg.anchor_program_id.is_empty() {
+            return Ok(None);
+        }
+        let program_id = Pubkey::from_str(&cfg.anchor_program_id)
+            .map_err(|e| anyhow!("anchor_program_id '{}' is not a pubkey: {}", cfg.anchor_program_id, e))?;
+        let cluster = Cluster::Custom(cfg.anchor_cluster.clone(), cfg.ws_url());
+        let owner = authority.pubkey();
+        let client = Client::new_with_options(cluster, authority, cfg.commitment.config());
+        Ok(Some(Self {
+            program: client.program(program_id)?,
+            program_id,
+            authority: owner,
+        }))
+    }
+
+    /// PnL account of `market` under this authority. `validate` keeps market
+    /// names within `MAX_SEED_LEN`, past which the derivation panics.
+    pub fn pnl_account(&self, market: &str) -> Pubkey {
+        let seeds: [&[u8]; 3] = [PNL_SEED, self.authority.as_ref(), market.as_bytes()];
+        Pubkey::find_program_address(&seeds, &self.program_id).0
+    }
+
+    /// `record_fill` instruction for `fill`: the Anchor discriminator, then
+    /// the Borsh-encoded arguments.
+    pub fn instruction(&self, fill: &OnchainFill) -> Instruction {
+        let mut data = discriminator(RECORD_FILL).to_vec();
+        data.push(match fill.side {
+            OrderSide::Buy => 0,
+            OrderSide::Sell => 1,
+        });
+        data.extend_from_slice(&fill.amount.to_le_bytes());
+        data.extend_from_slice(&fill.price.to_le_bytes());
+        data.extend_from_slice(&fill.realized_pnl.to_le_bytes());
+        Instruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(self.pnl_account(&fill.market), false),
+                AccountMeta::new(self.authority, true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data,
+        }
+    }
+
+    /// Send `fill` to the program and wait for the configured commitment.
+    pub async fn record(&self, fill: &OnchainFill) -> Result<Signature> {
+        let signature = self.program.request().instruction(self.instruction(fill)).send().await?;
+        Ok(signature)
+    }
+}
+
+/// First 8 bytes of `sha256("global:<method>")`, which Anchor uses to
+/// dispatch instructions.
+fn discriminator(method: &str) -> [u8; 8] {
+    let digest = hash(format!("global:{}", method).as_bytes());
+    let mut out = [0u8; 8];
+    out.copy_from_slice(&digest.to_bytes()[..8]);
+    out
+}
@@ -0,0 +1,65 @@
+//! Risk-adjusted performance of the live trader, updated incrementally so it
+//! stays cheap and numerically stable over long runs.
+
+/// Running Sharpe and Sortino ratios of per-trade returns, and the drawdown
+/// of the marked PnL.
+#[derive(Debug, Clone, Default)]
+pub struct Performance {
+    /// Closed trades, and the running mean and sum of squared deviations of
+    /// their returns (Welford's algorithm).
+    trades: u64,
+    mean: f64,
+    m2: f64,
+    /// Sum of squared negative returns, for the downside deviation.
+    downside_sq: f64,
+    /// Highest marked PnL seen, and the largest decline from it.
+    peak: Option<f64>,
+    max_drawdown: f64,
+}
+
+impl Performance {
+    /// Add the return of a closed trade, as a fraction of its entry notional.
+    pub fn record_return(&mut self, ret: f64) {
+        self.trades += 1;
+        let delta = ret - self.mean;
+        self.mean += delta / self.trades as f64;
+        self.m2 += delta * (ret - self.mean);
+        if ret < 0.0 {
+            self.downside_sq += ret * ret;
+        }
+    }
+
+    /// Track the peak and drawdown of the marked PnL.
+    pub fn observe_equity(&mut self, equity: f64) {
+        let peak = self.peak.map_or(equity, |peak| peak.max(equity));
+        self.peak = Some(peak);
+        self.max_drawdown = self.max_drawdown.max(peak - equity);
+    }
+
+    /// Mean over sample standard deviation of per-trade returns. Zero with
+    /// fewer than two trades or no variance.
+    pub fn sharpe(&self) -> f64 {
+        if self.trades < 2 || self.m2 <= 0.0 {
+            return 0.0;
+        }
+        self.mean / (self.m2 / (self.trades - 1) as f64).sqrt()
+    }
+
+    /// Mean over downside deviation of per-trade returns. Zero until a trade
+    /// has lost money.
+    pub fn sortino(&self) -> f64 {
+        if self.downside_sq <= 0.0 {
+            return 0.0;
+        }
+        self.mean / (self.downside_sq / self.trades as f64).sqrt()
+    }
+
+    /// Largest peak-to-trough decline of the marked PnL, in quote units.
+    pub fn max_drawdown(&self) -> f64 {
+        self.max_drawdown
+    }
+
+    pub fn trades(&self) -> u64 {
+        self.trades
+    }
+}
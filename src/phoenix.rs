@@ -0,0 +1,174 @@
+//! Phoenix order book markets, decoded with the `phoenix-v1` account layout.
+//!
+//! Phoenix publishes fills only as instruction logs; the market account holds
+//! the resting book. Fills are therefore inferred by diffing consecutive book
+//! snapshots: size that disappears from levels at or better than the new
+//! best price was taken. Cancels at the top of the book are indistinguishable
+//! from fills and show up as trades too.
+
+use anyhow::{anyhow, Result};
+use phoenix::program::{load_with_dispatch, MarketHeader};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+use crate::config::MarketConfig;
+
+/// One aggregated price level, in quote per base and base tokens.
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    price: f64,
+    size: f64,
+}
+
+/// Top levels of both book sides, best price first.
+#[derive(Debug, Clone, Default)]
+struct Book {
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+}
+
+/// Fill inferred from a book update.
+#[derive(Debug, Clone, Copy)]
+pub struct BookFill {
+    /// Volume-weighted price of the consumed levels.
+    pub price: f64,
+    pub size: f64,
+    /// Taker side: "bid" when asks were lifted, "ask" when bids were hit.
+    pub side: &'static str,
+}
+
+/// Fills and book summary produced by one market account update.
+#[derive(Debug, Clone, Default)]
+pub struct BookUpdate {
+    pub fills: Vec<BookFill>,
+    /// Best ask minus best bid, `None` when a side is empty.
+    pub spread: Option<f64>,
+    /// Base quantity on the top levels of each side.
+    pub bid_volume: f64,
+    pub ask_volume: f64,
+}
+
+#[derive(Debug)]
+pub struct PhoenixMarket {
+    /// Quote atoms per base unit for one price tick.
+    tick_size: u64,
+    raw_base_units_per_base_unit: u32,
+    /// Base atoms per base lot.
+    base_lot_size: u64,
+    base_decimals: u8,
+    quote_decimals: u8,
+    book: Option<Book>,
+}
+
+impl PhoenixMarket {
+    /// Read the market header, checking the configured mints against it.
+    pub async fn load(rpc: &RpcClient, cfg: &MarketConfig) -> Result<Self> {
+        let market = Pubkey::from_str(&cfg.market)
+            .map_err(|e| anyhow!("market {}: invalid account '{}': {}", cfg.name, cfg.market, e))?;
+        let data = rpc.get_account_data(&market).await?;
+        let header = read_header(&data).ok_or_else(|| anyhow!("invalid Phoenix market account {}", market))?;
+        let expected = [
+            ("base_mint", &cfg.base_mint, header.base_params.mint_key),
+            ("quote_mint", &cfg.quote_mint, header.quote_params.mint_key),
+        ];
+        for (field, configured, actual) in expected {
+            if actual.to_string() != *configured {
+                return Err(anyhow!(
+                    "market {}: configured {} {} does not match on-chain {}",
+                    cfg.name,
+                    field,
+                    configured,
+                    actual
+                ));
+            }
+        }
+        Ok(Self {
+            tick_size: header.get_tick_size_in_quote_atoms_per_base_unit().as_u64(),
+            raw_base_units_per_base_unit: header.raw_base_units_per_base_unit,
+            base_lot_size: header.get_base_lot_size().as_u64(),
+            base_decimals: header.base_params.decimals as u8,
+            quote_decimals: header.quote_params.decimals as u8,
+            book: None,
+        })
+    }
+
+    /// Decode a market account update, summing the best `levels` levels per
+    /// side. `None` for malformed data.
+    pub fn update(&mut self, raw: &[u8], levels: usize) -> Option<BookUpdate> {
+        let book = self.decode_book(raw, levels)?;
+        let mut update = BookUpdate {
+            spread: match (book.bids.first(), book.asks.first()) {
+                (Some(bid), Some(ask)) => Some(ask.price - bid.price),
+                _ => None,
+            },
+            bid_volume: book.bids.iter().map(|l| l.size).sum(),
+            ask_volume: book.asks.iter().map(|l| l.size).sum(),
+            fills: Vec::new(),
+        };
+        if let Some(prev) = &self.book {
+            update.fills.extend(consumed(&prev.asks, &book.asks, false));
+            update.fills.extend(consumed(&prev.bids, &book.bids, true));
+        }
+        self.book = Some(book);
+        Some(update)
+    }
+
+    fn decode_book(&self, raw: &[u8], levels: usize) -> Option<Book> {
+        let header_len = std::mem::size_of::<MarketHeader>();
+        let header = read_header(raw)?;
+        // The market is read through zero-copy casts, so work on an 8-byte aligned copy.
+        let body = raw.get(header_len..)?;
+        let mut words = vec![0u64; body.len().div_ceil(8)];
+        let buf = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut words)[..body.len()];
+        buf.copy_from_slice(body);
+        let market = load_with_dispatch(&header.market_size_params, buf).ok()?.inner;
+        let ladder = market.get_ladder(levels as u64);
+        let level = |price_in_ticks: u64, size_in_base_lots: u64| Level {
+            price: self.ticks_to_price(price_in_ticks),
+            size: self.lots_to_size(size_in_base_lots),
+        };
+        Some(Book {
+            bids: ladder.bids.iter().map(|o| level(o.price_in_ticks, o.size_in_base_lots)).collect(),
+            asks: ladder.asks.iter().map(|o| level(o.price_in_ticks, o.size_in_base_lots)).collect(),
+        })
+    }
+
+    fn ticks_to_price(&self, ticks: u64) -> f64 {
+        ticks as f64 * self.tick_size as f64
+            / (10f64.powi(self.quote_decimals as i32) * self.raw_base_units_per_base_unit as f64)
+    }
+
+    fn lots_to_size(&self, lots: u64) -> f64 {
+        lots as f64 * self.base_lot_size as f64 / 10f64.powi(self.base_decimals as i32)
+    }
+}
+
+fn read_header(raw: &[u8]) -> Option<MarketHeader> {
+    let header = raw.get(..std::mem::size_of::<MarketHeader>())?;
+    bytemuck::try_pod_read_unaligned(header).ok()
+}
+
+/// Size taken from one book side between snapshots `prev` and `now`: every
+/// level better than the new best price, plus the shrinkage of the level at
+/// it. `is_bid` selects the side's price ordering.
+fn consumed(prev: &[Level], now: &[Level], is_bid: bool) -> Option<BookFill> {
+    let best = now.first();
+    let mut size = 0.0;
+    let mut notional = 0.0;
+    for level in prev {
+        let taken = match best {
+            None => level.size,
+            Some(b) if (is_bid && level.price > b.price) || (!is_bid && level.price < b.price) => level.size,
+            Some(b) if level.price == b.price => (level.size - b.size).max(0.0),
+            Some(_) => break,
+        };
+        size += taken;
+        notional += taken * level.price;
+    }
+    (size > 0.0).then(|| BookFill {
+        price: notional / size,
+        size,
+        side: if is_bid { "ask" } else { "bid" },
+    })
+}
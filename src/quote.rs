@@ -0,0 +1,55 @@
+//! Side-by-side Jupiter quotes for one market at several slippage settings,
+//! to tune `slippage_bps` and the liquidity limits before trading. Nothing
+//! is signed or sent.
+
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use std::sync::Arc;
+
+use crate::config::BotConfig;
+use crate::swap_client::{Quote, SwapClient, SwapMode};
+
+pub struct QuoteParams<'a> {
+    /// Market name as configured, e.g. "SOL/USDC".
+    pub symbol: &'a str,
+    /// Base amount bought or sold.
+    pub amount: f64,
+    pub sell: bool,
+    pub slippage_bps: &'a [u64],
+}
+
+/// Quote of one slippage setting, or why it could not be fetched.
+pub struct QuoteRow {
+    pub slippage_bps: u64,
+    pub quote: Result<Quote>,
+}
+
+/// Fetch a quote per slippage setting the way the trader would for an order
+/// of `amount`: buys fix the base amount received, sells the base amount
+/// spent.
+pub async fn run(cfg: &BotConfig, params: &QuoteParams<'_>) -> Result<Vec<QuoteRow>> {
+    let market = cfg
+        .markets
+        .iter()
+        .find(|m| m.name == params.symbol)
+        .ok_or_else(|| {
+            let names: Vec<&str> = cfg.markets.iter().map(|m| m.name.as_str()).collect();
+            anyhow!("unknown market '{}', configured: {}", params.symbol, names.join(", "))
+        })?;
+    // The RPC client is only used to send swaps, never here
+    let rpc = Arc::new(RpcClient::new_with_timeout_and_commitment(
+        cfg.anchor_cluster.clone(),
+        cfg.http_timeout(),
+        cfg.commitment.config(),
+    ));
+    let swap_client = SwapClient::from_config(cfg, rpc)?;
+    let mode = if params.sell { SwapMode::ExactIn } else { SwapMode::ExactOut };
+    let mut rows = Vec::with_capacity(params.slippage_bps.len());
+    for &slippage_bps in params.slippage_bps {
+        let quote = swap_client
+            .quote(market, params.amount, Some(params.sell), mode, slippage_bps)
+            .await;
+        rows.push(QuoteRow { slippage_bps, quote });
+    }
+    Ok(rows)
+}
@@ -0,0 +1,65 @@
+//! Client-side token bucket keeping Jupiter calls under the API's request
+//! rate.
+
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What a call does when the bucket is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleMode {
+    /// Wait for a token, up to the request timeout.
+    #[default]
+    Wait,
+    /// Fail the call at once.
+    Skip,
+}
+
+/// Token bucket refilled at `rate` tokens per second, holding up to one
+/// second's worth (at least one token) for bursts.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    /// Tokens left, negative while waiters hold reservations, and when they
+    /// were last counted.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        let burst = rate.max(1.0);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Tokens added per second.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Reserve a token and return how long to wait before using it: zero
+    /// when one is available. `None`, reserving nothing, when the wait would
+    /// exceed `max_wait`. Waiters are served in the order they reserved.
+    pub fn reserve(&self, max_wait: Duration) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, counted) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*counted).as_secs_f64() * self.rate).min(self.burst);
+        *counted = now;
+        let wait = if *tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+        };
+        if wait > max_wait {
+            return None;
+        }
+        *tokens -= 1.0;
+        Some(wait)
+    }
+}
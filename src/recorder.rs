@@ -0,0 +1,139 @@
+//! Capture of the live feed to a CSV the `backtest` and `train` subcommands
+//! read directly.
+//!
+//! Rows carry the `TradeMsg` columns followed by the features the trader
+//! computed (`feature_0`, `feature_1`, ...), which the CSV reader ignores.
+//! Writes happen on a blocking thread behind a bounded queue so the trading
+//! loop never waits on the disk.
+
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::data::TradeMsg;
+
+/// Rows buffered for the writer thread; beyond that rows are dropped.
+const RECORD_QUEUE: usize = 8192;
+
+const TRADE_COLUMNS: [&str; 10] = [
+    "price",
+    "size",
+    "side",
+    "ts",
+    "spread",
+    "market",
+    "bid_volume",
+    "ask_volume",
+    "imbalance",
+    "event_ts",
+];
+
+pub struct Recorder {
+    tx: mpsc::Sender<(TradeMsg, Vec<f64>)>,
+    task: JoinHandle<()>,
+    /// Rows dropped because the writer fell behind.
+    dropped: u64,
+}
+
+impl Recorder {
+    /// Create (or truncate) `path` and start the writer thread.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)?;
+        let (tx, mut rx) = mpsc::channel::<(TradeMsg, Vec<f64>)>(RECORD_QUEUE);
+        let path = path.to_string();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(BufWriter::new(file));
+            let mut header_written = false;
+            while let Some((trade, features)) = rx.blocking_recv() {
+                if !header_written {
+                    let mut header: Vec<String> = TRADE_COLUMNS.iter().map(|c| c.to_string()).collect();
+                    header.extend((0..features.len()).map(|i| format!("feature_{}", i)));
+                    if let Err(e) = writer.write_record(&header) {
+                        log::error!("Failed to write {}: {}; recording stopped", path, e);
+                        return;
+                    }
+                    header_written = true;
+                }
+                if let Err(e) = writer.write_record(row(&trade, &features)) {
+                    log::error!("Failed to write {}: {}; recording stopped", path, e);
+                    return;
+                }
+                // Flush whenever the queue drains so little is lost on a crash
+                if rx.is_empty() {
+                    if let Err(e) = writer.flush() {
+                        log::error!("Failed to flush {}: {}", path, e);
+                    }
+                }
+            }
+            if let Err(e) = writer.flush() {
+                log::error!("Failed to flush {}: {}", path, e);
+            }
+        });
+        Ok(Self { tx, task, dropped: 0 })
+    }
+
+    /// Queue a trade and its features for writing.
+    pub fn record(&mut self, trade: &TradeMsg, features: &[f64]) {
+        if self.tx.try_send((trade.clone(), features.to_vec())).is_err() {
+            self.dropped += 1;
+        }
+    }
+
+    /// Write out the queued rows and close the file.
+    pub async fn finish(self) {
+        if self.dropped > 0 {
+            log::warn!("{} trades could not be recorded: the writer fell behind", self.dropped);
+        }
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+}
+
+/// Read back a recording: each trade with the features recorded for it.
+pub fn read(path: &str) -> Result<Vec<(TradeMsg, Vec<f64>)>> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let feature_cols: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| h.starts_with("feature_"))
+        .map(|(i, _)| i)
+        .collect();
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let trade: TradeMsg = record.deserialize(Some(&headers))?;
+        let features = feature_cols
+            .iter()
+            .map(|&col| {
+                let value = record.get(col).unwrap_or_default();
+                value
+                    .parse::<f64>()
+                    .map_err(|e| anyhow!("{}:{}: invalid feature '{}': {}", path, i + 2, value, e))
+            })
+            .collect::<Result<Vec<f64>>>()?;
+        rows.push((trade, features));
+    }
+    Ok(rows)
+}
+
+fn row(trade: &TradeMsg, features: &[f64]) -> Vec<String> {
+    let mut row = vec![
+        trade.price.to_string(),
+        trade.size.to_string(),
+        trade.side.clone(),
+        trade.ts.to_string(),
+        trade.spread.map(|spread| spread.to_string()).unwrap_or_default(),
+        trade.market.clone(),
+        trade.bid_volume.to_string(),
+        trade.ask_volume.to_string(),
+        trade.imbalance.to_string(),
+        trade.event_ts.map(|ts| ts.to_string()).unwrap_or_default(),
+    ];
+    row.extend(features.iter().map(f64::to_string));
+    row
+}
@@ -0,0 +1,54 @@
+//! UTC time windows during which orders may be sent.
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Deserializer};
+
+/// One `[[trading_hours]]` window, e.g. `{ start = "13:30", end = "20:00",
+/// days = ["mon", "tue"] }`. An `end` before `start` wraps past midnight; the
+/// window then belongs to the day it starts on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradingWindow {
+    #[serde(deserialize_with = "time_of_day")]
+    pub start: NaiveTime,
+    /// Exclusive. Equal to `start` for a window covering the whole day.
+    #[serde(deserialize_with = "time_of_day")]
+    pub end: NaiveTime,
+    /// Weekdays the window opens on. Every day when unset.
+    #[serde(default)]
+    pub days: Option<Vec<Weekday>>,
+}
+
+impl TradingWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        let today = now.weekday();
+        if self.start < self.end {
+            self.opens_on(today) && time >= self.start && time < self.end
+        } else if self.start > self.end {
+            // Before `end` we are in the window opened yesterday
+            (self.opens_on(today) && time >= self.start) || (self.opens_on(today.pred()) && time < self.end)
+        } else {
+            self.opens_on(today)
+        }
+    }
+
+    fn opens_on(&self, day: Weekday) -> bool {
+        match &self.days {
+            Some(days) => days.contains(&day),
+            None => true,
+        }
+    }
+}
+
+/// Whether any window of `windows` contains `now`; no windows means always.
+pub fn is_open(windows: &[TradingWindow], now: DateTime<Utc>) -> bool {
+    windows.is_empty() || windows.iter().any(|window| window.contains(now))
+}
+
+/// Parse "HH:MM" or "HH:MM:SS".
+fn time_of_day<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M:%S"))
+        .map_err(|e| serde::de::Error::custom(format!("invalid time '{}', expected HH:MM: {}", s, e)))
+}
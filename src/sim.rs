@@ -0,0 +1,82 @@
+//! Synthetic trade feed for `--sim`, so the whole trading loop can run
+//! offline in paper mode.
+
+use chrono::Utc;
+use rand::prelude::*;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use crate::data::TradeMsg;
+use crate::grpc_stream::TradeStream;
+
+/// Price process of the synthetic feed, per trade.
+#[derive(Debug, Clone)]
+pub struct SimParams {
+    /// Mean log return per trade.
+    pub drift: f64,
+    /// Standard deviation of the log return per trade.
+    pub volatility: f64,
+    /// Share of the distance to `start_price` (in log terms) recovered each
+    /// trade; 0 is a plain random walk.
+    pub mean_reversion: f64,
+    pub start_price: f64,
+    pub seed: u64,
+    /// Trades generated before the feed ends; endless when unset.
+    pub trades: Option<u64>,
+    /// Pause between two trades.
+    pub interval: Duration,
+}
+
+/// One market's price path.
+struct Path {
+    market: String,
+    log_price: f64,
+}
+
+/// Feed of synthetic trades cycling over `markets`, each following its own
+/// path drawn from one seeded generator, so a run can be repeated exactly.
+pub fn stream(params: SimParams, markets: Vec<String>) -> TradeStream {
+    let anchor = params.start_price.ln();
+    let paths: Vec<Path> = markets
+        .into_iter()
+        .map(|market| Path { market, log_price: anchor })
+        .collect();
+    let rng = StdRng::seed_from_u64(params.seed);
+    let state = (params, paths, rng, 0u64);
+    Box::pin(futures_util::stream::unfold(state, move |(params, mut paths, mut rng, n)| async move {
+        if paths.is_empty() || params.trades.is_some_and(|max| n >= max) {
+            return None;
+        }
+        if !params.interval.is_zero() {
+            tokio::time::sleep(params.interval).await;
+        }
+        let count = paths.len();
+        let path = &mut paths[(n % count as u64) as usize];
+        let ret = params.drift + params.volatility * standard_normal(&mut rng);
+        path.log_price += ret + params.mean_reversion * (anchor - path.log_price);
+        let price = path.log_price.exp();
+        let bid_volume = rng.gen_range(1.0..100.0);
+        let ask_volume = rng.gen_range(1.0..100.0);
+        let trade = TradeMsg {
+            price,
+            size: rng.gen_range(0.1..10.0),
+            side: if ret >= 0.0 { "buy" } else { "sell" }.to_string(),
+            ts: Utc::now().timestamp_millis(),
+            spread: Some(price * params.volatility.max(1e-4)),
+            market: path.market.clone(),
+            bid_volume,
+            ask_volume,
+            imbalance: (bid_volume - ask_volume) / (bid_volume + ask_volume),
+            event_ts: None,
+        };
+        Some((Ok(trade), (params, paths, rng, n + 1)))
+    }))
+}
+
+/// Draw from N(0, 1) with the Box-Muller transform.
+fn standard_normal(rng: &mut StdRng) -> f64 {
+    // `gen` is in [0, 1); keep the logarithm finite
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
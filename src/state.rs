@@ -0,0 +1,129 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+/// Cumulative PnL and open position of one market.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MarketState {
+    pub pnl: f64,
+    pub fees_paid: f64,
+    pub position: f64,
+    pub entry_price: f64,
+}
+
+/// Trading state carried across restarts, keyed by market name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TraderState {
+    /// Whether the positions were simulated; unknown in older files.
+    #[serde(default)]
+    pub paper_mode: Option<bool>,
+    /// Public keys of the wallets holding the positions; empty in older files.
+    #[serde(default)]
+    pub wallets: Vec<String>,
+    pub markets: BTreeMap<String, MarketState>,
+}
+
+impl TraderState {
+    /// Why this state does not belong to a run in `paper_mode` trading from
+    /// `wallets`, if it does not: paper positions are never live ones, and
+    /// live ones are only held by the wallets that wrote them. Files written
+    /// before the mode and wallets were recorded are accepted.
+    pub fn mismatch(&self, paper_mode: bool, wallets: &[String]) -> Option<String> {
+        let mode = |paper| if paper { "paper" } else { "live" };
+        match self.paper_mode {
+            Some(saved) if saved != paper_mode => {
+                Some(format!("it was written in {} mode, this run is {}", mode(saved), mode(paper_mode)))
+            }
+            Some(false) if !paper_mode && !self.wallets.is_empty() && self.wallets != wallets => Some(format!(
+                "it was written by wallets {}, this run trades from {}",
+                self.wallets.join(", "),
+                wallets.join(", ")
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// JSON file holding the `TraderState`.
+pub struct StateFile {
+    path: String,
+}
+
+impl StateFile {
+    pub fn new(path: &str) -> Self {
+        Self { path: path.to_string() }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Read the saved state. A missing file is a fresh start; an unreadable
+    /// or corrupt one is reported and also yields an empty state.
+    pub fn load(&self) -> TraderState {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                log::info!("No state file at {}; starting flat", self.path);
+                return TraderState::default();
+            }
+            Err(e) => {
+                log::warn!("Failed to read state file {}: {}; starting flat", self.path, e);
+                return TraderState::default();
+            }
+        };
+        match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn!("Corrupt state file {}: {}; starting flat", self.path, e);
+                TraderState::default()
+            }
+        }
+    }
+
+    /// Write the state through a temporary file so a crash mid-write leaves
+    /// the previous state intact.
+    pub fn save(&self, state: &TraderState) -> Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(paper_mode: bool, wallets: &[&str]) -> TraderState {
+        TraderState {
+            paper_mode: Some(paper_mode),
+            wallets: wallets.iter().map(|w| w.to_string()).collect(),
+            markets: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn paper_state_is_not_restored_live() {
+        let wallets = ["A".to_string()];
+        assert!(written(true, &["A"]).mismatch(false, &wallets).is_some());
+        assert!(written(false, &["A"]).mismatch(true, &wallets).is_some());
+        assert!(written(true, &["B"]).mismatch(true, &wallets).is_none());
+    }
+
+    #[test]
+    fn live_state_needs_the_same_wallets() {
+        let wallets = ["A".to_string()];
+        assert!(written(false, &["A"]).mismatch(false, &wallets).is_none());
+        assert!(written(false, &["B"]).mismatch(false, &wallets).is_some());
+    }
+
+    #[test]
+    fn older_files_are_accepted() {
+        let legacy: TraderState = serde_json::from_str(r#"{"markets": {}}"#).unwrap();
+        assert!(legacy.mismatch(false, &["A".to_string()]).is_none());
+        assert!(legacy.mismatch(true, &[]).is_none());
+    }
+}
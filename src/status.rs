@@ -0,0 +1,141 @@
+//! Snapshot of the trader's state served as JSON on `/status`, and the
+//! `POST /reload-model` admin endpoint.
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::metrics::{status_response, Handler};
+use crate::model::{self, Model};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    /// "paper" or "live".
+    pub mode: &'static str,
+    /// Net PnL over all markets, in quote units.
+    pub pnl: f64,
+    /// PnL of closed volume net of fees, and of the open positions marked at
+    /// the last price, in quote units.
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    /// Ratios of per-trade returns, zero until enough trades have closed.
+    pub sharpe: f64,
+    pub sortino: f64,
+    /// Largest decline of the marked PnL from its peak, in quote units.
+    pub max_drawdown: f64,
+    /// Net base-asset position per market.
+    pub positions: BTreeMap<String, f64>,
+    /// Time (ms) of the bot's last fill.
+    pub last_trade_ts: Option<i64>,
+    /// Time (ms) of the last market trade received from the feed.
+    pub last_market_ts: Option<i64>,
+    /// Fills since the start of the UTC day.
+    pub trades_today: u64,
+    /// Samples in the training dataset.
+    pub model_samples: usize,
+    /// Whether the daily loss circuit breaker blocks new orders.
+    pub circuit_breaker_tripped: bool,
+    /// Whether `/halt` or the kill switch file stops all orders.
+    pub halted: bool,
+}
+
+/// Latest `Status`, written by the trader and read by the HTTP server.
+pub type SharedStatus = Arc<RwLock<Status>>;
+
+/// Model loaded by `/reload-model`, swapped in by the trader on its next trade.
+pub type PendingModel = Arc<Mutex<Option<Model>>>;
+
+/// Set by `POST /halt`, cleared by `POST /resume`.
+pub type HaltFlag = Arc<AtomicBool>;
+
+/// Handler for `/status`.
+pub fn handler(status: SharedStatus) -> Handler {
+    Arc::new(move |req: &Request<Body>| {
+        (req.method() == Method::GET && req.uri().path() == "/status").then(|| {
+            let snapshot = status.read().unwrap_or_else(|e| e.into_inner()).clone();
+            json_response(StatusCode::OK, &snapshot)
+        })
+    })
+}
+
+/// Handler for `POST /reload-model`: re-read `model_path` and queue it for
+/// the trader, rejecting a missing file or a model not trained on `features`.
+pub fn reload_handler(model_path: String, features: Vec<String>, pending: PendingModel) -> Handler {
+    Arc::new(move |req: &Request<Body>| {
+        if req.uri().path() != "/reload-model" {
+            return None;
+        }
+        if req.method() != Method::POST {
+            return Some(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+        Some(reload(&model_path, &features, &pending))
+    })
+}
+
+/// Handler for `POST /halt` and `POST /resume`, toggling `flag`.
+pub fn halt_handler(flag: HaltFlag) -> Handler {
+    Arc::new(move |req: &Request<Body>| {
+        let halt = match req.uri().path() {
+            "/halt" => true,
+            "/resume" => false,
+            _ => return None,
+        };
+        if req.method() != Method::POST {
+            return Some(status_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+        if flag.swap(halt, Ordering::SeqCst) != halt {
+            if halt {
+                log::warn!("Trading halted through /halt; open positions are kept");
+            } else {
+                log::warn!("Trading resumed through /resume");
+            }
+        }
+        Some(json_response(StatusCode::OK, &json!({ "halted": halt })))
+    })
+}
+
+fn reload(path: &str, features: &[String], pending: &PendingModel) -> Response<Body> {
+    let error = |status, msg: String| {
+        log::warn!("Model reload rejected: {}", msg);
+        json_response(status, &json!({ "error": msg }))
+    };
+    if !Path::new(path).is_file() {
+        return error(StatusCode::NOT_FOUND, format!("model file '{}' not found", path));
+    }
+    // Unlike `model::load`, leave a corrupt file in place and keep the live model
+    let model = match model::read(path) {
+        Ok(model) => model,
+        Err(e) => return error(StatusCode::UNPROCESSABLE_ENTITY, format!("failed to load '{}': {:#}", path, e)),
+    };
+    if let Err(e) = model::check_features(&model, features) {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e));
+    }
+    let body = json!({
+        "path": path,
+        "model_type": model.model_type(),
+        "n_features": model.n_features(),
+        "trained": model.is_trained(),
+    });
+    *pending.lock().unwrap_or_else(|e| e.into_inner()) = Some(model);
+    log::info!("Model reloaded from {}; applying on the next trade", path);
+    json_response(StatusCode::OK, &body)
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    match serde_json::to_string(value) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(e) => {
+            log::error!("Failed to render JSON response: {}", e);
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -1,30 +1,464 @@
-use crate::model::MlModel;
+use crate::model::Model;
 use anyhow::Result;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+
+/// Threshold used when `signal_threshold` is not configured.
+pub const DEFAULT_SIGNAL_THRESHOLD: f64 = 0.55;
+/// Trades in the moving average used by the cold-start rules.
+const COLD_START_WINDOW: usize = 20;
+/// Distance from the moving average, in bps, that triggers a cold-start signal.
+const COLD_START_BAND_BPS: f64 = 10.0;
+/// Imbalance beyond which the book alone votes for a side, by default.
+pub const DEFAULT_IMBALANCE_THRESHOLD: f64 = 0.2;
+/// Weight of the model in the `Weighted` policy, by default.
+pub const DEFAULT_MODEL_WEIGHT: f64 = 0.5;
+/// Remainder below which a position counts as closed, so rounding in a run
+/// of partial exits cannot keep a stale entry price alive.
+const POSITION_DUST: f64 = 1e-9;
+
+/// Rule used instead of the model while it is untrained.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColdStartStrategy {
+    /// Stay out of the market until the first model is trained.
+    #[default]
+    None,
+    /// Follow moves away from the moving average.
+    Momentum,
+    /// Fade moves away from the moving average.
+    MeanReversion,
+}
 
 pub struct Strategy {
-    model: MlModel,
-    threshold: f64,
+    model: Model,
+    /// Probability above which a long is opened from flat (short below
+    /// `1 - enter_threshold`).
+    enter_threshold: f64,
+    /// Probability below which a long is reversed (a short above
+    /// `1 - exit_threshold`). At most `1 - enter_threshold`.
+    exit_threshold: f64,
+    cold_start: ColdStartStrategy,
+    /// Last `COLD_START_WINDOW` prices, for the cold-start rules.
+    recent_prices: VecDeque<f64>,
+    /// Loss from the entry price, in bps, at which the position is closed.
+    stop_loss_bps: Option<u64>,
+    /// Gain from the entry price, in bps, at which the position is closed.
+    take_profit_bps: Option<u64>,
 }
 
 impl Strategy {
-    pub fn new(model: MlModel, threshold: f64) -> Self {
-        Self { model, threshold }
+    /// Strategy without hysteresis: reversing needs the same confidence as
+    /// entering.
+    pub fn new(model: Model, threshold: f64) -> Self {
+        Self {
+            model,
+            enter_threshold: threshold,
+            exit_threshold: 1.0 - threshold,
+            cold_start: ColdStartStrategy::None,
+            recent_prices: VecDeque::with_capacity(COLD_START_WINDOW + 1),
+            stop_loss_bps: None,
+            take_profit_bps: None,
+        }
+    }
+
+    /// Enable the stop-loss and/or take-profit exits.
+    pub fn with_exits(mut self, stop_loss_bps: Option<u64>, take_profit_bps: Option<u64>) -> Self {
+        self.stop_loss_bps = stop_loss_bps;
+        self.take_profit_bps = take_profit_bps;
+        self
+    }
+
+    /// Require a reversal to clear `exit_threshold` instead of the mirrored
+    /// enter threshold. Values above `1 - enter_threshold` are clamped to it.
+    pub fn with_exit_threshold(mut self, exit_threshold: f64) -> Self {
+        self.exit_threshold = exit_threshold.min(1.0 - self.enter_threshold);
+        self
+    }
+
+    /// Trade on `cold_start` rules while the model is untrained.
+    pub fn with_cold_start(mut self, cold_start: ColdStartStrategy) -> Self {
+        self.cold_start = cold_start;
+        self
+    }
+
+    /// Whether signals currently come from the cold-start rules.
+    pub fn in_cold_start(&self) -> bool {
+        self.cold_start != ColdStartStrategy::None && !self.model.is_trained()
+    }
+
+    /// Swap in a retrained model, keeping the thresholds and exit rules.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Signal a buy or sell when the model disagrees with the side of
+    /// `position`. From flat the enter threshold applies; once long (short)
+    /// only a probability beyond the exit threshold reverses it, so values
+    /// hovering near the boundary do not flip the signal back and forth. The
+    /// stance is read from the position rather than from past signals, which
+    /// may have been dropped before reaching the market.
+    ///
+    /// While the model is untrained the cold-start rule, if any, decides
+    /// instead on `price`.
+    ///
+    /// Returns the side with the confidence `|2 * prob - 1|` in [0, 1] of
+    /// the probability passed through `adjust`; cold-start signals have zero
+    /// confidence.
+    fn signal_with(
+        &mut self,
+        features: &[f64],
+        price: f64,
+        position: &PositionState,
+        adjust: impl FnOnce(f64) -> f64,
+    ) -> Option<(OrderSide, f64)> {
+        let average = self.record_price(price);
+        let stance = Stance::of(position);
+        let mut confidence = 0.0;
+        let (buy, sell) = if self.in_cold_start() {
+            let dir = self.cold_start_direction(average);
+            (
+                dir == Some(OrderSide::Buy) && stance != Stance::Long,
+                dir == Some(OrderSide::Sell) && stance != Stance::Short,
+            )
+        } else {
+            let prob = adjust(self.model.predict(features));
+            confidence = (2.0 * prob - 1.0).abs();
+            match stance {
+                Stance::Flat => (prob > self.enter_threshold, prob < 1.0 - self.enter_threshold),
+                Stance::Long => (false, prob < self.exit_threshold),
+                Stance::Short => (prob > 1.0 - self.exit_threshold, false),
+            }
+        };
+        if buy {
+            Some((OrderSide::Buy, confidence))
+        } else if sell {
+            Some((OrderSide::Sell, confidence))
+        } else {
+            None
+        }
+    }
+
+    /// Scale the order size linearly with `confidence`: `floor` at the enter
+    /// threshold (or below), `max` at certainty.
+    pub fn order_size(&self, confidence: f64, floor: f64, max: f64) -> f64 {
+        let min_confidence = 2.0 * self.enter_threshold - 1.0;
+        let t = if min_confidence < 1.0 {
+            ((confidence - min_confidence) / (1.0 - min_confidence)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        floor + (max - floor).max(0.0) * t
+    }
+
+    /// Decide what to do at `price` given the open `position`. Exits take
+    /// precedence over the model: once the price crosses the stop-loss or
+    /// take-profit level the whole position is closed.
+    pub fn evaluate(&mut self, features: &[f64], price: f64, position: &PositionState) -> Action {
+        self.evaluate_with(features, price, position, |prob| prob)
+    }
+
+    /// `evaluate` with the model probability passed through `adjust` before
+    /// the thresholds apply.
+    fn evaluate_with(
+        &mut self,
+        features: &[f64],
+        price: f64,
+        position: &PositionState,
+        adjust: impl FnOnce(f64) -> f64,
+    ) -> Action {
+        if let Some(reason) = self.exit_reason(price, position) {
+            let side = if position.size > 0.0 {
+                OrderSide::Sell
+            } else {
+                OrderSide::Buy
+            };
+            return Action::Exit { side, reason };
+        }
+        match self.signal_with(features, price, position, adjust) {
+            Some((side, confidence)) => Action::Enter { side, confidence },
+            None => Action::Hold,
+        }
+    }
+
+    /// Push `price` into the cold-start window and return the moving average
+    /// of the window before it, once the window is full.
+    fn record_price(&mut self, price: f64) -> Option<f64> {
+        let average = (self.recent_prices.len() == COLD_START_WINDOW)
+            .then(|| self.recent_prices.iter().sum::<f64>() / COLD_START_WINDOW as f64);
+        self.recent_prices.push_back(price);
+        if self.recent_prices.len() > COLD_START_WINDOW {
+            self.recent_prices.pop_front();
+        }
+        average
+    }
+
+    /// Direction of the cold-start rule for the latest price.
+    fn cold_start_direction(&self, average: Option<f64>) -> Option<OrderSide> {
+        let average = average.filter(|a| *a > 0.0)?;
+        let price = *self.recent_prices.back()?;
+        let deviation_bps = (price - average) / average * 10_000.0;
+        if deviation_bps.abs() <= COLD_START_BAND_BPS {
+            return None;
+        }
+        let buy = match self.cold_start {
+            ColdStartStrategy::None => return None,
+            ColdStartStrategy::Momentum => deviation_bps > 0.0,
+            ColdStartStrategy::MeanReversion => deviation_bps < 0.0,
+        };
+        Some(if buy { OrderSide::Buy } else { OrderSide::Sell })
     }
 
-    pub fn generate_signal(&self, features: &[f64]) -> Option<OrderSide> {
-        let prob = self.model.predict(features);
-        if prob > self.threshold {
-            Some(OrderSide::Buy)
-        } else if prob < 1.0 - self.threshold {
-            Some(OrderSide::Sell)
+    fn exit_reason(&self, price: f64, position: &PositionState) -> Option<ExitReason> {
+        if position.size == 0.0 || position.entry_price <= 0.0 {
+            return None;
+        }
+        // Return of the open position in bps, positive when it is in profit
+        let move_bps = (price - position.entry_price) / position.entry_price * 10_000.0;
+        let return_bps = move_bps * position.size.signum();
+        if matches!(self.stop_loss_bps, Some(sl) if return_bps <= -(sl as f64)) {
+            Some(ExitReason::StopLoss)
+        } else if matches!(self.take_profit_bps, Some(tp) if return_bps >= tp as f64) {
+            Some(ExitReason::TakeProfit)
         } else {
             None
         }
     }
 }
 
+/// How `CompositeStrategy` merges the model with the order-book imbalance.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CombinePolicy {
+    /// The model alone, as `Strategy` does.
+    #[default]
+    Model,
+    /// Signal only when the model and the imbalance agree on the side.
+    And,
+    /// Signal when either does; the model wins when both vote.
+    Or,
+    /// Threshold a weighted average of the model probability and the
+    /// imbalance mapped to [0, 1].
+    Weighted,
+}
+
+/// `Strategy` whose model probability is combined with an order-book
+/// imbalance rule. Thresholds, hysteresis, cold start and exits are those of
+/// the wrapped strategy, which it derefs to.
+pub struct CompositeStrategy {
+    inner: Strategy,
+    policy: CombinePolicy,
+    /// |imbalance| beyond which the book votes for a side.
+    imbalance_threshold: f64,
+    /// Share of the model in the `Weighted` average.
+    model_weight: f64,
+}
+
+impl CompositeStrategy {
+    pub fn new(inner: Strategy, policy: CombinePolicy, imbalance_threshold: f64, model_weight: f64) -> Self {
+        Self {
+            inner,
+            policy,
+            imbalance_threshold,
+            model_weight: model_weight.clamp(0.0, 1.0),
+        }
+    }
+
+    /// `Strategy::evaluate` on the probability combined with the order-book
+    /// `imbalance` of the trade.
+    pub fn evaluate(&mut self, features: &[f64], price: f64, imbalance: f64, position: &PositionState) -> Action {
+        let (policy, threshold, weight) = (self.policy, self.imbalance_threshold, self.model_weight);
+        let enter = self.inner.enter_threshold;
+        self.inner.evaluate_with(features, price, position, |prob| {
+            combine(policy, prob, enter, imbalance, threshold, weight)
+        })
+    }
+}
+
+/// Merge the model probability `prob` with `imbalance` into the probability
+/// the strategy thresholds. Returning 0.5 means "no signal".
+fn combine(policy: CombinePolicy, prob: f64, enter: f64, imbalance: f64, threshold: f64, weight: f64) -> f64 {
+    let vote = |score: f64, bar: f64| (score.abs() > bar).then_some(score.signum());
+    let model_vote = vote(prob - 0.5, enter - 0.5);
+    let book_vote = vote(imbalance, threshold);
+    match policy {
+        CombinePolicy::Model => prob,
+        CombinePolicy::And if model_vote.is_some() && model_vote == book_vote => prob,
+        CombinePolicy::And => 0.5,
+        CombinePolicy::Or => match (model_vote, book_vote) {
+            (None, Some(sign)) => {
+                // Map the imbalance past its threshold onto the range past
+                // the enter threshold, so it clears it by the same margin
+                let strength = (imbalance.abs() - threshold) / (1.0 - threshold).max(f64::EPSILON);
+                0.5 + sign * ((enter - 0.5) + (1.0 - enter) * strength.clamp(0.0, 1.0))
+            }
+            _ => prob,
+        },
+        CombinePolicy::Weighted => weight * prob + (1.0 - weight) * (0.5 + imbalance / 2.0),
+    }
+}
+
+impl Deref for CompositeStrategy {
+    type Target = Strategy;
+
+    fn deref(&self) -> &Strategy {
+        &self.inner
+    }
+}
+
+impl DerefMut for CompositeStrategy {
+    fn deref_mut(&mut self) -> &mut Strategy {
+        &mut self.inner
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
+
+impl OrderSide {
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stance {
+    Flat,
+    Long,
+    Short,
+}
+
+impl Stance {
+    fn of(position: &PositionState) -> Self {
+        if position.size > 0.0 {
+            Stance::Long
+        } else if position.size < 0.0 {
+            Stance::Short
+        } else {
+            Stance::Flat
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitReason {
+    StopLoss,
+    TakeProfit,
+}
+
+/// Outcome of `Strategy::evaluate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Hold,
+    /// Model signal to open or add to a position, with the model's
+    /// confidence in [0, 1] for sizing.
+    Enter { side: OrderSide, confidence: f64 },
+    /// Close the whole open position.
+    Exit { side: OrderSide, reason: ExitReason },
+}
+
+/// Net position with the volume-weighted average price it was opened at.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PositionState {
+    /// Signed base amount: positive long, negative short.
+    pub size: f64,
+    pub entry_price: f64,
+}
+
+impl PositionState {
+    /// Apply a fill of `qty` at `price`. Adds move the volume-weighted entry
+    /// price; reductions realize PnL against it. Returns the PnL realized on
+    /// the part of the position it closed, if any.
+    pub fn apply(&mut self, side: OrderSide, qty: f64, price: f64) -> Option<f64> {
+        let signed = if side == OrderSide::Buy { qty } else { -qty };
+        let mut new_size = self.size + signed;
+        if new_size.abs() < POSITION_DUST {
+            // Partial exits summing to the position must leave it flat
+            new_size = 0.0;
+        }
+        let mut realized = None;
+
+        if self.size != 0.0 && self.size.signum() != signed.signum() {
+            // Part (or all) of the open position is closed at `price`
+            let closed = qty.min(self.size.abs());
+            realized = Some(closed * (price - self.entry_price) * self.size.signum());
+            if new_size == 0.0 {
+                self.entry_price = 0.0;
+            } else if new_size.signum() != self.size.signum() {
+                // Flipped sides: the remainder opens at the fill price
+                self.entry_price = price;
+            }
+        } else if new_size != 0.0 {
+            // Opening or adding: update the average entry price
+            self.entry_price = (self.entry_price * self.size.abs() + price * qty) / new_size.abs();
+        }
+
+        self.size = new_size;
+        realized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn adds_move_entry_to_vwap() {
+        let mut position = PositionState::default();
+        assert_eq!(position.apply(OrderSide::Buy, 1.0, 100.0), None);
+        assert_eq!(position.apply(OrderSide::Buy, 3.0, 104.0), None);
+        assert!(close(position.size, 4.0));
+        assert!(close(position.entry_price, 103.0));
+    }
+
+    #[test]
+    fn partial_reduction_realizes_against_entry() {
+        let mut position = PositionState::default();
+        position.apply(OrderSide::Buy, 4.0, 100.0);
+        let realized = position.apply(OrderSide::Sell, 1.5, 110.0).unwrap();
+        assert!(close(realized, 15.0));
+        assert!(close(position.size, 2.5));
+        assert!(close(position.entry_price, 100.0));
+
+        let mut short = PositionState::default();
+        short.apply(OrderSide::Sell, 2.0, 50.0);
+        let realized = short.apply(OrderSide::Buy, 1.0, 52.0).unwrap();
+        assert!(close(realized, -2.0));
+        assert!(close(short.size, -1.0));
+        assert!(close(short.entry_price, 50.0));
+    }
+
+    #[test]
+    fn full_close_resets_entry() {
+        let mut position = PositionState::default();
+        position.apply(OrderSide::Buy, 0.3, 100.0);
+        position.apply(OrderSide::Sell, 0.1, 101.0);
+        position.apply(OrderSide::Sell, 0.1, 102.0);
+        let realized = position.apply(OrderSide::Sell, 0.1, 103.0).unwrap();
+        assert!(close(realized, 0.3));
+        assert_eq!(position.size, 0.0);
+        assert_eq!(position.entry_price, 0.0);
+    }
+
+    #[test]
+    fn flip_rebases_at_fill_price() {
+        let mut position = PositionState::default();
+        position.apply(OrderSide::Buy, 2.0, 100.0);
+        let realized = position.apply(OrderSide::Sell, 5.0, 90.0).unwrap();
+        // Only the 2 long units close; the other 3 open a short at 90
+        assert!(close(realized, -20.0));
+        assert!(close(position.size, -3.0));
+        assert!(close(position.entry_price, 90.0));
+    }
+}
@@ -1,39 +1,1007 @@
-use anyhow::Result;
-use solana_sdk::signature::{Keypair, Signature};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use prometheus::IntCounter;
+use rand::Rng;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    compute_budget::{self, ComputeBudgetInstruction},
+    hash::Hash,
+    instruction::CompiledInstruction,
+    message::VersionedMessage,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    transaction::VersionedTransaction,
+};
+use solana_transaction_status_client_types::{
+    option_serializer::OptionSerializer, UiTransactionEncoding, UiTransactionTokenBalance,
+};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-/// Minimal placeholder Quote structure.
-/// In a production setup, this would mirror the response schema from the
-/// Jupiter Swap API. For now we only need a stub to satisfy the compiler.
+use crate::config::{BotConfig, MarketConfig};
+use crate::rate_limit::{RateLimiter, ThrottleMode};
+
+/// Compute units assumed when the transaction does not set its own limit.
+const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+/// Largest compute unit limit a transaction may request.
+const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+/// Discriminator of `ComputeBudgetInstruction::SetComputeUnitLimit`.
+const SET_COMPUTE_UNIT_LIMIT: u8 = 2;
+/// Delay between two signature status polls.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Minimum delay between two checks of an unlanded swap's blockhash.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Shortest wait for a submission to land: a blockhash lives 150 blocks,
+/// 60 to 90 seconds, and its expiry must be seen to re-quote the swap.
+pub const MIN_CONFIRM_WAIT: Duration = Duration::from_secs(100);
+/// Blockhash retries of an expired swap, by default.
+const DEFAULT_MAX_BLOCKHASH_RETRIES: u32 = 2;
+/// Age past which a quote is no longer sent as a swap, by default.
+const DEFAULT_QUOTE_MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Failure of a Jupiter HTTP call, after any retries.
+#[derive(Debug, thiserror::Error)]
+pub enum SwapError {
+    /// Non-success response; the body is kept for debugging.
+    #[error("Jupiter returned HTTP {status}: {body}")]
+    Http { status: StatusCode, body: String },
+    /// A request or confirmation wait exceeded its deadline.
+    #[error("{what} timed out after {after:?}")]
+    Timeout { what: String, after: Duration },
+    #[error("Jupiter request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The swap or its route would touch a mint on `blacklisted_mints`.
+    #[error("{context} involves blacklisted mint {mint}")]
+    Blacklisted { mint: String, context: String },
+    /// Every submission's blockhash expired before it landed.
+    #[error("swap {signature} expired unconfirmed after {submissions} submissions")]
+    Expired { signature: Signature, submissions: u32 },
+    /// `jupiter_rps` was exhausted and the call was skipped, or would have
+    /// waited longer than the request timeout.
+    #[error("Jupiter rate limit of {rps} requests/s reached")]
+    Throttled { rps: f64 },
+    /// The quote grew older than `quote_max_age_ms` before the swap was sent.
+    #[error("quote is {age:?} old, over the {max:?} limit")]
+    StaleQuote { age: Duration, max: Duration },
+}
+
+impl SwapError {
+    /// Timeouts, connection failures, rate limits and server errors may
+    /// succeed on a retry; anything else (e.g. 400) will not.
+    fn is_retryable(&self) -> bool {
+        match self {
+            SwapError::Http { status, .. } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            SwapError::Timeout { .. } => true,
+            SwapError::Transport(e) => e.is_connect(),
+            SwapError::Blacklisted { .. }
+            | SwapError::Expired { .. }
+            | SwapError::Throttled { .. }
+            | SwapError::StaleQuote { .. } => false,
+        }
+    }
+}
+
+/// Which side of the swap `amount` fixes, mirroring Jupiter's `swapMode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SwapMode {
+    /// `amount` is the exact input; the output floats.
+    ExactIn,
+    /// `amount` is the exact output; the input floats.
+    ExactOut,
+}
+
+impl SwapMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SwapMode::ExactIn => "ExactIn",
+            SwapMode::ExactOut => "ExactOut",
+        }
+    }
+}
+
+/// Kind of Jupiter deployment `jupiter_api_url` points at.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JupiterMode {
+    /// Jupiter's public API: versioned `/v6/...` paths, rate limited, and
+    /// authenticated with `x-api-key` on the paid tier.
+    #[default]
+    Hosted,
+    /// A self-hosted `jupiter-swap-api`: unversioned paths, no API key.
+    SelfHosted,
+}
+
+impl JupiterMode {
+    /// URL of `endpoint` (e.g. "quote") under `base`, which may already
+    /// include the version segment.
+    fn url(self, base: &str, endpoint: &str) -> String {
+        match self {
+            JupiterMode::Hosted if !base.ends_with("/v6") => format!("{}/v6/{}", base, endpoint),
+            _ => format!("{}/{}", base, endpoint),
+        }
+    }
+}
+
+/// Quote returned by the Jupiter `/quote` endpoint.
+/// The raw JSON is kept verbatim because `/swap` expects it back unchanged.
+#[derive(Debug, Clone)]
+pub struct Quote {
+    /// Input amount in atomic units of the input mint.
+    pub in_amount: u64,
+    /// Expected output amount in atomic units of the output mint.
+    pub out_amount: u64,
+    /// Slippage bound: the minimum output for `ExactIn`, the maximum input
+    /// for `ExactOut`.
+    pub other_amount_threshold: u64,
+    pub swap_mode: SwapMode,
+    /// Decimals of the input mint, to convert `in_amount`.
+    pub input_decimals: u8,
+    /// Decimals of the output mint, to convert `out_amount` and `platform_fee`.
+    pub output_decimals: u8,
+    /// Price impact of the route as a fraction (0.01 = 1%).
+    pub price_impact_pct: f64,
+    /// Platform fee in atomic units of the output mint, zero when none.
+    pub platform_fee: u64,
+    /// When the quote was received, to refuse it once stale.
+    pub fetched_at: Instant,
+    pub raw: Value,
+}
+
+impl Quote {
+    /// Whether this quote's expected amounts are no worse than the slippage
+    /// bound of `first`, quoted for the same swap: at most its maximum input
+    /// for `ExactOut`, at least its minimum output for `ExactIn`.
+    pub fn within_bound_of(&self, first: &Quote) -> bool {
+        match first.swap_mode {
+            SwapMode::ExactIn => self.out_amount >= first.other_amount_threshold,
+            SwapMode::ExactOut => self.in_amount <= first.other_amount_threshold,
+        }
+    }
+
+    /// Execution price in quote units per base unit, `sell` meaning the
+    /// input is the base token. `None` for an empty quote.
+    pub fn implied_price(&self, sell: bool) -> Option<f64> {
+        let input = self.in_amount as f64 / 10f64.powi(self.input_decimals as i32);
+        let output = self.out_amount as f64 / 10f64.powi(self.output_decimals as i32);
+        let (base, quote) = if sell { (input, output) } else { (output, input) };
+        (base > 0.0).then(|| quote / base)
+    }
+
+    /// AMMs the route passes through, e.g. "Raydium -> Orca".
+    pub fn route(&self) -> String {
+        let hops: Vec<&str> = self.raw
+            .get("routePlan")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|hop| hop["swapInfo"].get("label").and_then(Value::as_str).unwrap_or("unknown AMM"))
+            .collect();
+        if hops.is_empty() {
+            "-".to_string()
+        } else {
+            hops.join(" -> ")
+        }
+    }
+}
+
+/// Outcome of a submitted (not yet confirmed) swap.
 #[derive(Debug, Clone)]
-pub struct Quote;
+pub struct SwapSubmission {
+    pub signature: Signature,
+    /// Compute units consumed by the pre-flight simulation, when it ran.
+    pub units_consumed: Option<u64>,
+    /// Blockhash the transaction was signed with, to notice its expiry.
+    blockhash: Hash,
+}
+
+/// How a swap was quoted, to quote it again once its blockhash expired.
+#[derive(Debug, Clone)]
+pub struct QuoteRequest {
+    pub market: MarketConfig,
+    pub amount: f64,
+    pub sell: bool,
+    pub mode: SwapMode,
+    pub slippage_bps: u64,
+    pub priority_fee_lamports: Option<u64>,
+}
+
+/// How the wait for a submitted swap ended.
+enum Landing {
+    Landed,
+    /// The blockhash expired with the transaction nowhere to be seen.
+    Expired,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SwapResponse {
+    swap_transaction: String,
+}
 
-/// Very small stub implementation that mimics the interface exposed by the old
-/// `jup_ag::swap::SwapClient`. It can later be upgraded to call the real
-/// Jupiter v6 Swap API using `reqwest`.
+/// Thin client for the Jupiter v6 Swap API. Quotes and swap transactions are
+/// fetched over HTTP, signed locally and submitted through the injected
+/// `RpcClient`.
 #[derive(Clone)]
 pub struct SwapClient {
-    base_url: String,
+    quote_url: String,
+    swap_url: String,
+    /// Sent as `x-api-key`; only set for the hosted API.
+    api_key: Option<String>,
+    http: reqwest::Client,
+    rpc: Arc<RpcClient>,
+    /// Mints never swapped, directly or as a route hop.
+    blacklisted_mints: HashSet<String>,
+    /// Route restrictions passed to every quote.
+    dexes: Option<Vec<String>>,
+    exclude_dexes: Option<Vec<String>>,
+    only_direct_routes: bool,
+    /// Let Jupiter add instructions creating missing token accounts.
+    auto_create_ata: bool,
+    /// Swap native SOL through a temporary wrapped-SOL account.
+    wrap_unwrap_sol: bool,
+    simulate_before_send: bool,
+    compute_unit_limit: Option<u32>,
+    compute_unit_margin_pct: Option<u32>,
+    /// Commitment a swap must reach to count as landed.
+    confirm_commitment: CommitmentConfig,
+    max_blockhash_retries: u32,
+    /// Oldest quote still sent as a swap.
+    quote_max_age: Duration,
+    /// Client-side cap on Jupiter requests per second, when configured.
+    limiter: Option<RateLimiter>,
+    throttle: ThrottleMode,
+    /// Counts calls delayed or skipped by `limiter`.
+    throttled: Option<IntCounter>,
+    max_retries: u32,
+    base_delay: Duration,
+    timeout: Duration,
 }
 
 impl SwapClient {
-    /// Create a new instance pointing at the given HTTP endpoint (e.g. the
-    /// Jupiter hosted API or a self-hosted instance).
-    pub fn new(base_url: String) -> Self {
-        Self { base_url }
+    /// Create a client for the configured Jupiter endpoint (hosted API or a
+    /// self-hosted instance), submitting transactions through `rpc`.
+    pub fn from_config(cfg: &BotConfig, rpc: Arc<RpcClient>) -> Result<Self> {
+        let timeout = cfg.http_timeout();
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()?;
+        let base = cfg.jupiter_api_url.trim_end_matches('/');
+        let api_key = match cfg.jupiter_mode {
+            JupiterMode::Hosted => cfg.jupiter_api_key.clone(),
+            JupiterMode::SelfHosted => {
+                if cfg.jupiter_api_key.is_some() {
+                    log::warn!("jupiter_api_key is ignored for a self-hosted Jupiter API");
+                }
+                None
+            }
+        };
+        Ok(Self {
+            quote_url: cfg.jupiter_mode.url(base, "quote"),
+            swap_url: cfg.jupiter_mode.url(base, "swap"),
+            api_key,
+            http,
+            rpc,
+            blacklisted_mints: cfg.blacklisted_mints.iter().cloned().collect(),
+            dexes: cfg.dexes.clone(),
+            exclude_dexes: cfg.exclude_dexes.clone(),
+            only_direct_routes: cfg.only_direct_routes.unwrap_or(false),
+            auto_create_ata: cfg.auto_create_ata.unwrap_or(true),
+            wrap_unwrap_sol: cfg.wrap_unwrap_sol.unwrap_or(true),
+            simulate_before_send: cfg.simulate_before_send.unwrap_or(true),
+            compute_unit_limit: cfg.compute_unit_limit,
+            compute_unit_margin_pct: cfg.compute_unit_margin_pct,
+            confirm_commitment: cfg.confirm_commitment.unwrap_or(cfg.commitment).config(),
+            max_blockhash_retries: cfg.max_blockhash_retries.unwrap_or(DEFAULT_MAX_BLOCKHASH_RETRIES),
+            quote_max_age: cfg.quote_max_age_ms.map_or(DEFAULT_QUOTE_MAX_AGE, Duration::from_millis),
+            limiter: cfg.jupiter_rps.map(RateLimiter::new),
+            throttle: cfg.jupiter_throttle,
+            throttled: None,
+            max_retries: cfg.swap_max_retries.unwrap_or(3),
+            base_delay: Duration::from_millis(cfg.swap_base_delay_ms.unwrap_or(250)),
+            timeout,
+        })
+    }
+
+    /// Count calls delayed or skipped by the rate limit on `counter`.
+    pub fn with_throttle_counter(mut self, counter: IntCounter) -> Self {
+        self.throttled = Some(counter);
+        self
+    }
+
+    /// Take a `jupiter_rps` token, waiting for one up to the request timeout
+    /// or failing at once, as `jupiter_throttle` says.
+    async fn throttle(&self) -> Result<(), SwapError> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
+        };
+        let max_wait = match self.throttle {
+            ThrottleMode::Wait => self.timeout,
+            ThrottleMode::Skip => Duration::ZERO,
+        };
+        let reserved = limiter.reserve(max_wait);
+        if reserved != Some(Duration::ZERO) {
+            if let Some(counter) = &self.throttled {
+                counter.inc();
+            }
+        }
+        match reserved {
+            Some(wait) => {
+                if !wait.is_zero() {
+                    log::debug!("Jupiter rate limit reached, waiting {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                }
+                Ok(())
+            }
+            None => Err(SwapError::Throttled { rps: limiter.rate() }),
+        }
+    }
+
+    /// Attach the API key, if any, to a request.
+    fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => req.header("x-api-key", key),
+            None => req,
+        }
     }
 
-    /// Fetch a swap quote. The implementation is currently a stub that returns
-    /// an empty `Quote` object.
-    pub async fn quote(&self, _symbol: &str, _amount: f64, _sell: Option<bool>) -> Result<Quote> {
-        // TODO: Implement real quote call against Swap API
-        Ok(Quote)
+    /// Send the request built by `build`, retrying retryable failures with
+    /// exponential backoff plus up to one base delay of random jitter. Every
+    /// attempt, retries included, counts against `jupiter_rps`.
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<T, SwapError> {
+        let mut attempt = 0;
+        loop {
+            self.throttle().await?;
+            let err = match self.authorize(build()).send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(resp.json().await?),
+                Ok(resp) => SwapError::Http {
+                    status: resp.status(),
+                    body: resp.text().await.unwrap_or_default(),
+                },
+                Err(e) if e.is_timeout() => SwapError::Timeout {
+                    what: format!("Jupiter request to {}", e.url().map_or("?", |u| u.path())),
+                    after: self.timeout,
+                },
+                Err(e) => SwapError::Transport(e),
+            };
+            if attempt >= self.max_retries || !err.is_retryable() {
+                return Err(err);
+            }
+            let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+            let jitter = self.base_delay.mul_f64(rand::thread_rng().gen::<f64>());
+            log::warn!(
+                "Jupiter call failed ({}), retry {}/{} in {:?}",
+                err,
+                attempt + 1,
+                self.max_retries,
+                backoff + jitter
+            );
+            tokio::time::sleep(backoff + jitter).await;
+            attempt += 1;
+        }
+    }
+
+    /// Fetch a swap quote between the mints of `market`, scaled with its
+    /// configured decimals. Selling swaps base into quote, buying swaps quote
+    /// into base. `amount` is expressed in human units of the input token for
+    /// `ExactIn` and of the output token for `ExactOut`. The swap built from
+    /// the quote accepts `slippage_bps` of slippage.
+    pub async fn quote(
+        &self,
+        market: &MarketConfig,
+        amount: f64,
+        sell: Option<bool>,
+        mode: SwapMode,
+        slippage_bps: u64,
+    ) -> Result<Quote> {
+        let base = (market.base_mint.as_str(), market.base_decimals);
+        let quote = (market.quote_mint.as_str(), market.quote_decimals);
+        let (input, output) = if sell.unwrap_or(false) { (base, quote) } else { (quote, base) };
+        let decimals = match mode {
+            SwapMode::ExactIn => input.1,
+            SwapMode::ExactOut => output.1,
+        };
+        for mint in [input.0, output.0] {
+            if self.blacklisted_mints.contains(mint) {
+                return Err(SwapError::Blacklisted {
+                    mint: mint.to_string(),
+                    context: format!("market {}", market.name),
+                }
+                .into());
+            }
+        }
+        let atomic = to_atomic(amount, decimals);
+
+        let url = &self.quote_url;
+        let mut query = vec![
+            ("inputMint", input.0.to_string()),
+            ("outputMint", output.0.to_string()),
+            ("amount", atomic.to_string()),
+            ("slippageBps", slippage_bps.to_string()),
+            ("swapMode", mode.as_str().to_string()),
+        ];
+        if let Some(dexes) = &self.dexes {
+            query.push(("dexes", dexes.join(",")));
+        }
+        if let Some(dexes) = &self.exclude_dexes {
+            query.push(("excludeDexes", dexes.join(",")));
+        }
+        if self.only_direct_routes {
+            query.push(("onlyDirectRoutes", "true".to_string()));
+        }
+        let raw: Value = self.send_json(|| self.http.get(url).query(&query)).await?;
+        self.check_route(&raw)?;
+
+        let price_impact_pct = raw
+            .get("priceImpactPct")
+            .and_then(Value::as_str)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.0);
+        let platform_fee = match raw.get("platformFee") {
+            Some(fee) if !fee.is_null() => amount_field(fee, "amount")?,
+            _ => 0,
+        };
+        Ok(Quote {
+            in_amount: amount_field(&raw, "inAmount")?,
+            out_amount: amount_field(&raw, "outAmount")?,
+            other_amount_threshold: amount_field(&raw, "otherAmountThreshold")?,
+            swap_mode: mode,
+            input_decimals: input.1,
+            output_decimals: output.1,
+            price_impact_pct,
+            platform_fee,
+            fetched_at: Instant::now(),
+            raw,
+        })
+    }
+
+    /// Refuse a quote older than `quote_max_age`: Jupiter stops honoring
+    /// quotes quickly and the swap would only fail its slippage check.
+    fn check_fresh(&self, quote: &Quote) -> Result<()> {
+        let age = quote.fetched_at.elapsed();
+        if age > self.quote_max_age {
+            return Err(SwapError::StaleQuote {
+                age,
+                max: self.quote_max_age,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Refuse a quote whose `routePlan` passes through a blacklisted mint.
+    fn check_route(&self, raw: &Value) -> Result<()> {
+        if self.blacklisted_mints.is_empty() {
+            return Ok(());
+        }
+        let hops = raw.get("routePlan").and_then(Value::as_array).into_iter().flatten();
+        for hop in hops {
+            let info = &hop["swapInfo"];
+            for key in ["inputMint", "outputMint"] {
+                let Some(mint) = info.get(key).and_then(Value::as_str) else {
+                    continue;
+                };
+                if self.blacklisted_mints.contains(mint) {
+                    let amm = info.get("label").and_then(Value::as_str).unwrap_or("unknown AMM");
+                    return Err(SwapError::Blacklisted {
+                        mint: mint.to_string(),
+                        context: format!("route hop through {}", amm),
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Body of the `/swap` request building `quote` for `user`.
+    fn swap_body(&self, quote: &Quote, user: &Pubkey, priority_fee_lamports: Option<u64>) -> Value {
+        let mut body = json!({
+            "quoteResponse": quote.raw,
+            "userPublicKey": user.to_string(),
+            "wrapAndUnwrapSol": self.wrap_unwrap_sol,
+            // Without the account checks Jupiter assumes every token account
+            // exists and never adds instructions creating them
+            "skipUserAccountsRpcCalls": !self.auto_create_ata,
+        });
+        if let Some(fee) = priority_fee_lamports {
+            body["prioritizationFeeLamports"] = json!(fee);
+        }
+        body
     }
 
     /// Submit a swap request and return the resulting transaction signature.
-    /// At the moment this just returns `Signature::default()` so that downstream
-    /// logic can continue to build.
-    pub async fn swap(&self, _wallet: &Keypair, _quote: &Quote) -> Result<Signature> {
-        // TODO: Implement real swap execution against Swap API
-        Ok(Signature::default())
+    /// The serialized transaction returned by Jupiter is signed with `wallet`
+    /// and sent through the RPC client; it is not confirmed here. Unless
+    /// disabled, the signed transaction is simulated first and not sent if
+    /// the simulation fails. A quote older than `quote_max_age_ms`, checked
+    /// both before building the swap and right before sending it, fails with
+    /// `SwapError::StaleQuote` so the caller re-quotes.
+    ///
+    /// When `priority_fee_lamports` is set, it is forwarded to Jupiter and a
+    /// matching `SetComputeUnitPrice` instruction is placed at the front of the
+    /// transaction. With `None` the transaction is left untouched.
+    ///
+    /// Jupiter already puts a `SetComputeUnitLimit` in the transaction. A
+    /// configured `compute_unit_limit` replaces it rather than adding a second
+    /// one, which the runtime would reject. With `compute_unit_margin_pct` the
+    /// limit is instead re-derived from the simulation and the transaction is
+    /// signed again. Either way the unit price is recomputed from the final
+    /// limit so the total priority fee stays as configured.
+    pub async fn swap(
+        &self,
+        wallet: &Keypair,
+        quote: &Quote,
+        priority_fee_lamports: Option<u64>,
+    ) -> Result<SwapSubmission> {
+        self.check_fresh(quote)?;
+        let url = &self.swap_url;
+        let body = self.swap_body(quote, &wallet.pubkey(), priority_fee_lamports);
+        let resp: SwapResponse = self.send_json(|| self.http.post(url).json(&body)).await?;
+
+        let tx_bytes = BASE64
+            .decode(&resp.swap_transaction)
+            .context("swapTransaction is not valid base64")?;
+        let unsigned: VersionedTransaction =
+            bincode::deserialize(&tx_bytes).context("failed to deserialize swap transaction")?;
+        let message = unsigned.message;
+        let sign = |limit: Option<u64>| -> Result<VersionedTransaction> {
+            let mut message = message.clone();
+            if let Some(limit) = limit {
+                let ix = ComputeBudgetInstruction::set_compute_unit_limit(limit as u32);
+                prepend_compute_budget_ix(&mut message, ix.data);
+            }
+            if let Some(fee) = priority_fee_lamports {
+                let cu_limit = compute_unit_limit(&message).unwrap_or(DEFAULT_COMPUTE_UNIT_LIMIT);
+                let micro_lamports = fee.saturating_mul(1_000_000) / cu_limit.max(1);
+                let ix = ComputeBudgetInstruction::set_compute_unit_price(micro_lamports);
+                prepend_compute_budget_ix(&mut message, ix.data);
+            }
+            VersionedTransaction::try_new(message, &[wallet]).context("failed to sign swap transaction")
+        };
+        let mut signed = sign(self.compute_unit_limit.map(u64::from))?;
+
+        let mut units_consumed = None;
+        if self.simulate_before_send {
+            let sim = self.rpc.simulate_transaction(&signed).await?.value;
+            if let Some(err) = sim.err {
+                let logs = sim.logs.unwrap_or_default().join("\n");
+                return Err(anyhow!("swap simulation failed: {}\n{}", err, logs));
+            }
+            units_consumed = sim.units_consumed;
+        }
+        if let (None, Some(margin), Some(units)) =
+            (self.compute_unit_limit, self.compute_unit_margin_pct, units_consumed)
+        {
+            let limit = (units.saturating_mul(100 + margin as u64) / 100).min(MAX_COMPUTE_UNIT_LIMIT);
+            log::debug!("Compute unit limit {} ({} simulated + {}%)", limit, units, margin);
+            signed = sign(Some(limit))?;
+        }
+
+        self.check_fresh(quote)?;
+        let signature = self.rpc.send_transaction(&signed).await?;
+        Ok(SwapSubmission {
+            signature,
+            units_consumed,
+            blockhash: *signed.message.recent_blockhash(),
+        })
+    }
+
+    /// Wait for a swap built from `quote` to reach `confirm_commitment`,
+    /// polling until it lands or its blockhash expires. An expired swap's
+    /// route is as old as its blockhash, so `request` is quoted again and
+    /// the fresh quote swapped, up to `max_blockhash_retries` times, as long
+    /// as it still fits the slippage bound of `quote`. Each submission may
+    /// wait `limit`, but never less than `MIN_CONFIRM_WAIT`. Returns the
+    /// signature that landed and the quote it was built from.
+    pub async fn confirm(
+        &self,
+        wallet: &Keypair,
+        submission: SwapSubmission,
+        quote: Quote,
+        request: &QuoteRequest,
+        limit: Duration,
+    ) -> Result<(Signature, Quote)> {
+        let limit = limit.max(MIN_CONFIRM_WAIT);
+        let SwapSubmission { mut signature, mut blockhash, .. } = submission;
+        let mut landing_quote = quote.clone();
+        let mut submissions = 1;
+        loop {
+            let landing = match tokio::time::timeout(limit, self.poll_landing(&signature, &blockhash)).await {
+                Ok(landing) => landing?,
+                Err(_) => {
+                    return Err(SwapError::Timeout {
+                        what: format!("confirmation of {}", signature),
+                        after: limit,
+                    }
+                    .into())
+                }
+            };
+            if let Landing::Landed = landing {
+                return Ok((signature, landing_quote));
+            }
+            if submissions > self.max_blockhash_retries {
+                return Err(SwapError::Expired { signature, submissions }.into());
+            }
+            let fresh = self
+                .quote(&request.market, request.amount, Some(request.sell), request.mode, request.slippage_bps)
+                .await?;
+            if !fresh.within_bound_of(&quote) {
+                return Err(anyhow!(
+                    "swap {} expired and its re-quote ({} in, {} out) is past the first quote's bound {}",
+                    signature,
+                    fresh.in_amount,
+                    fresh.out_amount,
+                    quote.other_amount_threshold
+                ));
+            }
+            let resubmitted = self.swap(wallet, &fresh, request.priority_fee_lamports).await?;
+            let expired = signature;
+            (signature, blockhash, landing_quote) = (resubmitted.signature, resubmitted.blockhash, fresh);
+            submissions += 1;
+            log::warn!("Swap {} expired unlanded; re-quoted and resubmitted as {}", expired, signature);
+        }
+    }
+
+    /// Poll the status of `signature` until it reaches `confirm_commitment`
+    /// or `blockhash` expires without it. Fails if the transaction errored
+    /// on-chain.
+    async fn poll_landing(&self, signature: &Signature, blockhash: &Hash) -> Result<Landing> {
+        let mut expiry_checked = Instant::now();
+        let mut expired = false;
+        loop {
+            match self.rpc.get_signature_statuses(&[*signature]).await {
+                Ok(resp) => match resp.value.into_iter().next().flatten() {
+                    Some(status) => {
+                        if let Some(err) = status.err {
+                            return Err(anyhow!("transaction {} failed: {}", signature, err));
+                        }
+                        if status.satisfies_commitment(self.confirm_commitment) {
+                            return Ok(Landing::Landed);
+                        }
+                    }
+                    // Still unseen one poll after the expiry: it never will be
+                    None if expired => return Ok(Landing::Expired),
+                    None if expiry_checked.elapsed() >= EXPIRY_CHECK_INTERVAL => {
+                        expiry_checked = Instant::now();
+                        match self.rpc.is_blockhash_valid(blockhash, CommitmentConfig::processed()).await {
+                            Ok(valid) => expired = !valid,
+                            Err(e) => log::warn!("is_blockhash_valid error for {}: {}", signature, e),
+                        }
+                    }
+                    None => {}
+                },
+                Err(e) => log::warn!("get_signature_statuses error for {}: {}", signature, e),
+            }
+            tokio::time::sleep(CONFIRM_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Lamports held by `owner`.
+    pub async fn sol_balance(&self, owner: &Pubkey) -> Result<u64> {
+        Ok(self.rpc.get_balance(owner).await?)
+    }
+
+    /// `owner`'s associated token account of `mint` and whether it exists.
+    async fn token_account(&self, owner: &Pubkey, mint: &str) -> Result<(Pubkey, bool)> {
+        let mint = Pubkey::from_str(mint).map_err(|e| anyhow!("invalid mint '{}': {}", mint, e))?;
+        // The mint's owner is its token program, which the address depends on
+        let program = self.rpc.get_account(&mint).await?.owner;
+        let account = associated_token_address(owner, &mint, &program);
+        let exists = self
+            .rpc
+            .get_account_with_commitment(&account, self.rpc.commitment())
+            .await?
+            .value
+            .is_some();
+        Ok((account, exists))
+    }
+
+    /// Atomic units of `mint` in `owner`'s associated token account, zero
+    /// when the account does not exist.
+    pub async fn token_account_balance(&self, owner: &Pubkey, mint: &str) -> Result<u64> {
+        let (account, exists) = self.token_account(owner, mint).await?;
+        if !exists {
+            return Ok(0);
+        }
+        let balance = self.rpc.get_token_account_balance(&account).await?;
+        Ok(balance.amount.parse()?)
+    }
+
+    /// Mints of `market` for which `owner` has no associated token account.
+    /// Wrapped SOL is left out when Jupiter wraps it in a temporary account.
+    pub async fn missing_token_accounts(&self, owner: &Pubkey, market: &MarketConfig) -> Result<Vec<String>> {
+        let mut missing = Vec::new();
+        for mint in self.required_token_accounts(market) {
+            if !self.token_account(owner, mint).await?.1 {
+                missing.push(mint.clone());
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Mints of `market` that need an associated token account of their own.
+    fn required_token_accounts<'a>(&self, market: &'a MarketConfig) -> Vec<&'a String> {
+        [&market.base_mint, &market.quote_mint]
+            .into_iter()
+            .filter(|mint| !(self.wrap_unwrap_sol && *mint == NATIVE_MINT))
+            .collect()
+    }
+
+    /// Atomic units of `mint` that `owner` can swap away. For wrapped SOL
+    /// this includes the native lamports above `sol_reserve` unless
+    /// `wrap_unwrap_sol` is off, as Jupiter then only spends the wrapped-SOL
+    /// account.
+    pub async fn spendable_balance(&self, owner: &Pubkey, mint: &str, sol_reserve: u64) -> Result<u64> {
+        let tokens = self.token_account_balance(owner, mint).await?;
+        if mint != NATIVE_MINT || !self.wrap_unwrap_sol {
+            return Ok(tokens);
+        }
+        let lamports = self.sol_balance(owner).await?;
+        Ok(tokens + lamports.saturating_sub(sol_reserve))
+    }
+
+    /// On-chain record of a confirmed swap on `market`: where it landed, the
+    /// network fee and the balance changes of `owner` in the market's mints.
+    pub async fn transaction_meta(
+        &self,
+        signature: &Signature,
+        owner: &Pubkey,
+        market: &MarketConfig,
+    ) -> Result<TxMeta> {
+        // getTransaction rejects "processed"; confirmed is the lowest it serves
+        let mut commitment = self.rpc.commitment();
+        if commitment == CommitmentConfig::processed() {
+            commitment = CommitmentConfig::confirmed();
+        }
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(commitment),
+            max_supported_transaction_version: Some(0),
+        };
+        let tx = self
+            .rpc
+            .get_transaction_with_config(signature, config)
+            .await?;
+        let meta = tx
+            .transaction
+            .meta
+            .ok_or_else(|| anyhow!("transaction {} has no status metadata", signature))?;
+        let owner = owner.to_string();
+        let delta = |mint: &str, decimals: u8| {
+            let pre = token_balance(&meta.pre_token_balances, &owner, mint);
+            let post = token_balance(&meta.post_token_balances, &owner, mint);
+            if pre.is_none() && post.is_none() && mint == NATIVE_MINT {
+                // SOL wrapped and unwrapped within the swap only shows in the
+                // fee payer's lamports, which also paid the fee
+                let lamports = |balances: &[u64]| balances.first().copied().unwrap_or(0) as i128;
+                let change = lamports(&meta.post_balances) - lamports(&meta.pre_balances) + meta.fee as i128;
+                return change as f64 / LAMPORTS_PER_SOL as f64;
+            }
+            (post.unwrap_or(0) as i128 - pre.unwrap_or(0) as i128) as f64 / 10f64.powi(decimals as i32)
+        };
+        Ok(TxMeta {
+            slot: tx.slot,
+            block_time: tx.block_time,
+            fee: meta.fee,
+            base_delta: delta(&market.base_mint, market.base_decimals),
+            quote_delta: delta(&market.quote_mint, market.quote_decimals),
+        })
+    }
+}
+
+/// Mint of wrapped SOL, which Jupiter unwraps into native lamports.
+pub const NATIVE_MINT: &str = "So11111111111111111111111111111111111111112";
+/// Program deriving a wallet's token account of each mint.
+const ASSOCIATED_TOKEN_PROGRAM: Pubkey = pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// Address of `owner`'s associated token account of `mint`, whose token
+/// program (SPL Token or Token-2022) is `token_program`.
+pub fn associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM,
+    )
+    .0
+}
+
+/// On-chain context of a landed transaction.
+#[derive(Debug, Clone, Copy)]
+pub struct TxMeta {
+    pub slot: u64,
+    /// Unix time in seconds, when the node knows it.
+    pub block_time: Option<i64>,
+    /// Network fee in lamports.
+    pub fee: u64,
+    /// Change of the wallet's base and quote balances, in tokens, network
+    /// fee excluded.
+    pub base_delta: f64,
+    pub quote_delta: f64,
+}
+
+impl TxMeta {
+    /// Price actually paid or received per base token, if base moved.
+    pub fn realized_price(&self) -> Option<f64> {
+        (self.base_delta != 0.0).then(|| (self.quote_delta / self.base_delta).abs())
+    }
+}
+
+/// Sum of `owner`'s token accounts of `mint` in atomic units, `None` when
+/// the transaction lists none.
+fn token_balance(
+    balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>,
+    owner: &str,
+    mint: &str,
+) -> Option<u64> {
+    let balances: Option<&Vec<UiTransactionTokenBalance>> = balances.as_ref().into();
+    balances?
+        .iter()
+        .filter(|b| b.mint == mint)
+        .filter(|b| Option::<&String>::from(b.owner.as_ref()).is_some_and(|o| o == owner))
+        .map(|b| b.ui_token_amount.amount.parse::<u64>().unwrap_or(0))
+        .fold(None, |total, amount| Some(total.unwrap_or(0) + amount))
+}
+
+/// Read the compute unit limit requested by an existing `SetComputeUnitLimit`
+/// instruction, if any.
+fn compute_unit_limit(message: &VersionedMessage) -> Option<u64> {
+    let program_id = compute_budget::id();
+    let keys = message.static_account_keys();
+    message.instructions().iter().find_map(|ix| {
+        let is_budget = keys.get(ix.program_id_index as usize) == Some(&program_id);
+        if is_budget && ix.data.len() >= 5 && ix.data[0] == SET_COMPUTE_UNIT_LIMIT {
+            Some(u32::from_le_bytes(ix.data[1..5].try_into().ok()?) as u64)
+        } else {
+            None
+        }
+    })
+}
+
+/// Insert a compute budget instruction at the front of `message`, replacing any
+/// existing instruction of the same kind (the runtime rejects duplicates).
+///
+/// If the compute budget program is not yet referenced it is appended to the
+/// static readonly keys, which shifts every index pointing into the address
+/// lookup table section by one.
+fn prepend_compute_budget_ix(message: &mut VersionedMessage, data: Vec<u8>) {
+    let program_id = compute_budget::id();
+    let (header, keys, instructions) = match message {
+        VersionedMessage::Legacy(m) => (&mut m.header, &mut m.account_keys, &mut m.instructions),
+        VersionedMessage::V0(m) => (&mut m.header, &mut m.account_keys, &mut m.instructions),
+    };
+
+    let program_idx = match keys.iter().position(|k| *k == program_id) {
+        Some(idx) => idx as u8,
+        None => {
+            let idx = keys.len() as u8;
+            keys.push(program_id);
+            header.num_readonly_unsigned_accounts += 1;
+            for ix in instructions.iter_mut() {
+                if ix.program_id_index >= idx {
+                    ix.program_id_index += 1;
+                }
+                for acc in ix.accounts.iter_mut() {
+                    if *acc >= idx {
+                        *acc += 1;
+                    }
+                }
+            }
+            idx
+        }
+    };
+
+    let discriminator = data.first().copied();
+    instructions.retain(|ix| ix.program_id_index != program_idx || ix.data.first().copied() != discriminator);
+    instructions.insert(
+        0,
+        CompiledInstruction {
+            program_id_index: program_idx,
+            accounts: vec![],
+            data,
+        },
+    );
+}
+
+fn to_atomic(amount: f64, decimals: u8) -> u64 {
+    (amount * 10f64.powi(decimals as i32)).round() as u64
+}
+
+/// Jupiter encodes token amounts as decimal strings.
+fn amount_field(raw: &Value, key: &str) -> Result<u64> {
+    raw.get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("quote response missing '{}'", key))?
+        .parse()
+        .with_context(|| format!("quote field '{}' is not an integer", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn client(extra: &str) -> (SwapClient, BotConfig) {
+        let cfg = test_config(extra);
+        let rpc = Arc::new(RpcClient::new(cfg.anchor_cluster.clone()));
+        (SwapClient::from_config(&cfg, rpc).unwrap(), cfg)
+    }
+
+    fn quote() -> Quote {
+        Quote {
+            in_amount: 1_000_000_000,
+            out_amount: 150_000_000,
+            other_amount_threshold: 149_250_000,
+            swap_mode: SwapMode::ExactIn,
+            input_decimals: 9,
+            output_decimals: 6,
+            price_impact_pct: 0.0,
+            platform_fee: 0,
+            fetched_at: Instant::now(),
+            raw: json!({ "inputMint": NATIVE_MINT }),
+        }
+    }
+
+    #[test]
+    fn swap_body_wraps_sol_and_checks_accounts_by_default() {
+        let (client, _) = client("");
+        let user = Pubkey::new_unique();
+        let body = client.swap_body(&quote(), &user, Some(5_000));
+        assert_eq!(body["wrapAndUnwrapSol"], json!(true));
+        assert_eq!(body["skipUserAccountsRpcCalls"], json!(false));
+        assert_eq!(body["userPublicKey"], json!(user.to_string()));
+        assert_eq!(body["quoteResponse"]["inputMint"], json!(NATIVE_MINT));
+        assert_eq!(body["prioritizationFeeLamports"], json!(5_000));
+    }
+
+    #[test]
+    fn swap_body_follows_wrap_and_ata_settings() {
+        let (client, _) = client("wrap_unwrap_sol = false\nauto_create_ata = false");
+        let body = client.swap_body(&quote(), &Pubkey::new_unique(), None);
+        assert_eq!(body["wrapAndUnwrapSol"], json!(false));
+        assert_eq!(body["skipUserAccountsRpcCalls"], json!(true));
+        assert!(body.get("prioritizationFeeLamports").is_none());
+    }
+
+    #[test]
+    fn wrapped_sol_needs_no_token_account() {
+        let (wrapping, cfg) = client("");
+        let market = &cfg.markets[0];
+        assert_eq!(market.base_mint, NATIVE_MINT);
+        assert_eq!(wrapping.required_token_accounts(market), vec![&market.quote_mint]);
+
+        let (unwrapped, cfg) = client("wrap_unwrap_sol = false");
+        let market = &cfg.markets[0];
+        assert_eq!(
+            unwrapped.required_token_accounts(market),
+            vec![&market.base_mint, &market.quote_mint]
+        );
+    }
+
+    #[test]
+    fn requote_must_fit_the_first_bound() {
+        // ExactIn: the first quote accepts no less than 149.25 USDC out
+        let first = quote();
+        let worse = Quote { out_amount: 149_000_000, ..quote() };
+        let slipped = Quote { out_amount: 149_500_000, ..quote() };
+        assert!(slipped.within_bound_of(&first));
+        assert!(!worse.within_bound_of(&first));
+
+        // ExactOut: the first quote spends no more than its threshold
+        let first = Quote { swap_mode: SwapMode::ExactOut, other_amount_threshold: 1_005_000_000, ..quote() };
+        assert!(Quote { in_amount: 1_004_000_000, ..quote() }.within_bound_of(&first));
+        assert!(!Quote { in_amount: 1_006_000_000, ..quote() }.within_bound_of(&first));
     }
 }
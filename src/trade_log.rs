@@ -0,0 +1,71 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader};
+
+/// One row of the CSV trade log.
+#[derive(Debug, Default, Serialize)]
+pub struct TradeRecord {
+    pub timestamp: String,
+    pub side: String,
+    pub price: f64,
+    pub size: f64,
+    pub signature: String,
+    pub status: String,
+    pub pnl: f64,
+    /// Market the order was placed on (see `MarketConfig::name`).
+    pub market: String,
+    /// Where a confirmed swap landed and what it cost on-chain; empty for
+    /// paper fills and failed orders, or when the transaction could not be read.
+    pub slot: Option<u64>,
+    pub block_time: Option<i64>,
+    pub fee_lamports: Option<u64>,
+    /// Change of the wallet's base and quote balances, in tokens.
+    pub base_delta: Option<f64>,
+    pub quote_delta: Option<f64>,
+}
+
+/// Append-only CSV log with one row per order.
+pub struct TradeLog {
+    writer: csv::Writer<File>,
+}
+
+impl TradeLog {
+    /// Open `path` for appending; the header row is only written when the file
+    /// is new or empty. A file written with other columns, by an older
+    /// version, is moved to `<path>.old` first so rows never misalign.
+    pub fn open(path: &str) -> Result<Self> {
+        if let Ok(file) = File::open(path) {
+            let mut first = String::new();
+            BufReader::new(file).read_line(&mut first)?;
+            let first = first.trim_end();
+            if !first.is_empty() && first != header()? {
+                let old = format!("{}.old", path);
+                log::warn!("Trade log '{}' has outdated columns; moving it to '{}'", path, old);
+                fs::rename(path, &old)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let writer = csv::WriterBuilder::new()
+            .has_headers(is_new)
+            .from_writer(file);
+        Ok(Self { writer })
+    }
+
+    /// Write a row and flush it immediately so a crash cannot lose it.
+    pub fn write(&mut self, record: &TradeRecord) -> Result<()> {
+        self.writer.serialize(record)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Header row of the current `TradeRecord` columns.
+fn header() -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(TradeRecord::default())?;
+    let bytes = writer.into_inner().map_err(|e| anyhow::anyhow!("{}", e.error()))?;
+    let text = String::from_utf8(bytes)?;
+    Ok(text.lines().next().unwrap_or_default().to_string())
+}
@@ -1,162 +1,2325 @@
-use crate::config::BotConfig;
-use crate::data::TradeMsg;
-use crate::grpc_stream::GrpcStream;
-use crate::strategy::{OrderSide, Strategy};
-use anyhow::Result;
+use crate::config::{BotConfig, DataSource, LabelHorizon, MarketConfig, OrderType};
+use crate::data::{self, LaserStream, TradeMsg};
+use crate::dataset::{DatasetFile, Sample};
+use crate::escalation::{Escalation, EscalationState};
+use crate::features::RollingFeatures;
+use crate::fill::{self, FillResult, PaperCosts};
+use crate::grpc_stream::{GrpcStream, TradeStream};
+use crate::metrics::{self, Metrics};
+use crate::model::{self, Model};
+use crate::onchain::{FillRecorder, OnchainFill};
+use crate::performance::Performance;
+use crate::state::{MarketState, StateFile, TraderState};
+use crate::status::{self, HaltFlag, PendingModel, SharedStatus, Status};
+use crate::strategy::{
+    Action, CompositeStrategy, OrderSide, PositionState, Strategy, DEFAULT_IMBALANCE_THRESHOLD, DEFAULT_MODEL_WEIGHT,
+    DEFAULT_SIGNAL_THRESHOLD,
+};
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
 use futures_util::StreamExt;
-use std::pin::Pin;
-use crate::swap_client::SwapClient;
-use ndarray::Array2;
+use crate::swap_client::{
+    Quote, QuoteRequest, SwapClient, SwapError, SwapMode, TxMeta, MIN_CONFIRM_WAIT, NATIVE_MINT,
+};
+use crate::recorder::{self, Recorder};
+use crate::schedule::{self, TradingWindow};
+use crate::trade_log::{TradeLog, TradeRecord};
+use ndarray::{s, Array2};
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
-    signature::{Keypair, Signature},
+    native_token::{lamports_to_sol, sol_to_lamports},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
 };
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Trades buffered between the feed and the trading loop, by default.
+const DEFAULT_INGEST_QUEUE_SIZE: usize = 1024;
+/// Minimum delay between two checks of the kill switch file.
+const KILL_SWITCH_POLL: Duration = Duration::from_secs(1);
+/// Pending limit orders kept per market; the oldest is dropped beyond it.
+const MAX_PENDING_LIMITS: usize = 4;
+/// New samples between two retrains, by default.
+const DEFAULT_RETRAIN_EVERY: usize = 500;
+/// Training samples a retrain needs, by default.
+const DEFAULT_MIN_SAMPLES_TO_TRAIN: usize = 10;
+/// Price impact, in percent, above which quotes are refused by default.
+const DEFAULT_MAX_PRICE_IMPACT_PCT: f64 = 1.0;
+/// Wide-spread skips of a market between two summary log lines.
+const WIDE_SPREAD_LOG_EVERY: u64 = 100;
+/// SOL kept in the wallet for network fees, by default.
+const DEFAULT_MIN_SOL_BALANCE: f64 = 0.01;
+
+/// Strategy and book-keeping of one traded market.
+struct MarketSlot {
+    /// Market name, as tagged on its trades.
+    name: String,
+    /// Mints and decimals swapped on Jupiter.
+    config: MarketConfig,
+    strategy: CompositeStrategy,
+    /// Whether the strategy runs the shared, periodically retrained model.
+    shared_model: bool,
+    /// Net base-asset holdings from confirmed fills and their entry price.
+    position: PositionState,
+    /// Cash PnL in quote units, net of fees.
+    pnl: f64,
+    /// Fees deducted from `pnl` so far; `pnl + fees_paid` is the gross PnL.
+    fees_paid: f64,
+    /// EMAs and volatility of this market's recent prices.
+    features: RollingFeatures,
+    /// Samples waiting for `label_horizon` to elapse, oldest first.
+    pending: VecDeque<PendingSample>,
+    /// Trades seen on this market, to measure tick horizons.
+    ticks: u64,
+    last_price: Option<f64>,
+    /// When the last order was submitted (or paper signal taken).
+    last_order_at: Option<Instant>,
+    /// Limit entries waiting for a marketable quote, oldest first.
+    limits: VecDeque<LimitOrder>,
+    /// Base amounts of sent buys and sells still awaiting confirmation.
+    inflight_buys: f64,
+    inflight_sells: f64,
+    /// Slippage and priority fee escalation after failed confirmations.
+    escalation: EscalationState,
+    /// Entry signals skipped for a wide spread since startup.
+    wide_spread_skips: u64,
+}
+
+/// Outcome of a live order confirmed in the background.
+struct Confirmation {
+    idx: usize,
+    side: OrderSide,
+    amount: f64,
+    price: f64,
+    signature: Signature,
+    result: Result<FillResult>,
+}
+
+/// Entry swapped only once a quote is at or better than `limit_price`.
+#[derive(Debug, Clone, Copy)]
+struct LimitOrder {
+    side: OrderSide,
+    amount: f64,
+    limit_price: f64,
+    /// Cancelled once reached; good till cancelled when `None`.
+    expires_at: Option<Instant>,
+}
+
+/// Features of a trade, kept until its forward return can be labelled.
+struct PendingSample {
+    features: Vec<f64>,
+    price: f64,
+    ts: i64,
+    tick: u64,
+}
+
+impl MarketSlot {
+    /// Cash PnL plus the position marked at the last seen price.
+    fn equity(&self) -> f64 {
+        self.pnl + self.position.size * self.last_price.unwrap_or(0.0)
+    }
+
+    /// Base amount of sent orders on `side` still awaiting confirmation.
+    fn inflight(&self, side: OrderSide) -> f64 {
+        match side {
+            OrderSide::Buy => self.inflight_buys,
+            OrderSide::Sell => self.inflight_sells,
+        }
+    }
+
+    fn inflight_mut(&mut self, side: OrderSide) -> &mut f64 {
+        match side {
+            OrderSide::Buy => &mut self.inflight_buys,
+            OrderSide::Sell => &mut self.inflight_sells,
+        }
+    }
+
+    /// PnL locked in by the volume closed so far, net of every fee paid.
+    /// Cash PnL counts the cost of an open position as a loss; adding it
+    /// back at its entry price leaves what closing fills have realized.
+    fn realized_pnl(&self) -> f64 {
+        self.pnl + self.position.size * self.position.entry_price
+    }
+
+    /// The open position marked at the last seen price against its entry.
+    fn unrealized_pnl(&self) -> f64 {
+        match self.last_price {
+            Some(price) if self.position.size != 0.0 => self.position.size * (price - self.position.entry_price),
+            _ => 0.0,
+        }
+    }
+}
 
 pub struct Trader {
     cfg: BotConfig,
-    strategy: Strategy,
+    markets: Vec<MarketSlot>,
+    /// Market name to index into `markets`.
+    market_index: HashMap<String, usize>,
     stream: GrpcStream,
-    rpc: RpcClient,
-    swap_client: SwapClient,
-    wallet: Arc<Keypair>,
-    pnl: Arc<Mutex<f64>>,
+    /// Silence on the feed after which it is reconnected.
+    feed_stale: Duration,
+    ingest_queue_size: usize,
+    swap_client: Arc<SwapClient>,
+    /// Wallets signing live orders in turn; positions and PnL are aggregated
+    /// over all of them.
+    wallets: Vec<Arc<Keypair>>,
+    /// Index into `wallets` of the next order's signer.
+    next_wallet: usize,
     paper_mode: bool,
-    dataset: Arc<Mutex<Vec<(Vec<f64>, f64)>>>,
-    last_features: Option<Vec<f64>>,
-    last_price: Option<f64>,
+    /// Training window, oldest sample first.
+    dataset: Arc<Mutex<VecDeque<Sample>>>,
+    label_horizon: LabelHorizon,
+    /// Cap on `dataset`; the oldest samples are evicted beyond it.
+    max_dataset_samples: Option<usize>,
+    retrain_every: usize,
+    min_samples_to_train: usize,
+    /// Sample age, in samples, at which a training weight halves.
+    sample_half_life: Option<f64>,
+    dataset_file: Option<DatasetFile>,
+    /// Samples whose append to `dataset_file` failed, retried on shutdown.
+    unpersisted: Vec<Sample>,
+    /// Samples ever added to `dataset`, including evicted ones, and its value
+    /// at the last training. Their difference drives periodic retraining.
+    samples_seen: usize,
     last_trained: usize,
+    /// Share of the newest samples held out when retraining.
+    validation_fraction: f64,
+    min_validation_accuracy: f64,
     trade_amount: f64,
+    max_trade_amount: f64,
     slippage_bps: u64,
+    escalation: Option<Escalation>,
+    max_quote_deviation_bps: u64,
+    max_price_impact_pct: f64,
+    min_book_depth: Option<f64>,
+    max_spread_bps: Option<f64>,
+    order_type: OrderType,
+    limit_offset_bps: u64,
+    limit_expiry: Option<Duration>,
+    /// Fixed fee charged on each paper fill.
+    paper_fee: f64,
     confirm_secs: u64,
+    /// Close open positions at market on a live shutdown.
+    flatten_on_shutdown: bool,
+    /// Permits for orders awaiting confirmation, `max_inflight_orders` in all.
+    order_slots: Arc<Semaphore>,
+    max_inflight_orders: usize,
+    /// Confirmation tasks report fills here; they are applied by the trading
+    /// loop so PnL and positions only ever change in one place.
+    confirm_tx: mpsc::UnboundedSender<Confirmation>,
+    confirm_rx: mpsc::UnboundedReceiver<Confirmation>,
+    max_position: f64,
+    max_notional_per_order: Option<f64>,
+    clamp_to_max_notional: bool,
+    /// Lamports reserved for network fees.
+    min_sol_lamports: u64,
+    check_balance_before_orders: bool,
+    min_order_interval: Duration,
+    max_daily_loss: Option<f64>,
+    /// UTC day the daily loss is measured for, and the realized PnL at its
+    /// start.
+    trading_day: NaiveDate,
+    day_start_realized: f64,
+    /// Set by the daily loss circuit breaker; blocks new orders.
+    halted: bool,
+    /// Emergency stop from `/halt`; blocks every order until `/resume`.
+    manual_halt: HaltFlag,
+    kill_switch_path: Option<String>,
+    trading_hours: Vec<TradingWindow>,
+    /// Whether the last check found the schedule open.
+    schedule_open: bool,
+    /// Whether the kill switch file was present at the last check, and when
+    /// that was.
+    kill_switch: bool,
+    kill_switch_checked: Option<Instant>,
+    /// Fills since the start of `trading_day`.
+    trades_today: u64,
+    /// Sharpe, Sortino and drawdown since startup.
+    performance: Performance,
+    /// Time (ms) of the last fill and of the last trade from the feed.
+    last_fill_ts: Option<i64>,
+    last_market_ts: Option<i64>,
+    status: SharedStatus,
+    /// Model queued by `/reload-model` for the shared-model markets.
+    pending_model: PendingModel,
+    metrics: Arc<Metrics>,
+    trade_log: Option<TradeLog>,
+    recorder: Option<Recorder>,
+    /// Sends confirmed fills to `anchor_program_id` when enabled.
+    fill_recorder: Option<Arc<FillRecorder>>,
+    state_file: Option<StateFile>,
+    /// Cancelled on shutdown to stop background server tasks.
+    shutdown_token: CancellationToken,
+    server_tasks: Vec<JoinHandle<()>>,
 }
 
 impl Trader {
     pub async fn new(cfg: BotConfig) -> Result<Self> {
-        let model = crate::model::MlModel::load(&cfg.model_path)?;
-        let strategy = Strategy::new(model, 0.55);
+        let shared = model::load(&cfg.model_path)?;
+        let mut markets = Vec::with_capacity(cfg.markets.len());
+        let mut market_index = HashMap::new();
+        for market in &cfg.markets {
+            market_index.insert(market.name.clone(), markets.len());
+            markets.push(Self::market_slot(&cfg, market, &shared)?);
+        }
+        if markets.is_empty() {
+            return Err(anyhow!("no markets configured"));
+        }
 
         let stream = GrpcStream::from_config(&cfg);
-        let rpc = RpcClient::new(cfg.anchor_cluster.clone());
-        let swap_client = SwapClient::new(cfg.jupiter_api_url.clone());
-        let wallet = Arc::new(Keypair::from_bytes(&bs58::decode(&cfg.wallet_keypair).into_vec()?)?);
+        let wallets: Vec<_> = cfg.wallets()?.into_iter().map(Arc::new).collect();
 
-        let paper_mode = cfg.anchor_cluster.contains("devnet") || cfg.anchor_program_id.is_empty();
+        let (paper_mode, reason) = match cfg.paper_mode {
+            Some(true) => (true, "forced by paper_mode / --dry-run"),
+            Some(false) => (false, "paper_mode = false"),
+            None if cfg.anchor_cluster.contains("devnet") => (true, "devnet cluster"),
+            None if cfg.anchor_program_id.is_empty() => (true, "no anchor_program_id"),
+            None => (false, "mainnet cluster with a program id"),
+        };
+        if paper_mode {
+            log::warn!("*** PAPER MODE ({}): orders are simulated, no transaction will be sent ***", reason);
+        } else {
+            log::warn!("*** LIVE MODE ({}): orders are sent to {} ***", reason, cfg.anchor_cluster);
+        }
 
         // trading parameters with defaults
         let trade_amount = cfg.trade_amount.unwrap_or(1.0);
+        let max_trade_amount = cfg.max_trade_amount.unwrap_or(trade_amount).max(trade_amount);
         let slippage_bps = cfg.slippage_bps.unwrap_or(50);
-        let confirm_secs = cfg.tx_confirm_secs.unwrap_or(30);
+        let max_quote_deviation_bps = cfg.max_quote_deviation_bps.unwrap_or(100);
+        let paper_fee = cfg.paper_fee.unwrap_or(0.0);
+        let confirm_secs = cfg.tx_confirm_secs.unwrap_or(MIN_CONFIRM_WAIT.as_secs());
+        let max_position = cfg.max_position.unwrap_or(10.0);
+        let max_daily_loss = cfg.max_daily_loss;
+        let min_order_interval = Duration::from_millis(cfg.min_order_interval_ms.unwrap_or(0));
+        let feed_stale = Duration::from_secs(cfg.feed_stale_secs.unwrap_or(120));
+        let validation_fraction = cfg.validation_fraction.unwrap_or(0.2);
+        let min_validation_accuracy = cfg.min_validation_accuracy.unwrap_or(0.5);
 
-        Ok(Self {
-            cfg,
-            strategy,
+        // Reload samples collected by previous runs
+        let (dataset, dataset_file) = match &cfg.dataset_path {
+            Some(path) => {
+                let file = DatasetFile::open(path)?;
+                let samples = file.load()?;
+                log::info!("Loaded {} samples from {}", samples.len(), path);
+                (samples, Some(file))
+            }
+            None => (Vec::new(), None),
+        };
+        let samples_seen = dataset.len();
+        let mut dataset = VecDeque::from(dataset);
+        if let Some(max) = cfg.max_dataset_samples {
+            let excess = dataset.len().saturating_sub(max);
+            dataset.drain(..excess);
+        }
+
+        let trade_log = cfg.trade_log_path.as_deref().map(TradeLog::open).transpose()?;
+        let recorder = cfg.record_path.as_deref().map(Recorder::create).transpose()?;
+        let state_file = cfg.state_path.as_deref().map(StateFile::new);
+
+        let metrics = Arc::new(Metrics::new()?);
+        let shutdown_token = CancellationToken::new();
+        let mut server_tasks = Vec::new();
+        let status = SharedStatus::default();
+        let pending_model = PendingModel::default();
+        let manual_halt = HaltFlag::default();
+        // Endpoints configured on the same port share one server
+        let mut servers: BTreeMap<u16, (IpAddr, Vec<metrics::Handler>)> = BTreeMap::new();
+        if let Some(port) = cfg.metrics_port {
+            let (_, handlers) = servers.entry(port).or_insert((Ipv4Addr::UNSPECIFIED.into(), Vec::new()));
+            handlers.push(metrics::handler(metrics.clone()));
+        }
+        if let Some(port) = cfg.status_port {
+            // The unauthenticated admin endpoints pick the address of a shared server
+            let admin_ip = cfg.status_bind_address.unwrap_or(Ipv4Addr::LOCALHOST.into());
+            let (ip, handlers) = servers.entry(port).or_insert((admin_ip, Vec::new()));
+            *ip = admin_ip;
+            handlers.push(status::handler(status.clone()));
+            handlers.push(status::reload_handler(
+                cfg.model_path.clone(),
+                cfg.feature_spec().names(),
+                pending_model.clone(),
+            ));
+            handlers.push(status::halt_handler(manual_halt.clone()));
+        }
+        for (port, (ip, handlers)) in servers {
+            let addr = SocketAddr::new(ip, port);
+            server_tasks.push(metrics::spawn_server(handlers, addr, shutdown_token.clone()));
+        }
+
+        let rpc = Arc::new(RpcClient::new_with_timeout_and_commitment(
+            cfg.anchor_cluster.clone(),
+            cfg.http_timeout(),
+            cfg.commitment.config(),
+        ));
+        let swap_client =
+            Arc::new(SwapClient::from_config(&cfg, rpc)?.with_throttle_counter(metrics.jupiter_throttled.clone()));
+        // Records are signed by the first wallet, whatever wallet filled
+        let fill_recorder = if paper_mode {
+            None
+        } else {
+            FillRecorder::from_config(&cfg, wallets[0].clone())?.map(Arc::new)
+        };
+        let max_inflight_orders = cfg.max_inflight_orders.unwrap_or(1);
+        let (confirm_tx, confirm_rx) = mpsc::unbounded_channel();
+
+        let mut trader = Self {
+            markets,
+            market_index,
             stream,
-            rpc,
+            feed_stale,
+            ingest_queue_size: cfg.ingest_queue_size.unwrap_or(DEFAULT_INGEST_QUEUE_SIZE),
             swap_client,
-            wallet,
-            pnl: Arc::new(Mutex::new(0.0)),
+            wallets,
+            next_wallet: 0,
             paper_mode,
-            dataset: Arc::new(Mutex::new(Vec::new())),
-            last_features: None,
-            last_price: None,
-            last_trained: 0,
+            dataset: Arc::new(Mutex::new(dataset)),
+            dataset_file,
+            unpersisted: Vec::new(),
+            max_dataset_samples: cfg.max_dataset_samples,
+            retrain_every: cfg.retrain_every.unwrap_or(DEFAULT_RETRAIN_EVERY),
+            min_samples_to_train: cfg.min_samples_to_train.unwrap_or(DEFAULT_MIN_SAMPLES_TO_TRAIN),
+            sample_half_life: cfg.sample_half_life,
+            label_horizon: cfg.label_horizon.unwrap_or_default(),
+            samples_seen,
+            // The saved model already covers the reloaded samples
+            last_trained: samples_seen,
+            validation_fraction,
+            min_validation_accuracy,
             trade_amount,
+            max_trade_amount,
             slippage_bps,
+            escalation: cfg
+                .slippage_escalation
+                .as_ref()
+                .map(|esc| Escalation::new(esc, slippage_bps, cfg.priority_fee_lamports)),
+            max_quote_deviation_bps,
+            max_price_impact_pct: cfg.max_price_impact_pct.unwrap_or(DEFAULT_MAX_PRICE_IMPACT_PCT),
+            min_book_depth: cfg.min_book_depth,
+            max_spread_bps: cfg.max_spread_bps,
+            order_type: cfg.order_type,
+            limit_offset_bps: cfg.limit_offset_bps.unwrap_or(0),
+            limit_expiry: cfg.limit_expiry_ms.map(Duration::from_millis),
+            paper_fee,
             confirm_secs,
+            flatten_on_shutdown: cfg.flatten_on_shutdown.unwrap_or(false),
+            order_slots: Arc::new(Semaphore::new(max_inflight_orders)),
+            max_inflight_orders,
+            confirm_tx,
+            confirm_rx,
+            max_position,
+            max_notional_per_order: cfg.max_notional_per_order,
+            clamp_to_max_notional: cfg.clamp_to_max_notional.unwrap_or(false),
+            min_sol_lamports: sol_to_lamports(cfg.min_sol_balance.unwrap_or(DEFAULT_MIN_SOL_BALANCE)),
+            check_balance_before_orders: cfg.check_balance_before_orders.unwrap_or(false),
+            min_order_interval,
+            max_daily_loss,
+            trading_day: Utc::now().date_naive(),
+            day_start_realized: 0.0,
+            halted: false,
+            manual_halt,
+            kill_switch_path: cfg.kill_switch_path.clone(),
+            trading_hours: cfg.trading_hours.clone(),
+            schedule_open: true,
+            kill_switch: false,
+            kill_switch_checked: None,
+            trades_today: 0,
+            performance: Performance::default(),
+            last_fill_ts: None,
+            last_market_ts: None,
+            status,
+            pending_model,
+            metrics,
+            trade_log,
+            recorder,
+            fill_recorder,
+            state_file,
+            shutdown_token,
+            server_tasks,
+            cfg,
+        };
+        trader.restore_state();
+        if let Some(path) = trader.cfg.warmup_data.clone() {
+            trader.warm_up(&path).await?;
+        }
+        if !trader.paper_mode {
+            trader.check_funds().await?;
+        }
+        Ok(trader)
+    }
+
+    /// Label a `--record` recording with the configured horizon, put its
+    /// samples ahead of the loaded dataset and train on them right away, so
+    /// the bot starts with a model fitted to the recorded market.
+    async fn warm_up(&mut self, path: &str) -> Result<()> {
+        let rows = recorder::read(path)?;
+        let width = self.cfg.feature_spec().width();
+        if let Some((trade, features)) = rows.iter().find(|(_, f)| f.len() != width) {
+            return Err(anyhow!(
+                "warmup_data '{}' holds {} features per trade (first at ts {}), the feature pipeline produces {}; \
+                 record it again with the current ema_windows, volatility_window and features",
+                path,
+                features.len(),
+                trade.ts,
+                width
+            ));
+        }
+        let samples = label_recording(rows, self.label_horizon);
+        log::info!("Warming up on {} labelled samples from {}", samples.len(), path);
+        let mut dataset = self.dataset.lock().await;
+        // Recorded samples are older than the ones collected live
+        for sample in samples.into_iter().rev() {
+            dataset.push_front(sample);
+        }
+        if let Some(max) = self.max_dataset_samples {
+            let excess = dataset.len().saturating_sub(max);
+            dataset.drain(..excess);
+        }
+        drop(dataset);
+        self.train_model().await
+    }
+
+    /// Run `check_wallet_funds` on every wallet.
+    async fn check_funds(&self) -> Result<()> {
+        for wallet in &self.wallets {
+            self.check_wallet_funds(wallet.pubkey()).await?;
+        }
+        Ok(())
+    }
+
+    /// Fail unless `owner` holds `min_sol_balance` for fees and, on every
+    /// market, the token accounts swaps need when Jupiter may not create
+    /// them and enough quote token for a `trade_amount` buy at the current
+    /// quote. A base balance too small for a `trade_amount` sell only warns,
+    /// as the bot may still buy first.
+    async fn check_wallet_funds(&self, owner: Pubkey) -> Result<()> {
+        let lamports = self.swap_client.sol_balance(&owner).await?;
+        if lamports < self.min_sol_lamports {
+            return Err(anyhow!(
+                "wallet {} holds {} SOL, below min_sol_balance {} SOL needed for fees",
+                owner,
+                lamports_to_sol(lamports),
+                lamports_to_sol(self.min_sol_lamports)
+            ));
+        }
+        let auto_create_ata = self.cfg.auto_create_ata.unwrap_or(true);
+        for market in &self.markets {
+            let cfg = &market.config;
+            if !auto_create_ata {
+                let missing = self.swap_client.missing_token_accounts(&owner, cfg).await?;
+                if !missing.is_empty() {
+                    return Err(anyhow!(
+                        "{}: wallet {} has no token account for mint(s) {}; create them or set auto_create_ata = true",
+                        market.name,
+                        owner,
+                        missing.join(", ")
+                    ));
+                }
+            }
+            let quote = self
+                .swap_client
+                .quote(cfg, self.trade_amount, Some(false), SwapMode::ExactOut, self.slippage_bps)
+                .await?;
+            // For ExactOut the threshold is the most the swap may spend
+            let needed = quote.other_amount_threshold;
+            let held = self
+                .swap_client
+                .spendable_balance(&owner, &cfg.quote_mint, self.min_sol_lamports)
+                .await?;
+            let units = |atomic: u64, decimals: u8| atomic as f64 / 10f64.powi(decimals as i32);
+            if held < needed {
+                return Err(anyhow!(
+                    "{}: wallet {} holds {} of quote mint {}, a {} buy needs up to {}",
+                    market.name,
+                    owner,
+                    units(held, cfg.quote_decimals),
+                    cfg.quote_mint,
+                    self.trade_amount,
+                    units(needed, cfg.quote_decimals)
+                ));
+            }
+            let base = self
+                .swap_client
+                .spendable_balance(&owner, &cfg.base_mint, self.min_sol_lamports)
+                .await?;
+            if units(base, cfg.base_decimals) < self.trade_amount {
+                log::warn!(
+                    "{}: wallet {} holds {} base, less than trade_amount {}; sells are limited until it buys",
+                    market.name,
+                    owner,
+                    units(base, cfg.base_decimals),
+                    self.trade_amount
+                );
+            }
+            log::info!(
+                "{}: wallet {} holds {} quote and {} base",
+                market.name,
+                owner,
+                units(held, cfg.quote_decimals),
+                units(base, cfg.base_decimals)
+            );
+        }
+        Ok(())
+    }
+
+    /// Next wallet in the rotation, skipping those that cannot pay the input
+    /// of `quote` on market `idx` when `check_balance_before_orders` is set.
+    /// `None`, logging the skip, when no wallet can.
+    async fn next_signer(&mut self, idx: usize, side: OrderSide, quote: &Quote) -> Result<Option<Arc<Keypair>>> {
+        let count = self.wallets.len();
+        for offset in 0..count {
+            let slot = (self.next_wallet + offset) % count;
+            let wallet = self.wallets[slot].clone();
+            if self.check_balance_before_orders && !self.can_fund(idx, side, quote, &wallet.pubkey()).await? {
+                continue;
+            }
+            self.next_wallet = (slot + 1) % count;
+            return Ok(Some(wallet));
+        }
+        log::warn!(
+            "{}: skipping {:?}, no wallet holds the input the swap may spend",
+            self.markets[idx].name,
+            side
+        );
+        Ok(None)
+    }
+
+    /// Whether `owner` can pay the input of `quote` on market `idx`.
+    async fn can_fund(&self, idx: usize, side: OrderSide, quote: &Quote, owner: &Pubkey) -> Result<bool> {
+        let cfg = &self.markets[idx].config;
+        let mint = match side {
+            OrderSide::Buy => &cfg.quote_mint,
+            OrderSide::Sell => &cfg.base_mint,
+        };
+        let held = self
+            .swap_client
+            .spendable_balance(owner, mint, self.min_sol_lamports)
+            .await?;
+        // The most the swap may spend: the threshold for ExactOut
+        let needed = match quote.swap_mode {
+            SwapMode::ExactIn => quote.in_amount,
+            SwapMode::ExactOut => quote.other_amount_threshold,
+        };
+        if held < needed {
+            log::debug!(
+                "{}: {:?} not funded by {}, which holds {} atomic units of {} but the swap may spend {}",
+                self.markets[idx].name,
+                side,
+                owner,
+                held,
+                mint,
+                needed
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Restore PnL and positions saved by a previous run. A state written in
+    /// the other mode or by other wallets is left untouched: it is neither
+    /// restored nor overwritten by this run.
+    fn restore_state(&mut self) {
+        let Some(file) = &self.state_file else {
+            return;
+        };
+        let state = file.load();
+        if let Some(reason) = state.mismatch(self.paper_mode, &self.wallet_keys()) {
+            log::warn!("Not restoring state file {}: {}; this run's state is not saved", file.path(), reason);
+            self.state_file = None;
+            return;
+        }
+        for market in &mut self.markets {
+            let Some(saved) = state.markets.get(&market.name) else {
+                continue;
+            };
+            market.pnl = saved.pnl;
+            market.fees_paid = saved.fees_paid;
+            market.position = PositionState {
+                size: saved.position,
+                entry_price: saved.entry_price,
+            };
+            // Mark the position at entry until the feed delivers a price
+            if saved.position != 0.0 {
+                market.last_price = Some(saved.entry_price);
+            }
+            log::info!(
+                "{}: restored PnL {}, position {} at {}",
+                market.name,
+                saved.pnl,
+                saved.position,
+                saved.entry_price
+            );
+            self.metrics.position.with_label_values(&[&market.name]).set(saved.position);
+        }
+        self.metrics.pnl.set(self.total_pnl());
+        self.metrics.realized_pnl.set(self.total_realized_pnl());
+        // Restored PnL is not a loss of the current day
+        self.day_start_realized = self.total_realized_pnl();
+    }
+
+    /// Write every market's PnL and position to the state file.
+    fn save_state(&self) {
+        let Some(file) = &self.state_file else {
+            return;
+        };
+        let state = TraderState {
+            paper_mode: Some(self.paper_mode),
+            wallets: self.wallet_keys(),
+            markets: self
+                .markets
+                .iter()
+                .map(|m| {
+                    let saved = MarketState {
+                        pnl: m.pnl,
+                        fees_paid: m.fees_paid,
+                        position: m.position.size,
+                        entry_price: m.position.entry_price,
+                    };
+                    (m.name.clone(), saved)
+                })
+                .collect(),
+        };
+        if let Err(e) = file.save(&state) {
+            log::error!("Failed to save state: {:#}", e);
+        }
+    }
+
+    fn wallet_keys(&self) -> Vec<String> {
+        self.wallets.iter().map(|w| w.pubkey().to_string()).collect()
+    }
+
+    /// Build the strategy of `market`, on its own model if it has one and on
+    /// the `shared` model otherwise.
+    fn market_slot(cfg: &BotConfig, market: &MarketConfig, shared: &Model) -> Result<MarketSlot> {
+        let mut model = match &market.model_path {
+            Some(path) => model::load(path)?,
+            None => shared.clone(),
+        };
+        let spec = cfg.feature_spec();
+        if let Err(e) = model::check_features(&model, &spec.names()) {
+            log::warn!("{}: {:#}; ignoring it until retrained", market.name, e);
+            model = model::untrained();
+        }
+        let signal_threshold = cfg.signal_threshold.unwrap_or(DEFAULT_SIGNAL_THRESHOLD);
+        let mut strategy = Strategy::new(model, signal_threshold)
+            .with_exits(cfg.stop_loss_bps, cfg.take_profit_bps);
+        if let Some(exit) = cfg.exit_threshold {
+            strategy = strategy.with_exit_threshold(exit);
+        }
+        strategy = strategy.with_cold_start(cfg.cold_start_strategy);
+        if strategy.in_cold_start() {
+            log::info!(
+                "{}: model is untrained; trading {:?} cold-start rules until the first retrain",
+                market.name,
+                cfg.cold_start_strategy
+            );
+        }
+        let strategy = CompositeStrategy::new(
+            strategy,
+            cfg.ensemble_policy,
+            cfg.imbalance_threshold.unwrap_or(DEFAULT_IMBALANCE_THRESHOLD),
+            cfg.ensemble_model_weight.unwrap_or(DEFAULT_MODEL_WEIGHT),
+        );
+        Ok(MarketSlot {
+            name: market.name.clone(),
+            config: market.clone(),
+            strategy,
+            shared_model: market.model_path.is_none(),
+            position: PositionState::default(),
+            pnl: 0.0,
+            fees_paid: 0.0,
+            features: RollingFeatures::new(&spec),
+            pending: VecDeque::new(),
+            ticks: 0,
+            last_price: None,
+            last_order_at: None,
+            limits: VecDeque::new(),
+            inflight_buys: 0.0,
+            inflight_sells: 0.0,
+            escalation: EscalationState::default(),
+            wide_spread_skips: 0,
         })
     }
 
+    /// Trade on the feed until it ends. The feed is drained by its own task
+    /// into a bounded queue, so slow order handling never stalls it.
+    /// With `data_source = "laserstream"` the Helius feed comes first and
+    /// the gRPC feed takes over once it gives up; a stale feed always
+    /// reconnects to the gRPC one.
     pub async fn run(&mut self) -> Result<()> {
-        let mut stream: Pin<Box<dyn futures_util::Stream<Item = TradeMsg> + Send>> = self.stream.connect().await?;
-        while let Some(trade) = stream.next().await {
-            self.handle_trade(trade).await?;
+        let stream = match self.cfg.data_source {
+            DataSource::Laserstream => {
+                let primary = LaserStream::from_config(&self.cfg).connect(&self.cfg.markets).await?;
+                let grpc = self.stream.clone();
+                data::with_fallback(primary, Box::pin(async move { grpc.connect().await }))
+            }
+            DataSource::Yellowstone | DataSource::RpcWs => self.stream.connect().await?,
+        };
+        self.trade_on(Some(self.stream.clone()), stream).await
+    }
+
+    /// Trade on `stream` instead of the configured feed, e.g. the synthetic
+    /// one of `--sim`. Every trade is handled in order, however slowly, and
+    /// the configured feed is never connected.
+    pub async fn run_on(&mut self, stream: TradeStream) -> Result<()> {
+        self.trade_on(None, stream).await
+    }
+
+    async fn trade_on(&mut self, source: Option<GrpcStream>, stream: TradeStream) -> Result<()> {
+        let (tx, mut ticks) = mpsc::channel(self.ingest_queue_size);
+        let mut ingest = tokio::spawn(ingest(
+            source,
+            stream,
+            self.feed_stale,
+            self.metrics.clone(),
+            tx,
+        ));
+        self.publish_status().await;
+        let result = loop {
+            tokio::select! {
+                tick = ticks.recv() => match tick {
+                    Some(trade) => {
+                        if let Err(e) = self.handle_trade(trade).await {
+                            break Err(e);
+                        }
+                        self.publish_status().await;
+                    }
+                    // The feed ended: stop with its error, if any
+                    None => break match (&mut ingest).await {
+                        Ok(res) => res,
+                        Err(e) => Err(e.into()),
+                    },
+                },
+                Some(confirmation) = self.confirm_rx.recv() => {
+                    self.apply_confirmation(confirmation);
+                    self.publish_status().await;
+                }
+            }
+        };
+        ingest.abort();
+        result
+    }
+
+    async fn handle_trade(&mut self, trade: TradeMsg) -> Result<()> {
+        self.metrics.trades_processed.inc();
+        if !trade.is_valid() {
+            self.metrics.ticks_invalid.inc();
+            log::warn!("Rejecting invalid trade on '{}': {:?}", trade.market, trade);
+            return Ok(());
+        }
+        self.apply_reloaded_model();
+        self.last_market_ts = Some(trade.ts);
+        if let Some(event_ts) = trade.event_ts {
+            // Block times have 1s resolution, so young events can look negative
+            let age_ms = (trade.ts - event_ts).max(0);
+            self.metrics.feed_event_age.observe(age_ms as f64 / 1000.0);
+        }
+        // Feeds without a market tag (e.g. LaserStream) trade the first market.
+        let idx = if trade.market.is_empty() {
+            0
+        } else {
+            match self.market_index.get(&trade.market) {
+                Some(&idx) => idx,
+                None => {
+                    log::debug!("Ignoring trade on unconfigured market '{}'", trade.market);
+                    return Ok(());
+                }
+            }
+        };
+        let market = &mut self.markets[idx];
+        let features = market.features.push(&trade);
+        // Never let a NaN reach the dataset or the strategy thresholds
+        if features.iter().any(|f| !f.is_finite()) {
+            self.metrics.ticks_invalid.inc();
+            log::warn!("{}: non-finite features {:?}; skipping trade", market.name, features);
+            return Ok(());
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&trade, &features);
+        }
+        market.ticks += 1;
+        market.last_price = Some(trade.price);
+        // Label the samples of this market whose horizon has now elapsed
+        // by the sign of the return up to this trade.
+        let mut matured = Vec::new();
+        while let Some(sample) = market.pending.front() {
+            let elapsed = match self.label_horizon {
+                LabelHorizon::Ticks(n) => market.ticks - sample.tick >= n,
+                LabelHorizon::Ms(ms) => trade.ts - sample.ts >= ms as i64,
+            };
+            if !elapsed {
+                break;
+            }
+            let Some(sample) = market.pending.pop_front() else {
+                break;
+            };
+            let label = if trade.price > sample.price { 1.0 } else { 0.0 };
+            matured.push((sample.features, label));
+        }
+        market.pending.push_back(PendingSample {
+            features: features.clone(),
+            price: trade.price,
+            ts: trade.ts,
+            tick: market.ticks,
+        });
+        for (features, label) in matured {
+            self.add_sample(features, label).await;
+        }
+
+        // Train model periodically in paper mode
+        if self.paper_mode && self.samples_seen - self.last_trained >= self.retrain_every {
+            // E.g. a quiet market leaving a single class in the training
+            // window; the next retrain may succeed
+            if let Err(e) = self.train_model().await {
+                log::warn!("Retrain failed: {:#}; keeping current model", e);
+            }
+        }
+
+        self.update_circuit_breaker();
+        self.observe_equity();
+        let closed = self.outside_trading_hours();
+        let stopped = self.emergency_stop() || closed;
+        if !stopped {
+            self.work_limits(idx, &trade).await;
+        }
+
+        let market = &mut self.markets[idx];
+        let position = market.position;
+        let action = market.strategy.evaluate(&features, trade.price, trade.imbalance, &position);
+        if let Action::Enter { side, .. } | Action::Exit { side, .. } = action {
+            self.cancel_limits(idx, side.opposite());
+            if stopped {
+                log::debug!("{}: trading halted, ignoring {:?} signal", self.markets[idx].name, side);
+                return Ok(());
+            }
+        }
+        let market = &mut self.markets[idx];
+        match action {
+            Action::Hold => {}
+            Action::Exit { side, reason } => {
+                // Exits close risk, so neither the cooldown nor the breaker
+                // applies; but the closing swap may already be in flight.
+                if market.inflight(side) > 0.0 {
+                    log::debug!(
+                        "{}: {:?} hit with {} {:?} in flight, waiting for it to confirm",
+                        market.name,
+                        reason,
+                        market.inflight(side),
+                        side
+                    );
+                    return Ok(());
+                }
+                log::info!(
+                    "{}: {:?} hit at {} (entry {}): closing {}",
+                    market.name,
+                    reason,
+                    trade.price,
+                    position.entry_price,
+                    position.size
+                );
+                market.last_order_at = Some(Instant::now());
+                self.submit_order(idx, side, position.size.abs(), &trade, None).await;
+            }
+            Action::Enter { side, confidence } => {
+                if self.spread_too_wide(idx, &trade) {
+                    return Ok(());
+                }
+                let market = &mut self.markets[idx];
+                self.metrics.signals_generated.inc();
+                // Keep ingesting data while halted, but block new entries.
+                if self.halted {
+                    return Ok(());
+                }
+                if let Some(last) = market.last_order_at {
+                    if last.elapsed() < self.min_order_interval {
+                        log::debug!(
+                            "{}: cooldown, suppressing {:?} signal, {:?} since last order",
+                            market.name,
+                            side,
+                            last.elapsed()
+                        );
+                        return Ok(());
+                    }
+                }
+                let Some(amount) = self.entry_amount(idx, side, confidence) else {
+                    return Ok(());
+                };
+                let Some(amount) = self.notional_allows(idx, side, amount, trade.price) else {
+                    return Ok(());
+                };
+                self.markets[idx].last_order_at = Some(Instant::now());
+                match self.order_type {
+                    OrderType::Market => {
+                        self.submit_order(idx, side, amount, &trade, None).await;
+                    }
+                    OrderType::Limit => self.place_limit(idx, side, amount, trade.price),
+                }
+            }
         }
         Ok(())
     }
 
-    async fn handle_trade(&mut self, trade: TradeMsg) -> Result<()> {
-        let features = vec![trade.price, trade.size, trade.spread];
+    /// Place an order in the current mode. A failed or unconfirmed order must
+    /// not stop the trading loop, so errors are only logged. With a `limit`,
+    /// returns false when the price has not reached it and nothing was done.
+    async fn submit_order(
+        &mut self,
+        idx: usize,
+        side: OrderSide,
+        amount: f64,
+        trade: &TradeMsg,
+        limit: Option<f64>,
+    ) -> bool {
+        if !self.book_deep_enough(idx, side, trade) {
+            return false;
+        }
+        if self.paper_mode {
+            if limit.is_some_and(|limit| !reaches_limit(side, trade.price, limit)) {
+                return false;
+            }
+            self.paper_order(idx, side, amount, trade);
+            return true;
+        }
+        match self.execute_order(idx, side, amount, trade, limit).await {
+            Ok(sent) => sent || limit.is_none(),
+            Err(e) => {
+                log_order_error(&self.markets[idx].name, side, &e);
+                true
+            }
+        }
+    }
 
-        // Build dataset for ML when previous trade exists
-        if let (Some(prev_feat), Some(prev_price)) = (self.last_features.clone(), self.last_price) {
-            let label = if trade.price > prev_price { 1.0 } else { 0.0 };
-            self.dataset.lock().await.push((prev_feat, label));
+    /// Whether the spread of `trade` exceeds `max_spread_bps`, counting the
+    /// skip and summarizing the count every `WIDE_SPREAD_LOG_EVERY` skips.
+    fn spread_too_wide(&mut self, idx: usize, trade: &TradeMsg) -> bool {
+        let (Some(max), Some(spread)) = (self.max_spread_bps, trade.spread) else {
+            return false;
+        };
+        let spread_bps = spread / trade.price * 10_000.0;
+        if spread_bps <= max {
+            return false;
+        }
+        self.metrics.wide_spread_skips.inc();
+        let market = &mut self.markets[idx];
+        market.wide_spread_skips += 1;
+        log::debug!("{}: spread {:.1} bps above {}, skipping entry", market.name, spread_bps, max);
+        if market.wide_spread_skips % WIDE_SPREAD_LOG_EVERY == 0 {
+            log::info!(
+                "{}: {} entry signals skipped for spreads above max_spread_bps {} so far",
+                market.name,
+                market.wide_spread_skips,
+                max
+            );
         }
+        true
+    }
 
-        self.last_features = Some(features.clone());
-        self.last_price = Some(trade.price);
+    /// Whether the book side `side` trades against holds at least
+    /// `min_book_depth`, logging the skip otherwise.
+    fn book_deep_enough(&self, idx: usize, side: OrderSide, trade: &TradeMsg) -> bool {
+        let Some(min) = self.min_book_depth else {
+            return true;
+        };
+        let depth = match side {
+            OrderSide::Buy => trade.ask_volume,
+            OrderSide::Sell => trade.bid_volume,
+        };
+        if depth >= min {
+            return true;
+        }
+        log::info!(
+            "{}: skipping {:?}, book depth {} below min_book_depth {}",
+            self.markets[idx].name,
+            side,
+            depth,
+            min
+        );
+        false
+    }
 
-        // Train model periodically in paper mode
-        if self.paper_mode && self.dataset.lock().await.len() - self.last_trained >= 500 {
-            self.train_model().await?;
+    /// Queue a limit entry `limit_offset_bps` better than the signal `price`.
+    fn place_limit(&mut self, idx: usize, side: OrderSide, amount: f64, price: f64) {
+        let offset = self.limit_offset_bps as f64 / 10_000.0;
+        let limit_price = match side {
+            OrderSide::Buy => price * (1.0 - offset),
+            OrderSide::Sell => price * (1.0 + offset),
+        };
+        let expires_at = self.limit_expiry.map(|expiry| Instant::now() + expiry);
+        let market = &mut self.markets[idx];
+        if market.limits.len() >= MAX_PENDING_LIMITS {
+            if let Some(dropped) = market.limits.pop_front() {
+                log::info!(
+                    "{}: limit queue full, dropping {:?} {} at {:.6}",
+                    market.name,
+                    dropped.side,
+                    dropped.amount,
+                    dropped.limit_price
+                );
+            }
         }
+        log::info!("{}: {:?} limit order for {} at {:.6} queued", market.name, side, amount, limit_price);
+        market.limits.push_back(LimitOrder { side, amount, limit_price, expires_at });
+    }
 
-        if let Some(side) = self.strategy.generate_signal(&features) {
-            if !self.paper_mode {
-                self.execute_order(side, trade.price).await?;
+    /// Drop the pending limit orders of `market` on `side`.
+    fn cancel_limits(&mut self, idx: usize, side: OrderSide) {
+        let market = &mut self.markets[idx];
+        let before = market.limits.len();
+        market.limits.retain(|order| order.side != side);
+        let cancelled = before - market.limits.len();
+        if cancelled > 0 {
+            log::info!("{}: opposing signal cancelled {} {:?} limit orders", market.name, cancelled, side);
+        }
+    }
+
+    /// Expire stale limit orders of a market and re-quote the others,
+    /// swapping those whose quote now reaches their limit.
+    async fn work_limits(&mut self, idx: usize, trade: &TradeMsg) {
+        let now = Instant::now();
+        let market = &mut self.markets[idx];
+        let before = market.limits.len();
+        market.limits.retain(|order| !order.expires_at.is_some_and(|at| at <= now));
+        let expired = before - market.limits.len();
+        if expired > 0 {
+            log::info!("{}: {} limit orders expired", market.name, expired);
+        }
+        if self.halted {
+            return;
+        }
+        let mut i = 0;
+        while let Some(&order) = self.markets[idx].limits.get(i) {
+            let done = if self.position_allows(idx, order.side, order.amount) {
+                self.submit_order(idx, order.side, order.amount, trade, Some(order.limit_price))
+                    .await
+            } else {
+                // The position moved since the order was queued
+                true
+            };
+            if done {
+                self.markets[idx].limits.remove(i);
             } else {
-                log::info!("[PAPER] Signal {:?} at price {}", side, trade.price);
+                i += 1;
             }
         }
-        Ok(())
+    }
+
+    /// Swap in a model loaded through `/reload-model`, if any.
+    fn apply_reloaded_model(&mut self) {
+        let Some(model) = self.pending_model.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            return;
+        };
+        for market in self.markets.iter_mut().filter(|m| m.shared_model) {
+            market.strategy.set_model(model.clone());
+        }
+        log::info!("Reloaded model of {} features is now live", model.n_features());
+    }
+
+    /// Persist a labelled sample and add it to the training window.
+    async fn add_sample(&mut self, features: Vec<f64>, label: f64) {
+        if let Some(file) = &self.dataset_file {
+            if let Err(e) = file.append(&features, label) {
+                log::error!("Failed to persist training sample: {:#}", e);
+                self.unpersisted.push((features.clone(), label));
+            }
+        }
+        let mut dataset = self.dataset.lock().await;
+        dataset.push_back((features, label));
+        if let Some(max) = self.max_dataset_samples {
+            while dataset.len() > max {
+                dataset.pop_front();
+            }
+        }
+        drop(dataset);
+        self.samples_seen += 1;
     }
 
     async fn train_model(&mut self) -> Result<()> {
         let data = self.dataset.lock().await.clone();
-        if data.len() < 10 {
+        if data.len() < self.min_samples_to_train {
             return Ok(());
         }
+        // Train on the current feature width; older samples may be narrower.
+        let width = data.back().map_or(0, |(f, _)| f.len());
+        let data: Vec<Sample> = data.into_iter().filter(|(f, _)| f.len() == width).collect();
         let n = data.len();
         let x: Vec<f64> = data.iter().flat_map(|(f, _)| f.clone()).collect();
-        let x = Array2::from_shape_vec((n, 3), x)?;
+        let x = Array2::from_shape_vec((n, width), x)?;
         let y_vec: Vec<i32> = data.iter().map(|(_, lbl)| if *lbl > 0.5 { 1 } else { 0 }).collect();
-        let model = crate::model::MlModel::train(x, y_vec)?;
+
+        // Samples are in arrival order: hold out the newest ones so the model
+        // is validated on data from after its training window.
+        let n_val = (n as f64 * self.validation_fraction).round() as usize;
+        let n_train = n - n_val;
+        let opts = self.cfg.train_options();
+        self.last_trained = self.samples_seen;
+        if n_train < self.min_samples_to_train {
+            return Ok(());
+        }
+        let weights = self.sample_half_life.map(|half_life| decay_weights(n_train, half_life));
+        let (model, report) = if n_val > 0 {
+            let x_train = x.slice(s![..n_train, ..]).to_owned();
+            let x_val = x.slice(s![n_train.., ..]).to_owned();
+            let eval = Some((&x_val, &y_vec[n_train..]));
+            model::train(x_train, y_vec[..n_train].to_vec(), weights.as_deref(), eval, &opts)?
+        } else {
+            model::train(x, y_vec, weights.as_deref(), None, &opts)?
+        };
+        let n_features = model.n_features();
+
+        if n_val > 0 && report.accuracy <= self.min_validation_accuracy {
+            log::warn!(
+                "Retrain rejected: validation accuracy {:.3} <= {:.3} ({}); keeping current model",
+                report.accuracy,
+                self.min_validation_accuracy,
+                report
+            );
+            return Ok(());
+        }
         model.save(&self.cfg.model_path)?;
 
-        // Update strategy with new model
-        self.strategy = Strategy::new(model, 0.55);
-        log::info!("Model retrained with {} samples; saved to {}.", n, self.cfg.model_path);
-        self.last_trained = n;
+        // Update every strategy running the shared model
+        for market in self.markets.iter_mut().filter(|m| m.shared_model) {
+            market.strategy.set_model(model.clone());
+        }
+        log::info!(
+            "Model retrained on {} samples of {} features; saved to {}.",
+            n_train,
+            n_features,
+            self.cfg.model_path
+        );
+        if n_val > 0 {
+            log::info!("Validation set: {}", report);
+        } else {
+            log::info!("Training set: {}", report);
+        }
+        self.metrics.model_retrains.inc();
         Ok(())
     }
 
-    async fn execute_order(&mut self, side: OrderSide, price: f64) -> Result<()> {
-        let symbol = &self.cfg.symbols[0];
+    /// Roll the daily loss window over at UTC midnight and trip the circuit
+    /// breaker once today's loss exceeds `max_daily_loss`. Today's loss is
+    /// the drop in realized PnL, net of fees, summed over all markets; open
+    /// positions neither count nor are closed by the breaker.
+    fn update_circuit_breaker(&mut self) {
+        let realized = self.total_realized_pnl();
+        let today = Utc::now().date_naive();
+        if today != self.trading_day {
+            if self.halted {
+                log::info!("New UTC day {}: circuit breaker reset, trading resumed", today);
+            }
+            self.trading_day = today;
+            self.day_start_realized = realized;
+            self.halted = false;
+            self.trades_today = 0;
+        }
+
+        let Some(limit) = self.max_daily_loss else {
+            return;
+        };
+        let day_loss = self.day_start_realized - realized;
+        if !self.halted && day_loss > limit {
+            self.halted = true;
+            log::warn!(
+                "!!! CIRCUIT BREAKER TRIPPED: daily loss {} exceeds max_daily_loss {}; new orders blocked until next UTC day !!!",
+                day_loss,
+                limit
+            );
+        }
+    }
+
+    /// Whether `/halt` or the kill switch file stops all orders. The file is
+    /// checked at most once per `KILL_SWITCH_POLL`.
+    fn emergency_stop(&mut self) -> bool {
+        if let Some(path) = &self.kill_switch_path {
+            if !self.kill_switch_checked.is_some_and(|at| at.elapsed() < KILL_SWITCH_POLL) {
+                self.kill_switch_checked = Some(Instant::now());
+                let present = Path::new(path).exists();
+                if present != self.kill_switch {
+                    if present {
+                        log::warn!("!!! KILL SWITCH {} present: all orders stopped; open positions are kept !!!", path);
+                    } else {
+                        log::warn!("Kill switch {} removed: trading resumed", path);
+                    }
+                    self.kill_switch = present;
+                }
+            }
+        }
+        self.kill_switch || self.manual_halt.load(Ordering::SeqCst)
+    }
+
+    /// Whether `trading_hours` currently forbids orders, logging each pause
+    /// and resume.
+    fn outside_trading_hours(&mut self) -> bool {
+        let open = schedule::is_open(&self.trading_hours, Utc::now());
+        if open != self.schedule_open {
+            if open {
+                log::info!("Trading hours started: order execution resumed");
+            } else {
+                log::info!("Outside trading hours: order execution paused, still collecting data");
+            }
+            self.schedule_open = open;
+        }
+        !open
+    }
+
+    /// Refresh the snapshot served on `/status`.
+    async fn publish_status(&self) {
+        let snapshot = Status {
+            mode: if self.paper_mode { "paper" } else { "live" },
+            pnl: self.total_pnl(),
+            realized_pnl: self.total_realized_pnl(),
+            unrealized_pnl: self.markets.iter().map(MarketSlot::unrealized_pnl).sum(),
+            sharpe: self.performance.sharpe(),
+            sortino: self.performance.sortino(),
+            max_drawdown: self.performance.max_drawdown(),
+            positions: self
+                .markets
+                .iter()
+                .map(|m| (m.name.clone(), m.position.size))
+                .collect(),
+            last_trade_ts: self.last_fill_ts,
+            last_market_ts: self.last_market_ts,
+            trades_today: self.trades_today,
+            model_samples: self.dataset.lock().await.len(),
+            circuit_breaker_tripped: self.halted,
+            halted: self.kill_switch || self.manual_halt.load(Ordering::SeqCst),
+        };
+        *self.status.write().unwrap_or_else(|e| e.into_inner()) = snapshot;
+    }
+
+    /// Net base-asset holdings of `market` accumulated from confirmed fills.
+    pub fn current_position(&self, market: &str) -> f64 {
+        self.market_index
+            .get(market)
+            .map_or(0.0, |&idx| self.markets[idx].position.size)
+    }
+
+    /// Cash PnL summed over all markets, net of fees.
+    fn total_pnl(&self) -> f64 {
+        self.markets.iter().map(|m| m.pnl).sum()
+    }
+
+    fn total_realized_pnl(&self) -> f64 {
+        self.markets.iter().map(MarketSlot::realized_pnl).sum()
+    }
+
+    fn total_equity(&self) -> f64 {
+        self.markets.iter().map(MarketSlot::equity).sum()
+    }
+
+    /// Size an entry from the signal confidence, trimming buys to the room
+    /// left under `max_position`. `None` when there is no room at all.
+    fn entry_amount(&self, idx: usize, side: OrderSide, confidence: f64) -> Option<f64> {
+        let market = &self.markets[idx];
+        let amount = market
+            .strategy
+            .order_size(confidence, self.trade_amount, self.max_trade_amount);
+        if side == OrderSide::Sell {
+            return Some(amount);
+        }
+        let room = self.max_position - market.position.size - market.inflight_buys;
+        if room <= 0.0 {
+            log::warn!("Refusing buy: position already at max_position {}", self.max_position);
+            return None;
+        }
+        Some(amount.min(room))
+    }
+
+    /// Whether a buy of `amount` stays within `max_position` on that market,
+    /// counting buys still in flight. Pending sells free no room until they
+    /// confirm.
+    fn position_allows(&self, idx: usize, side: OrderSide, amount: f64) -> bool {
+        if side == OrderSide::Buy {
+            let position = self.markets[idx].position.size + self.markets[idx].inflight_buys;
+            if position + amount > self.max_position {
+                log::warn!(
+                    "{}: refusing buy: position {} + {} would exceed max_position {}",
+                    self.markets[idx].name,
+                    position,
+                    amount,
+                    self.max_position
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Last price of SOL in the quote mint of market `idx`, from a SOL market
+    /// on the same quote mint. Only needed to value network fees on pairs
+    /// without SOL.
+    fn sol_price(&self, idx: usize) -> Option<f64> {
+        let quote_mint = &self.markets[idx].config.quote_mint;
+        self.markets
+            .iter()
+            .find(|m| m.config.base_mint == NATIVE_MINT && m.config.quote_mint == *quote_mint)
+            .and_then(|m| m.last_price)
+    }
+
+    /// `amount` if the notional of an entry at `price` is within
+    /// `max_notional_per_order`, else the amount at the cap when
+    /// `clamp_to_max_notional` is set and `None` when it is not. Exits are
+    /// never capped: a position built from several entries must still close
+    /// in one order.
+    fn notional_allows(&self, idx: usize, side: OrderSide, amount: f64, price: f64) -> Option<f64> {
+        let Some(cap) = self.max_notional_per_order else {
+            return Some(amount);
+        };
+        let notional = amount * price;
+        if notional <= cap {
+            return Some(amount);
+        }
+        let name = self.markets[idx].name.as_str();
+        if !self.clamp_to_max_notional {
+            log::warn!(
+                event = "order_rejected",
+                market = name,
+                side:? = side,
+                size = amount,
+                price = price;
+                "{}: refusing {:?} {} at {}: notional {:.2} exceeds max_notional_per_order {}",
+                name,
+                side,
+                amount,
+                price,
+                notional,
+                cap
+            );
+            return None;
+        }
+        let clamped = cap / price;
+        log::warn!(
+            event = "order_clamped",
+            market = name,
+            side:? = side,
+            size = clamped,
+            price = price;
+            "{}: clamping {:?} {} at {} to {} (notional {:.2} over max_notional_per_order {})",
+            name,
+            side,
+            amount,
+            price,
+            clamped,
+            notional,
+            cap
+        );
+        Some(clamped)
+    }
+
+    /// Book a fill of `amount` at `price` less `fee` on market `idx` and
+    /// publish the new PnL and position. Returns the market's new (net) PnL.
+    fn record_fill(&mut self, idx: usize, side: OrderSide, amount: f64, price: f64, fee: f64) -> f64 {
+        let market = &mut self.markets[idx];
+        market.pnl += if side == OrderSide::Buy {
+            -amount * price
+        } else {
+            amount * price
+        };
+        market.pnl -= fee;
+        market.fees_paid += fee;
+        let before = market.position;
+        // Return of the closed part on its entry notional, before fees
+        let closed_return = market.position.apply(side, amount, price).and_then(|realized| {
+            let notional = amount.min(before.size.abs()) * before.entry_price;
+            (notional > 0.0).then(|| realized / notional)
+        });
+        log::info!(
+            market = market.name.as_str(),
+            position = market.position.size,
+            pnl = market.pnl,
+            fees = market.fees_paid;
+            "{}: position {}, PnL gross {} / net {} (fees {})",
+            market.name,
+            market.position.size,
+            market.pnl + market.fees_paid,
+            market.pnl,
+            market.fees_paid
+        );
+        let (name, pnl, size) = (market.name.clone(), market.pnl, market.position.size);
+        self.trades_today += 1;
+        self.last_fill_ts = Some(Utc::now().timestamp_millis());
+        self.metrics.pnl.set(self.total_pnl());
+        self.metrics.realized_pnl.set(self.total_realized_pnl());
+        self.metrics.position.with_label_values(&[&name]).set(size);
+        if let Some(ret) = closed_return {
+            self.performance.record_return(ret);
+            self.metrics.sharpe.set(self.performance.sharpe());
+            self.metrics.sortino.set(self.performance.sortino());
+        }
+        self.observe_equity();
+        self.save_state();
+        pnl
+    }
+
+    /// Update the drawdown of the marked PnL and the unrealized PnL of the
+    /// open positions.
+    fn observe_equity(&mut self) {
+        self.performance.observe_equity(self.total_equity());
+        self.metrics
+            .unrealized_pnl
+            .set(self.markets.iter().map(MarketSlot::unrealized_pnl).sum());
+        self.metrics.max_drawdown.set(self.performance.max_drawdown());
+    }
+
+    /// Simulate an order in paper mode, costed the way a live fill is: the
+    /// network fee of the swap and the impact of crossing half the spread
+    /// (at most `slippage_bps`), plus `paper_fee`.
+    fn paper_order(&mut self, idx: usize, side: OrderSide, amount: f64, trade: &TradeMsg) {
+        if !self.position_allows(idx, side, amount) {
+            return;
+        }
+        let price = trade.price;
+        let lamports = fill::BASE_FEE_LAMPORTS + self.priority_fee_for(idx).unwrap_or(0);
+        let sol = sol_rate(&self.markets[idx].config, price, self.sol_price(idx));
+        let costs = PaperCosts {
+            impact: PaperCosts::impact(price, trade.spread, self.slippage_for(idx)),
+            network_fee: sol.map_or(0.0, |rate| lamports_to_sol(lamports) * rate),
+            extra_fee: self.paper_fee,
+        };
+        let sim = fill::simulate_fill(amount, price, &costs);
+        log::info!(
+            event = "paper_fill",
+            market = self.markets[idx].name.as_str(),
+            side:? = side,
+            size = amount,
+            price = sim.price,
+            fee = sim.total_fee();
+            "[PAPER] {}: {:?} {} at {}, fee {:.6} (network {:.6}, route {:.6}, extra {:.6})",
+            self.markets[idx].name,
+            side,
+            amount,
+            sim.price,
+            sim.total_fee(),
+            sim.network_fee,
+            sim.route_fee,
+            sim.extra_fee
+        );
+        let pnl = self.record_fill(idx, side, amount, sim.price, sim.total_fee());
+        self.log_trade(idx, side, amount, sim.price, "", "paper", pnl, None);
+    }
+
+    /// Quote and send an order for `amount` base units in response to
+    /// `trade`, then confirm it in a background task whose result comes back
+    /// through `confirm_rx`. Returns false when nothing was sent: the order
+    /// was refused by the position limit or the in-flight limit, or the
+    /// quote strayed too far from the trade price, did not reach `limit` or
+    /// went stale before the swap was sent.
+    async fn execute_order(
+        &mut self,
+        idx: usize,
+        side: OrderSide,
+        amount: f64,
+        trade: &TradeMsg,
+        limit: Option<f64>,
+    ) -> Result<bool> {
+        let price = trade.price;
+        if !self.position_allows(idx, side, amount) {
+            return Ok(false);
+        }
+        let Ok(permit) = self.order_slots.clone().try_acquire_owned() else {
+            log::warn!(
+                "{}: {} orders already in flight, skipping {:?} {}",
+                self.markets[idx].name,
+                self.max_inflight_orders,
+                side,
+                amount
+            );
+            return Ok(false);
+        };
+
+        let symbol = &self.markets[idx].name;
+        // Buys fix the base amount received, sells the base amount spent.
+        let mode = if side == OrderSide::Buy {
+            SwapMode::ExactOut
+        } else {
+            SwapMode::ExactIn
+        };
+        let request = QuoteRequest {
+            market: self.markets[idx].config.clone(),
+            amount,
+            sell: side == OrderSide::Sell,
+            mode,
+            slippage_bps: self.slippage_for(idx),
+            priority_fee_lamports: self.priority_fee_for(idx),
+        };
         let quote = self
             .swap_client
-            .quote(symbol, self.trade_amount, Some(side == OrderSide::Sell))
+            .quote(&request.market, amount, Some(request.sell), mode, request.slippage_bps)
             .await?;
+        log::debug!(
+            "Quote {:?}: in {} out {} threshold {}",
+            quote.swap_mode,
+            quote.in_amount,
+            quote.out_amount,
+            quote.other_amount_threshold
+        );
+        let Some(quoted) = quote.implied_price(side == OrderSide::Sell) else {
+            return Err(anyhow!("empty quote for {:?} {} {}", side, amount, symbol));
+        };
+        let impact_pct = quote.price_impact_pct * 100.0;
+        if impact_pct > self.max_price_impact_pct {
+            log::warn!(
+                "{}: skipping {:?} {}, quote price impact {:.3}% exceeds {}%",
+                symbol,
+                side,
+                amount,
+                impact_pct,
+                self.max_price_impact_pct
+            );
+            return Ok(false);
+        }
+        if let Some(limit) = limit {
+            if !reaches_limit(side, quoted, limit) {
+                log::debug!("{}: {:?} quote {:.6} short of limit {:.6}", symbol, side, quoted, limit);
+                return Ok(false);
+            }
+        }
+        // Positive when the quote is worse than the signal price for our side
+        let adverse_bps = match side {
+            OrderSide::Buy => (quoted - price) / price * 10_000.0,
+            OrderSide::Sell => (price - quoted) / price * 10_000.0,
+        };
+        if adverse_bps > self.max_quote_deviation_bps as f64 {
+            log::warn!(
+                "{}: rejecting {:?} quote at {:.6}, {:.1} bps worse than signal price {} (max {})",
+                symbol,
+                side,
+                quoted,
+                adverse_bps,
+                price,
+                self.max_quote_deviation_bps
+            );
+            return Ok(false);
+        }
 
-        let sig = self
-            .swap_client
-            .swap(&self.wallet, &quote)
-            .await?;
+        let Some(wallet) = self.next_signer(idx, side, &quote).await? else {
+            return Ok(false);
+        };
 
-        self.wait_for_confirmation(&sig).await?;
+        let submission = match self.swap_client.swap(&wallet, &quote, request.priority_fee_lamports).await {
+            Ok(submission) => submission,
+            Err(e) if matches!(e.downcast_ref::<SwapError>(), Some(SwapError::StaleQuote { .. })) => {
+                // Not sent: a pending limit re-quotes on the next trade
+                log::warn!("{}: dropping {:?} {}: {:#}", self.markets[idx].name, side, amount, e);
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
+        let sig = submission.signature;
+        self.metrics.orders_submitted.inc();
+        let latency_ms = (Utc::now().timestamp_millis() - trade.ts).max(0);
+        self.metrics.order_latency.observe(latency_ms as f64 / 1000.0);
+        log::info!(
+            event = "order_submitted",
+            market = self.markets[idx].name.as_str(),
+            side:? = side,
+            size = amount,
+            price,
+            signature:% = sig,
+            wallet:% = wallet.pubkey(),
+            latency_ms;
+            "{}: order {} submitted {} ms after its trigger",
+            self.markets[idx].name,
+            sig,
+            latency_ms
+        );
+        if let Some(units) = submission.units_consumed {
+            log::info!("Swap {} simulated with {} compute units", sig, units);
+        }
+
+        // Only account for the fill once the transaction is confirmed; until
+        // then its amount counts against `max_position`.
+        *self.markets[idx].inflight_mut(side) += amount;
+        let swap_client = self.swap_client.clone();
+        let owner = wallet.pubkey();
+        let sol_price = self.sol_price(idx);
+        let confirm_limit = Duration::from_secs(self.confirm_secs);
+        let tx = self.confirm_tx.clone();
+        tokio::spawn(async move {
+            // A resubmission after blockhash expiry lands under a new
+            // signature, built from a fresh quote
+            let (signature, result) = match swap_client.confirm(&wallet, submission, quote, &request, confirm_limit).await {
+                Ok((landed, quote)) => {
+                    let order = PlacedOrder { side, amount, price, sol_price, signature: landed };
+                    (landed, Ok(fill_result(&swap_client, &owner, &request.market, order, &quote).await))
+                }
+                Err(e) => (sig, Err(e)),
+            };
+            let _ = tx.send(Confirmation {
+                idx,
+                side,
+                amount,
+                price,
+                signature,
+                result,
+            });
+            drop(permit);
+        });
+        Ok(true)
+    }
+
+    /// Account for an order confirmed (or not) in the background.
+    fn apply_confirmation(&mut self, confirmation: Confirmation) {
+        let Confirmation {
+            idx,
+            side,
+            amount,
+            price,
+            signature: sig,
+            result,
+        } = confirmation;
+        *self.markets[idx].inflight_mut(side) -= amount;
+        self.update_escalation(idx, result.is_ok());
+        let fill = match result {
+            Ok(fill) => fill,
+            Err(e) => {
+                // A timed-out transaction may still land; it is logged as such.
+                let status = if is_timeout(&e) { "timeout" } else { "failed" };
+                let pnl = self.markets[idx].pnl;
+                self.log_trade(idx, side, amount, price, &sig.to_string(), status, pnl, None);
+                log_order_error(&self.markets[idx].name, side, &e);
+                return;
+            }
+        };
+        self.metrics.orders_confirmed.inc();
+
+        log::info!("{}: executed {:?} order sig: {}", self.markets[idx].name, side, sig);
+        log::info!(
+            event = "fill",
+            market = self.markets[idx].name.as_str(),
+            side:? = side,
+            size = fill.amount,
+            price = fill.price,
+            fee = fill.total_fee(),
+            signature:% = sig;
+            "Fill {:?} {} at {}: gross {:.6}, net {:.6} (network {:.6}, route {:.6}, platform {:.6})",
+            side,
+            fill.amount,
+            fill.price,
+            fill.gross(),
+            fill.net(),
+            fill.network_fee,
+            fill.route_fee,
+            fill.platform_fee
+        );
+        if let Some(meta) = &fill.onchain {
+            log_onchain(&self.markets[idx].name, &fill, meta);
+        }
+        let pnl = self.record_fill(idx, side, fill.amount, fill.price, fill.total_fee());
+        let sig = sig.to_string();
+        self.log_trade(idx, side, fill.amount, fill.price, &sig, "confirmed", pnl, fill.onchain.as_ref());
+        self.record_onchain(idx, side, fill.amount, fill.price);
+    }
+
+    /// Send a confirmed fill to the on-chain program in the background, if
+    /// `record_fills_onchain` is enabled. A failure is only logged.
+    fn record_onchain(&self, idx: usize, side: OrderSide, amount: f64, price: f64) {
+        let Some(recorder) = self.fill_recorder.clone() else {
+            return;
+        };
+        let market = &self.markets[idx];
+        let fill = OnchainFill {
+            market: market.name.clone(),
+            side,
+            amount: (amount * 10f64.powi(market.config.base_decimals as i32)).round() as u64,
+            price,
+            realized_pnl: market.realized_pnl(),
+        };
+        tokio::spawn(async move {
+            match recorder.record(&fill).await {
+                Ok(sig) => log::info!("{}: fill recorded on-chain in {}", fill.market, sig),
+                Err(e) => log::warn!("{}: failed to record fill on-chain: {:#}", fill.market, e),
+            }
+        });
+    }
 
-        log::info!("Executed {:?} order sig: {}", side, sig);
-        let mut pnl = self.pnl.lock().await;
-        *pnl += if side == OrderSide::Buy {
-            -self.trade_amount * price
+    /// Slippage tolerance of the next swap on market `idx`.
+    fn slippage_for(&self, idx: usize) -> u64 {
+        match &self.escalation {
+            Some(policy) => policy.slippage_bps(self.markets[idx].escalation.steps),
+            None => self.slippage_bps,
+        }
+    }
+
+    /// Priority fee of the next swap on market `idx`.
+    fn priority_fee_for(&self, idx: usize) -> Option<u64> {
+        match &self.escalation {
+            Some(policy) => policy.priority_fee(self.markets[idx].escalation.steps),
+            None => self.cfg.priority_fee_lamports,
+        }
+    }
+
+    /// Count a confirmation towards the escalation of market `idx`, logging
+    /// each step up or down.
+    fn update_escalation(&mut self, idx: usize, confirmed: bool) {
+        let Some(policy) = &self.escalation else {
+            return;
+        };
+        let market = &mut self.markets[idx];
+        let changed = if confirmed {
+            market.escalation.on_success()
         } else {
-            self.trade_amount * price
+            market.escalation.on_failure(policy)
         };
-        Ok(())
+        if !changed {
+            return;
+        }
+        let steps = market.escalation.steps;
+        let (slippage, fee) = (policy.slippage_bps(steps), policy.priority_fee(steps));
+        if confirmed {
+            log::info!(
+                "{}: fill confirmed, easing slippage to {} bps and priority fee to {:?} lamports",
+                market.name,
+                slippage,
+                fee
+            );
+        } else {
+            log::warn!(
+                "{}: swaps keep failing to land, raising slippage to {} bps and priority fee to {:?} lamports",
+                market.name,
+                slippage,
+                fee
+            );
+        }
+    }
+
+    /// Append a row to the CSV trade log, if enabled. Paper fills have an
+    /// empty signature and, like failed orders, no on-chain details.
+    #[allow(clippy::too_many_arguments)]
+    fn log_trade(
+        &mut self,
+        idx: usize,
+        side: OrderSide,
+        amount: f64,
+        price: f64,
+        sig: &str,
+        status: &str,
+        pnl: f64,
+        onchain: Option<&TxMeta>,
+    ) {
+        let Some(writer) = self.trade_log.as_mut() else {
+            return;
+        };
+        let record = TradeRecord {
+            timestamp: Utc::now().to_rfc3339(),
+            side: format!("{:?}", side),
+            price,
+            size: amount,
+            signature: sig.to_string(),
+            status: status.to_string(),
+            pnl,
+            market: self.markets[idx].name.clone(),
+            slot: onchain.map(|meta| meta.slot),
+            block_time: onchain.and_then(|meta| meta.block_time),
+            fee_lamports: onchain.map(|meta| meta.fee),
+            base_delta: onchain.map(|meta| meta.base_delta),
+            quote_delta: onchain.map(|meta| meta.quote_delta),
+        };
+        if let Err(e) = writer.write(&record) {
+            log::error!("Failed to write trade log: {:#}", e);
+        }
     }
 
-    async fn wait_for_confirmation(&self, _sig: &Signature) -> Result<()> {
-    // TODO: Replace this stub with real polling logic using the latest solana
-    // `RpcClient::get_signature_status` API. For now we simply wait for the
-    // configured confirmation timeout and assume success so that the bot
-    // remains functional while we migrate the API calls.
-    tokio::time::sleep(Duration::from_secs(self.confirm_secs)).await;
+    /// Retry samples that failed to append and sync the dataset file.
+    fn flush_dataset(&mut self) {
+        let Some(file) = &self.dataset_file else {
+            return;
+        };
+        let pending = std::mem::take(&mut self.unpersisted);
+        let mut lost = 0;
+        for (features, label) in &pending {
+            if file.append(features, *label).is_err() {
+                lost += 1;
+            }
+        }
+        if lost > 0 {
+            log::error!("{} training samples could not be written to {}", lost, file.path());
+        }
+        if let Err(e) = file.sync() {
+            log::error!("Failed to sync dataset file: {:#}", e);
+        }
+    }
+
+    /// Close each open position with a market swap, one market at a time,
+    /// waiting for every swap to confirm or exhaust its blockhash retries. A
+    /// second ctrl-c stops flattening; a swap already sent may still land.
+    async fn flatten_positions(&mut self) {
+        if self.kill_switch || self.manual_halt.load(Ordering::SeqCst) {
+            log::warn!("Orders are halted; leaving open positions as they are");
+            return;
+        }
+        for idx in 0..self.markets.len() {
+            let market = &self.markets[idx];
+            let size = market.position.size;
+            if size == 0.0 {
+                continue;
+            }
+            let Some(price) = market.last_price else {
+                log::warn!("{}: no price seen; position {} left open", market.name, size);
+                continue;
+            };
+            let side = if size > 0.0 { OrderSide::Sell } else { OrderSide::Buy };
+            log::info!("{}: flattening position {} with a market {:?}", market.name, size, side);
+            let trade = TradeMsg {
+                price,
+                ts: Utc::now().timestamp_millis(),
+                market: market.name.clone(),
+                ..Default::default()
+            };
+            let flatten = async {
+                match self.execute_order(idx, side, size.abs(), &trade, None).await {
+                    Ok(true) => {}
+                    not_sent => return not_sent,
+                }
+                // The confirmation task always reports back, once the swap
+                // landed or its blockhash resubmissions ran out, so no timeout
+                // here can miss a late fill
+                match self.confirm_rx.recv().await {
+                    Some(confirmation) => {
+                        self.apply_confirmation(confirmation);
+                        Ok(true)
+                    }
+                    None => Err(anyhow!("confirmation channel closed")),
+                }
+            };
+            let result = tokio::select! {
+                result = flatten => result,
+                _ = tokio::signal::ctrl_c() => {
+                    log::warn!("Second shutdown signal: abandoning flattening");
+                    return;
+                }
+            };
+            let market = &self.markets[idx];
+            match result {
+                Ok(true) if market.position.size == 0.0 => {
+                    log::info!("{}: flat, PnL net {}", market.name, market.pnl)
+                }
+                Ok(_) => log::warn!("{}: flattening left position {} open", market.name, market.position.size),
+                Err(e) => log::error!("{}: flattening failed, position {} left open: {:#}", market.name, size, e),
+            }
+        }
+    }
+
+    pub async fn shutdown(&mut self) {
+        // Account for the orders still confirming before reporting PnL
+        let inflight = self.max_inflight_orders - self.order_slots.available_permits();
+        if inflight > 0 {
+            log::info!("Waiting for {} in-flight orders to confirm", inflight);
+        }
+        drop(self.order_slots.acquire_many(self.max_inflight_orders as u32).await);
+        while let Ok(confirmation) = self.confirm_rx.try_recv() {
+            self.apply_confirmation(confirmation);
+        }
+        if self.flatten_on_shutdown && !self.paper_mode {
+            self.flatten_positions().await;
+        }
+        for market in &self.markets {
+            log::info!(
+                "{}: final PnL gross {}, net {} (fees {}), position {}",
+                market.name,
+                market.pnl + market.fees_paid,
+                market.pnl,
+                market.fees_paid,
+                market.position.size
+            );
+        }
+        let fees: f64 = self.markets.iter().map(|m| m.fees_paid).sum();
+        let pnl = self.total_pnl();
+        log::info!(
+            "Final PnL: gross {}, net {} (fees {}); Sharpe {:.3}, Sortino {:.3}, max drawdown {:.4} over {} closed trades",
+            pnl + fees,
+            pnl,
+            fees,
+            self.performance.sharpe(),
+            self.performance.sortino(),
+            self.performance.max_drawdown(),
+            self.performance.trades()
+        );
+        let limits: usize = self.markets.iter().map(|m| m.limits.len()).sum();
+        if limits > 0 {
+            log::info!("Cancelling {} pending limit orders", limits);
+        }
+        // Their forward return is unknown; labelling them with the last
+        // price would bias the short-horizon end of the dataset.
+        let unlabelled: usize = self.markets.iter().map(|m| m.pending.len()).sum();
+        if unlabelled > 0 {
+            log::info!(
+                "Discarding {} samples whose label horizon ({:?}) has not elapsed",
+                unlabelled,
+                self.label_horizon
+            );
+        }
+        self.flush_dataset();
+        self.save_state();
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish().await;
+        }
+        // Don't lose the samples collected since the last periodic retrain.
+        if self.paper_mode && self.samples_seen > self.last_trained {
+            if let Err(e) = self.train_model().await {
+                log::error!("Final retrain failed: {:#}", e);
+            }
+        }
+        self.shutdown_token.cancel();
+        for task in self.server_tasks.drain(..) {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Push feed trades into `ticks` until the feed ends or the trading loop
+/// goes away. Trades arriving while `ticks` is full are dropped and counted,
+/// and a stale feed is reconnected to `source`; without one the feed is
+/// replayed losslessly. Fails with the feed's error when it gave up
+/// reconnecting.
+async fn ingest(
+    source: Option<GrpcStream>,
+    mut stream: TradeStream,
+    stale: Duration,
+    metrics: Arc<Metrics>,
+    ticks: mpsc::Sender<TradeMsg>,
+) -> Result<()> {
+    let Some(source) = source else {
+        return replay(stream, ticks).await;
+    };
+    loop {
+        // Watchdog: the gRPC task only reconnects on errors, not on a
+        // stream that silently stops delivering.
+        match tokio::time::timeout(stale, stream.next()).await {
+            Ok(Some(Ok(trade))) => match ticks.try_send(trade) {
+                Ok(()) => {}
+                Err(TrySendError::Full(trade)) => {
+                    metrics.ticks_dropped.inc();
+                    log::debug!("Trading loop behind; dropped {} trade at {}", trade.market, trade.ts);
+                }
+                Err(TrySendError::Closed(_)) => return Ok(()),
+            },
+            Ok(Some(Err(e))) => {
+                log::error!("Market data feed failed: {}", e);
+                return Err(e.into());
+            }
+            Ok(None) => return Ok(()),
+            Err(_) => {
+                log::error!("No market data for {:?}; forcing a feed reconnect", stale);
+                metrics.feed_stale.inc();
+                // Dropping the old stream stops its background task.
+                match source.connect().await {
+                    Ok(fresh) => stream = fresh,
+                    Err(e) => log::error!("Feed reconnect failed, retrying later: {:#}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Forward every trade of a feed that cannot reconnect, waiting for the
+/// trading loop instead of dropping trades, so a seeded run repeats exactly.
+async fn replay(mut stream: TradeStream, ticks: mpsc::Sender<TradeMsg>) -> Result<()> {
+    while let Some(trade) = stream.next().await {
+        let trade = trade.map_err(|e| {
+            log::error!("Market data feed failed: {}", e);
+            e
+        })?;
+        if ticks.send(trade).await.is_err() {
+            break;
+        }
+    }
     Ok(())
 }
 
-    pub async fn shutdown(&mut self) {
-        log::info!("Final PnL: {}", *self.pnl.lock().await);
+/// A swap sent at `price`, as needed to price its fill.
+struct PlacedOrder {
+    side: OrderSide,
+    amount: f64,
+    price: f64,
+    /// SOL price in the market's quote units when the order was sent, for
+    /// pairs without SOL; see `Trader::sol_price`.
+    sol_price: Option<f64>,
+    signature: Signature,
+}
+
+/// Price a confirmed swap and its costs in quote units from its on-chain
+/// record, or from the quote when the transaction could not be read. The
+/// network fee is paid in SOL and converted at the fill price on SOL-based
+/// pairs, at `sol_price` otherwise.
+async fn fill_result(
+    swap_client: &SwapClient,
+    owner: &Pubkey,
+    market: &MarketConfig,
+    order: PlacedOrder,
+    quote: &Quote,
+) -> FillResult {
+    let PlacedOrder { side, amount, price, sol_price, signature } = order;
+    let onchain = match swap_client.transaction_meta(&signature, owner, market).await {
+        Ok(meta) => Some(meta),
+        Err(e) => {
+            log::warn!("Could not read transaction {}: {:#}", signature, e);
+            None
+        }
+    };
+    let quoted_price = quote.implied_price(side == OrderSide::Sell).unwrap_or(price);
+    // What the balances moved by, else what the quote promised
+    let landed = onchain
+        .as_ref()
+        .and_then(|meta| Some((meta.base_delta.abs(), meta.realized_price()?)));
+    let (amount, executed) = landed.unwrap_or((amount, quoted_price));
+    let lamports = onchain.as_ref().map_or(0, |meta| meta.fee);
+    let network_fee = match sol_rate(market, executed, sol_price) {
+        Some(rate) => lamports_to_sol(lamports) * rate,
+        None => {
+            log::warn!(
+                "{}: no SOL price in {} to value the {} lamport network fee of {}; not counted",
+                market.name,
+                market.quote_mint,
+                lamports,
+                signature
+            );
+            0.0
+        }
+    };
+    let route_fee = amount * executed * quote.price_impact_pct;
+    // The platform fee is taken from the output mint: base for buys, quote for sells.
+    let platform_fee = quote.platform_fee as f64 / 10f64.powi(quote.output_decimals as i32);
+    let platform_fee = match side {
+        OrderSide::Buy => platform_fee * executed,
+        OrderSide::Sell => platform_fee,
+    };
+    FillResult {
+        signature,
+        side,
+        amount,
+        price: FillResult::price_before_costs(side, amount, executed, route_fee + platform_fee),
+        quoted_price,
+        network_fee,
+        route_fee,
+        platform_fee,
+        onchain,
+    }
+}
+
+/// Price of SOL in the quote units of `market`: the fill `price` on SOL-based
+/// pairs, 1 when SOL is the quote, else `sol_price` from another market.
+fn sol_rate(market: &MarketConfig, price: f64, sol_price: Option<f64>) -> Option<f64> {
+    if market.base_mint == NATIVE_MINT {
+        Some(price)
+    } else if market.quote_mint == NATIVE_MINT {
+        Some(1.0)
+    } else {
+        sol_price
+    }
+}
+
+/// Log where a fill landed and how its balance changes compare with the
+/// quote, so recorded PnL can be reconciled against the chain.
+fn log_onchain(market: &str, fill: &FillResult, meta: &TxMeta) {
+    let realized = meta.realized_price();
+    // Positive when the chain shows a worse price than the quote for our side
+    let quoted = fill.quoted_price;
+    let deviation_bps = realized.map(|realized| match fill.side {
+        OrderSide::Buy => (realized - quoted) / quoted * 10_000.0,
+        OrderSide::Sell => (quoted - realized) / quoted * 10_000.0,
+    });
+    log::info!(
+        event = "onchain",
+        market,
+        signature:% = fill.signature,
+        slot = meta.slot,
+        block_time:? = meta.block_time,
+        fee_lamports = meta.fee,
+        base_delta = meta.base_delta,
+        quote_delta = meta.quote_delta,
+        deviation_bps:? = deviation_bps;
+        "{}: {} landed in slot {} (block time {:?}), fee {} lamports, base {:+}, quote {:+}, realized price {:?} vs quoted {}",
+        market,
+        fill.signature,
+        meta.slot,
+        meta.block_time,
+        meta.fee,
+        meta.base_delta,
+        meta.quote_delta,
+        realized,
+        quoted
+    );
+    let expected = match fill.side {
+        OrderSide::Buy => fill.amount,
+        OrderSide::Sell => -fill.amount,
+    };
+    if (meta.base_delta - expected).abs() > expected.abs() * 0.01 {
+        log::warn!(
+            "{}: {} moved base by {:+} on-chain, expected {:+}",
+            market,
+            fill.signature,
+            meta.base_delta,
+            expected
+        );
+    }
+}
+
+/// Log a failed or unconfirmed order; timeouts may still land.
+fn log_order_error(market: &str, side: OrderSide, err: &anyhow::Error) {
+    if is_timeout(err) {
+        log::warn!("{}: order {:?} timed out: {:#}", market, side, err);
+    } else if matches!(err.downcast_ref::<SwapError>(), Some(SwapError::Throttled { .. })) {
+        log::warn!("{}: order {:?} skipped: {:#}", market, side, err);
+    } else {
+        log::error!("{}: order {:?} failed: {:#}", market, side, err);
+    }
+}
+
+/// Whether `price` is at or better than `limit` for an order on `side`.
+fn reaches_limit(side: OrderSide, price: f64, limit: f64) -> bool {
+    match side {
+        OrderSide::Buy => price <= limit,
+        OrderSide::Sell => price >= limit,
+    }
+}
+
+fn is_timeout(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<SwapError>(), Some(SwapError::Timeout { .. }))
+}
+
+/// Samples of a recording, labelled as the trading loop labels live trades:
+/// 1 when the price of the same market rose by the time `horizon` elapsed.
+/// Trades whose horizon runs past the end of the recording are left out.
+fn label_recording(rows: Vec<(TradeMsg, Vec<f64>)>, horizon: LabelHorizon) -> Vec<Sample> {
+    let mut pending: HashMap<String, VecDeque<PendingSample>> = HashMap::new();
+    let mut ticks: HashMap<String, u64> = HashMap::new();
+    let mut samples = Vec::new();
+    for (trade, features) in rows {
+        let tick = ticks.entry(trade.market.clone()).or_default();
+        *tick += 1;
+        let queue = pending.entry(trade.market.clone()).or_default();
+        while let Some(sample) = queue.front() {
+            let elapsed = match horizon {
+                LabelHorizon::Ticks(n) => *tick - sample.tick >= n,
+                LabelHorizon::Ms(ms) => trade.ts - sample.ts >= ms as i64,
+            };
+            if !elapsed {
+                break;
+            }
+            let Some(sample) = queue.pop_front() else {
+                break;
+            };
+            let label = if trade.price > sample.price { 1.0 } else { 0.0 };
+            samples.push((sample.features, label));
+        }
+        queue.push_back(PendingSample {
+            features,
+            price: trade.price,
+            ts: trade.ts,
+            tick: *tick,
+        });
+    }
+    samples
+}
+
+/// Training weights of `n` samples in arrival order, halving every
+/// `half_life` samples of age and scaled to average 1.
+fn decay_weights(n: usize, half_life: f64) -> Vec<f64> {
+    let weights: Vec<f64> = (0..n)
+        .map(|i| 0.5f64.powf((n - 1 - i) as f64 / half_life))
+        .collect();
+    let mean = weights.iter().sum::<f64>() / n.max(1) as f64;
+    weights.into_iter().map(|w| w / mean).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Paper trader on the default SOL/USDC market with an untrained model.
+    async fn paper_trader() -> Trader {
+        Trader::new(crate::config::test_config("")).await.unwrap()
+    }
+
+    fn trade(price: f64) -> TradeMsg {
+        TradeMsg {
+            price,
+            size: 1.0,
+            side: "bid".to_string(),
+            ts: Utc::now().timestamp_millis(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn short_half_life_follows_the_recent_regime() {
+        // 300 old samples where up follows a positive feature, then 60 recent
+        // ones where the relation is reversed
+        let n_old = 300;
+        let n = n_old + 60;
+        let value = |i: usize| if i % 2 == 0 { 1.0 } else { -1.0 };
+        let x = Array2::from_shape_fn((n, 1), |(i, _)| value(i) * (1.0 + (i % 7) as f64 / 10.0));
+        let y: Vec<i32> = (0..n).map(|i| ((value(i) > 0.0) == (i < n_old)) as i32).collect();
+        let opts = model::TrainOptions::default();
+
+        let (unweighted, _) = model::train(x.clone(), y.clone(), None, None, &opts).unwrap();
+        assert!(unweighted.predict(&[1.0]) > 0.5);
+
+        let weights = decay_weights(n, 5.0);
+        assert!((weights.iter().sum::<f64>() / n as f64 - 1.0).abs() < 1e-9);
+        assert!(weights[n - 1] > weights[n_old] * 1000.0);
+        let (recent, _) = model::train(x, y, Some(&weights), None, &opts).unwrap();
+        assert!(recent.predict(&[1.0]) < 0.5);
+        assert!(recent.predict(&[-1.0]) > 0.5);
+    }
+
+    #[tokio::test]
+    async fn non_finite_trades_are_rejected() {
+        let mut trader = paper_trader().await;
+        let bad = [
+            trade(f64::NAN),
+            TradeMsg { size: f64::INFINITY, ..trade(100.0) },
+            TradeMsg { spread: Some(f64::NAN), ..trade(100.0) },
+            TradeMsg { imbalance: f64::NEG_INFINITY, ..trade(100.0) },
+        ];
+        for t in bad {
+            assert!(!t.is_valid());
+            trader.handle_trade(t).await.unwrap();
+        }
+        assert_eq!(trader.metrics.ticks_invalid.get(), 4);
+        assert_eq!(trader.markets[0].ticks, 0);
+        assert_eq!(trader.markets[0].last_price, None);
+
+        trader.handle_trade(trade(100.0)).await.unwrap();
+        assert_eq!(trader.markets[0].ticks, 1);
+    }
+
+    #[tokio::test]
+    async fn non_finite_features_skip_the_trade() {
+        let mut trader = paper_trader().await;
+        // A NaN spread that reached the rolling state is imputed into the
+        // next trade without one
+        trader.markets[0].features.push(&TradeMsg { spread: Some(f64::NAN), ..trade(100.0) });
+        let next = trade(101.0);
+        assert!(next.is_valid());
+        trader.handle_trade(next).await.unwrap();
+        assert_eq!(trader.metrics.ticks_invalid.get(), 1);
+        assert_eq!(trader.markets[0].ticks, 0);
+        assert!(trader.dataset.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_retrain_keeps_trading() {
+        let cfg = crate::config::test_config("retrain_every = 20\nmin_samples_to_train = 5");
+        let mut trader = Trader::new(cfg).await.unwrap();
+        // A flat price labels every sample 0, which logistic regression
+        // cannot be fitted on
+        for _ in 0..30 {
+            trader.handle_trade(trade(100.0)).await.unwrap();
+        }
+        assert!(trader.last_trained >= 20);
+        assert_eq!(trader.metrics.model_retrains.get(), 0);
+        assert_eq!(trader.markets[0].ticks, 30);
     }
 }
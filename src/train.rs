@@ -0,0 +1,71 @@
+//! Offline training from a labelled CSV of recorded trades.
+//!
+//! The CSV has the same `TradeMsg` columns as the backtester input plus a
+//! `label` column holding `0` or `1` (next-tick direction).
+
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use std::collections::HashMap;
+
+use crate::data;
+use crate::features::{FeatureSpec, RollingFeatures};
+use crate::model::{self, TrainOptions, TrainReport};
+
+pub struct TrainParams<'a> {
+    pub data_path: &'a str,
+    pub out_path: &'a str,
+    pub options: TrainOptions,
+    /// Rolling features; must match the bot's so the model can be loaded.
+    pub features: FeatureSpec,
+}
+
+#[derive(Debug)]
+pub struct TrainSummary {
+    pub n_features: usize,
+    /// Metrics on the training set.
+    pub report: TrainReport,
+}
+
+pub fn run(params: &TrainParams) -> Result<TrainSummary> {
+    let rows = data::read_trade_csv(params.data_path)?;
+    if rows.is_empty() {
+        return Err(anyhow!("'{}' contains no rows", params.data_path));
+    }
+
+    let mut features = Vec::with_capacity(rows.len());
+    let mut labels = Vec::with_capacity(rows.len());
+    let mut rolling: HashMap<&str, RollingFeatures> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        // Line numbers are 1-based and the header is line 1.
+        let line = i + 2;
+        let label = match row.label.as_deref() {
+            Some("0") => 0,
+            Some("1") => 1,
+            Some(other) => {
+                return Err(anyhow!(
+                    "{}:{}: label must be 0 or 1, got '{}'",
+                    params.data_path,
+                    line,
+                    other
+                ))
+            }
+            None => return Err(anyhow!("'{}' has no 'label' column", params.data_path)),
+        };
+        let state = rolling
+            .entry(row.trade.market.as_str())
+            .or_insert_with(|| RollingFeatures::new(&params.features));
+        features.push(state.push(&row.trade));
+        labels.push(label);
+    }
+
+    let n = features.len();
+    let width = features[0].len();
+    let x = Array2::from_shape_vec((n, width), features.iter().flatten().copied().collect())?;
+    let (model, report) = model::train(x, labels, None, None, &params.options)?;
+    model.save(params.out_path)?;
+
+    Ok(TrainSummary {
+        n_features: model.n_features(),
+        report,
+    })
+}